@@ -0,0 +1,330 @@
+//! Working with downloaded audio using ffmpeg and ffprobe.
+
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::Command;
+use tempfile::NamedTempFile;
+
+/// How quiet audio has to be (in dB), and for how long (in seconds), for
+/// silences() to count it as a pause.
+const SILENCE_NOISE_DB: i32 = -30;
+const SILENCE_SECONDS: f64 = 0.5;
+
+/// What share of its limit split() aims to fill with each piece, leaving
+/// room for cuts moved to a pause and for variable bitrates.
+const SPLIT_FILL: f64 = 0.8;
+
+/// Parse a time given as seconds ("90"), minutes and seconds ("1:30") or
+/// hours, minutes and seconds ("1:01:30") into seconds.
+pub fn parse_time(time: &str) -> Result<f64, String> {
+    let invalid = || format!("\"{}\" isn't a time like 90, 1:30 or 1:01:30", time);
+    let parts: Vec<&str> = time.split(':').collect();
+    if parts.len() > 3 {
+        return Err(invalid());
+    }
+    let mut seconds = 0.0;
+    for part in parts {
+        let value: f64 = part.parse().map_err(|_| invalid())?;
+        if value < 0.0 {
+            return Err(invalid());
+        }
+        seconds = seconds * 60.0 + value;
+    }
+    Ok(seconds)
+}
+
+/// Write audio to a temporary mp3 file, e.g. for ffmpeg to read or to upload
+/// without keeping it in memory. The file is deleted when it's dropped.
+pub fn temp_mp3(audio: &[u8]) -> io::Result<NamedTempFile> {
+    let mut file = NamedTempFile::with_suffix(".mp3")?;
+    file.write_all(audio)?;
+    file.flush()?;
+    Ok(file)
+}
+
+/// Run one of these helpers on tokio's blocking threads, so ffmpeg or
+/// ffprobe working away doesn't hold up everything else on the runtime.
+pub async fn blocking<T, F>(helper: F) -> io::Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> io::Result<T> + Send + 'static,
+{
+    tokio::task::spawn_blocking(helper).await.map_err(io::Error::other)?
+}
+
+/// Run a command, turning a non-zero exit into an error.
+fn run(command: &mut Command, name: &str) -> io::Result<Vec<u8>> {
+    let output = command.output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "{} failed: {}",
+            name,
+            String::from_utf8_lossy(&output.stderr)
+        )));
+    }
+    Ok(output.stdout)
+}
+
+/// How long some audio is, in seconds.
+pub fn duration(audio: &[u8]) -> io::Result<f64> {
+    let input = temp_mp3(audio)?;
+    let stdout = run(
+        Command::new("ffprobe")
+            .args(["-v", "error", "-show_entries", "format=duration", "-of", "csv=p=0"])
+            .arg(input.path()),
+        "ffprobe",
+    )?;
+    String::from_utf8_lossy(&stdout)
+        .trim()
+        .parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// The average bitrate of some audio, in bits per second.
+pub fn bit_rate(audio: &[u8]) -> io::Result<f64> {
+    let input = temp_mp3(audio)?;
+    let stdout = run(
+        Command::new("ffprobe")
+            .args(["-v", "error", "-show_entries", "format=bit_rate", "-of", "csv=p=0"])
+            .arg(input.path()),
+        "ffprobe",
+    )?;
+    String::from_utf8_lossy(&stdout)
+        .trim()
+        .parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// The title in an audio file's tags (e.g. ID3), if it has one.
+pub fn title(path: &Path) -> Option<String> {
+    let stdout = run(
+        Command::new("ffprobe")
+            .args(["-v", "error", "-show_entries", "format_tags=title", "-of", "default=nw=1:nk=1"])
+            .arg(path),
+        "ffprobe",
+    )
+    .ok()?;
+    let title = String::from_utf8_lossy(&stdout).trim().to_string();
+    Some(title).filter(|title| !title.is_empty())
+}
+
+/// Read an audio file as mp3, converting it first if it's in some other
+/// format.
+pub fn read_as_mp3(path: &Path) -> io::Result<Vec<u8>> {
+    let is_mp3 = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("mp3"));
+    if is_mp3 {
+        return std::fs::read(path);
+    }
+    let output = NamedTempFile::with_suffix(".mp3")?;
+    run(
+        Command::new("ffmpeg")
+            .args(["-v", "error", "-y", "-i"])
+            .arg(path)
+            .arg(output.path()),
+        "ffmpeg",
+    )?;
+    std::fs::read(output.path())
+}
+
+/// Convert audio to the 16 kHz mono WAV whisper.cpp reads, in a temporary
+/// file.
+pub fn to_wav(audio: &[u8]) -> io::Result<NamedTempFile> {
+    let input = temp_mp3(audio)?;
+    let output = NamedTempFile::with_suffix(".wav")?;
+    run(
+        Command::new("ffmpeg")
+            .args(["-v", "error", "-y", "-i"])
+            .arg(input.path())
+            .args(["-ar", "16000", "-ac", "1", "-c:a", "pcm_s16le"])
+            .arg(output.path()),
+        "ffmpeg",
+    )?;
+    Ok(output)
+}
+
+/// The samples of a 16-bit PCM WAV (as to_wav makes), scaled to between -1
+/// and 1.
+///
+/// WAVs ffmpeg writes to a pipe can't say how long they are, so the data
+/// runs to the end of the file whatever its header claims.
+pub fn wav_samples(wav: &[u8]) -> io::Result<Vec<f32>> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    if wav.len() < 12 || &wav[0..4] != b"RIFF" || &wav[8..12] != b"WAVE" {
+        return Err(invalid("not a WAV file"));
+    }
+    let mut rest = &wav[12..];
+    while rest.len() >= 8 {
+        let (id, size) = (&rest[0..4], u32::from_le_bytes([rest[4], rest[5], rest[6], rest[7]]) as usize);
+        rest = &rest[8..];
+        if id == b"data" {
+            let data = &rest[..size.min(rest.len())];
+            let samples = data.chunks_exact(2).map(|sample| i16::from_le_bytes([sample[0], sample[1]]) as f32 / 32768.0);
+            return Ok(samples.collect());
+        }
+        // Chunks are padded to an even length.
+        rest = rest.get(size + size % 2..).unwrap_or_default();
+    }
+    Err(invalid("WAV file without any audio data"))
+}
+
+/// Cut audio down to the part between `start` and `end` (in seconds). Either
+/// can be left out to keep the beginning or end.
+///
+/// Returns an error if the range is empty or goes past the end of the audio.
+pub fn clip(audio: &[u8], start: Option<f64>, end: Option<f64>) -> io::Result<Vec<u8>> {
+    let length = duration(audio)?;
+    let start = start.unwrap_or(0.0);
+    let end = end.unwrap_or(length);
+    if start >= end {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("the start ({}s) must be before the end ({}s)", start, end),
+        ));
+    }
+    if end > length {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("the audio is only {:.0}s long, so can't be clipped to {}s", length, end),
+        ));
+    }
+    let input = temp_mp3(audio)?;
+    let output = NamedTempFile::with_suffix(".mp3")?;
+    run(
+        Command::new("ffmpeg")
+            .args(["-v", "error", "-y", "-i"])
+            .arg(input.path())
+            .arg("-ss")
+            .arg(start.to_string())
+            .arg("-to")
+            .arg(end.to_string())
+            .arg(output.path()),
+        "ffmpeg",
+    )?;
+    std::fs::read(output.path())
+}
+
+/// Join several pieces of audio together, one after another.
+pub fn concat(parts: &[Vec<u8>]) -> io::Result<Vec<u8>> {
+    let inputs = parts.iter().map(|part| temp_mp3(part)).collect::<io::Result<Vec<_>>>()?;
+    let mut list = NamedTempFile::with_suffix(".txt")?;
+    for input in &inputs {
+        // ffmpeg's concat list quotes paths with single quotes.
+        let path = input.path().to_string_lossy().replace('\'', "'\\''");
+        writeln!(list, "file '{}'", path)?;
+    }
+    list.flush()?;
+    let output = NamedTempFile::with_suffix(".mp3")?;
+    run(
+        Command::new("ffmpeg")
+            .args(["-v", "error", "-y", "-f", "concat", "-safe", "0", "-i"])
+            .arg(list.path())
+            .args(["-c", "copy"])
+            .arg(output.path()),
+        "ffmpeg",
+    )?;
+    std::fs::read(output.path())
+}
+
+/// Where the pauses in some audio are, as the time (in seconds) halfway
+/// through each.
+pub fn silences(audio: &[u8]) -> io::Result<Vec<f64>> {
+    let input = temp_mp3(audio)?;
+    let filter = format!("silencedetect=noise={}dB:d={}", SILENCE_NOISE_DB, SILENCE_SECONDS);
+    let output = Command::new("ffmpeg")
+        .args(["-hide_banner", "-nostats", "-i"])
+        .arg(input.path())
+        .args(["-af", &filter, "-f", "null", "-"])
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "ffmpeg failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        )));
+    }
+    // The filter reports each pause on stderr, as e.g. "silence_start: 12.3"
+    // and then "silence_end: 13.1 | silence_duration: 0.8".
+    let mut silences = vec![];
+    let mut start = None;
+    for line in String::from_utf8_lossy(&output.stderr).lines() {
+        if let Some(time) = line.split("silence_start: ").nth(1) {
+            start = time.trim().parse::<f64>().ok();
+        } else if let Some(time) = line.split("silence_end: ").nth(1) {
+            let end = time.split('|').next().unwrap_or_default().trim().parse::<f64>();
+            if let (Some(start), Ok(end)) = (start.take(), end) {
+                silences.push((start + end) / 2.0);
+            }
+        }
+    }
+    Ok(silences)
+}
+
+/// Where to cut audio `duration` seconds long into `pieces` pieces of about
+/// the same length. Each cut is moved to the nearest of `silences` (see
+/// silences()) within an eighth of a piece's length, if there is one, so
+/// words aren't cut in half. Pieces are never less than three quarters of
+/// the length they'd otherwise be, so the last one is never just a scrap.
+pub fn cut_points(duration: f64, pieces: usize, silences: &[f64]) -> Vec<f64> {
+    let length = duration / pieces as f64;
+    (1..pieces)
+        .map(|i| {
+            let even = length * i as f64;
+            silences
+                .iter()
+                .copied()
+                .filter(|silence| (silence - even).abs() <= length / 8.0)
+                .min_by(|a, b| (a - even).abs().total_cmp(&(b - even).abs()))
+                .unwrap_or(even)
+        })
+        .collect()
+}
+
+/// The part of the audio file at `input` from `start` to `end` (in seconds,
+/// or to the end of the audio), cut without re-encoding it.
+fn cut(input: &Path, start: f64, end: Option<f64>) -> io::Result<Vec<u8>> {
+    let output = NamedTempFile::with_suffix(".mp3")?;
+    let mut command = Command::new("ffmpeg");
+    command.args(["-v", "error", "-y", "-i"]).arg(input).arg("-ss").arg(start.to_string());
+    if let Some(end) = end {
+        command.arg("-to").arg(end.to_string());
+    }
+    run(command.args(["-c", "copy"]).arg(output.path()), "ffmpeg")?;
+    std::fs::read(output.path())
+}
+
+/// Split audio into pieces of no more than `max_bytes` each, e.g. to get
+/// under an API's upload limit, cutting at pauses where possible (see
+/// cut_points). Returns each piece along with where it starts in the audio,
+/// in seconds.
+///
+/// Audio which is small enough already comes back as it is, without
+/// needing ffmpeg.
+pub fn split(audio: &[u8], max_bytes: usize) -> io::Result<Vec<(f64, Vec<u8>)>> {
+    if audio.len() <= max_bytes {
+        return Ok(vec![(0.0, audio.to_vec())]);
+    }
+    let length = duration(audio)?;
+    let silences = silences(audio)?;
+    let input = temp_mp3(audio)?;
+    let mut pieces = (audio.len() as f64 / (max_bytes as f64 * SPLIT_FILL)).ceil() as usize;
+    // Pieces of the same length can still differ in size (e.g. with a
+    // variable bitrate), so use more of them until they all fit.
+    loop {
+        let cuts = cut_points(length, pieces, &silences);
+        let starts = std::iter::once(0.0).chain(cuts.iter().copied());
+        let ends = cuts.iter().copied().map(Some).chain(std::iter::once(None));
+        let parts = starts
+            .zip(ends)
+            .map(|(start, end)| Ok((start, cut(input.path(), start, end)?)))
+            .collect::<io::Result<Vec<_>>>()?;
+        if parts.iter().all(|(_, part)| part.len() <= max_bytes) {
+            return Ok(parts);
+        }
+        if pieces >= audio.len() / max_bytes * 4 {
+            return Err(io::Error::other(format!(
+                "couldn't split the audio into pieces of at most {} bytes",
+                max_bytes
+            )));
+        }
+        pieces += 1;
+    }
+}
@@ -0,0 +1,93 @@
+//! A cache of transcripts on disk, so the same audio is never paid for
+//! twice.
+//!
+//! Entries are keyed by a SHA-256 hash of the audio and the Whisper model
+//! which transcribed it, so switching models doesn't return stale
+//! transcripts. Each entry is written to a temporary file and renamed into
+//! place, so a reader (even in another lqcli process) never sees half of
+//! one, and transcriptions of the same audio within a process wait for each
+//! other rather than both calling the API.
+
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::future::Future;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tempfile::NamedTempFile;
+
+pub struct TranscriptCache {
+    dir: PathBuf,
+
+    /// One lock per key which is being looked up or filled in.
+    locks: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+impl TranscriptCache {
+    /// A cache kept in `dir`, which is created when the first entry is
+    /// written.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into(), locks: Mutex::new(HashMap::new()) }
+    }
+
+    /// The key for some audio transcribed with a model.
+    pub fn key(audio: &[u8], model: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(model.as_bytes());
+        // Keep the model name from running into the audio.
+        hasher.update([0]);
+        hasher.update(audio);
+        hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.txt", key))
+    }
+
+    /// The cached transcript for a key, if there is one.
+    pub fn get(&self, key: &str) -> Option<String> {
+        std::fs::read_to_string(self.path(key)).ok()
+    }
+
+    /// Store a transcript, replacing any earlier one for the key.
+    pub fn put(&self, key: &str, transcript: &str) -> io::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        // The temporary file has to be in the same directory for the rename
+        // to be atomic.
+        let mut file = NamedTempFile::new_in(&self.dir)?;
+        file.write_all(transcript.as_bytes())?;
+        file.flush()?;
+        file.persist(self.path(key)).map_err(|e| e.error)?;
+        Ok(())
+    }
+
+    fn lock_for(&self, key: &str) -> Arc<tokio::sync::Mutex<()>> {
+        let mut locks = self.locks.lock().unwrap();
+        locks.entry(key.to_string()).or_default().clone()
+    }
+
+    /// The cached transcript for a key, or else the one `transcribe` makes,
+    /// which is then cached. While one caller is transcribing, others
+    /// asking for the same key wait for it to finish. Failures (including
+    /// empty transcripts) aren't cached.
+    pub async fn get_or_transcribe<F, Fut, E>(&self, key: &str, transcribe: F) -> Result<String, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<String, E>>,
+    {
+        let lock = self.lock_for(key);
+        let _guard = lock.lock().await;
+        if let Some(transcript) = self.get(key) {
+            return Ok(transcript);
+        }
+        let transcript = transcribe().await?;
+        if let Err(e) = self.put(key, &transcript) {
+            crate::report::warn(
+                "cache",
+                None,
+                format!("couldn't cache the transcript in {}: {}", self.dir.display(), e),
+            );
+        }
+        Ok(transcript)
+    }
+}
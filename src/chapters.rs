@@ -0,0 +1,66 @@
+//! Chapter markers, as found by yt-dlp, and fitting them into transcripts.
+
+use serde::Deserialize;
+use std::io;
+use tokio::process::Command;
+
+use crate::openai::Segment;
+
+#[derive(Debug, Deserialize)]
+pub struct Chapter {
+    /// Where the chapter starts, in seconds from the start of the audio.
+    pub start_time: f32,
+    pub title: String,
+}
+
+/// The part of `yt-dlp --dump-json` output we care about.
+#[derive(Deserialize)]
+struct YtDlpInfo {
+    chapters: Option<Vec<Chapter>>,
+}
+
+/// Ask yt-dlp for the chapters of a video or episode, without downloading
+/// it. Content without chapters gives an empty list.
+pub async fn fetch(url: &str) -> io::Result<Vec<Chapter>> {
+    let output = Command::new("yt-dlp")
+        .arg("--dump-json")
+        .arg("--skip-download")
+        .arg("--no-playlist")
+        .arg(url)
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "yt-dlp failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        )));
+    }
+    let info: YtDlpInfo = serde_json::from_slice(&output.stdout)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(info.chapters.unwrap_or_default())
+}
+
+/// Group transcript segments under the chapter they start in.
+///
+/// Returns (chapter title, text) pairs in order. Any text from before the
+/// first chapter starts has no title.
+pub fn split_by_chapters(segments: &[Segment], chapters: &[Chapter]) -> Vec<(Option<String>, String)> {
+    let mut sections: Vec<(Option<String>, String)> = vec![];
+    let mut next_chapter = 0;
+    for segment in segments {
+        while next_chapter < chapters.len() && chapters[next_chapter].start_time <= segment.start {
+            sections.push((Some(chapters[next_chapter].title.clone()), String::new()));
+            next_chapter += 1;
+        }
+        if sections.is_empty() {
+            sections.push((None, String::new()));
+        }
+        let (_, text) = sections.last_mut().unwrap();
+        if !text.is_empty() {
+            text.push(' ');
+        }
+        text.push_str(segment.text.trim());
+    }
+    sections.retain(|(_, text)| !text.is_empty());
+    sections
+}
@@ -1,7 +1,19 @@
+use crate::fetch;
+use crate::lingq;
+use crate::postprocess;
 use crate::source;
-use serde::Deserialize;
+use crate::transcribe;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
 
+/// How long to wait for a configuration file given as a URL.
+const REMOTE_CONFIG_TIMEOUT: Duration = Duration::from_secs(15);
 const DEFAULT_REQUEST_DELAY: u64 = 5;
+const DEFAULT_IMPORT_TIMEOUT: u64 = 300;
+const DEFAULT_API_VERSION: lingq::ApiVersion = lingq::ApiVersion::V3;
 const DEFAULT_POSTPROCESSING_PROMPT: &str = "\
 You are editing the transcript for a podcast or video.
 You must NEVER modify the content of the transcript.
@@ -19,8 +31,17 @@ You SHALL group sentences into paragraphs if and when necessary.
 IF the transcript has multiple people, then you shall group sentences into \
 paragraphs by speaker.
 You SHALL insert a blank line between paragraphs.";
+const DEFAULT_POSTPROCESS_VIA: postprocess::PostprocessVia = postprocess::PostprocessVia::Openai;
+const DEFAULT_POSTPROCESS_MIN_CHARS: usize = 200;
+const DEFAULT_ON_POSTPROCESS_FAILURE: postprocess::PostprocessFailure = postprocess::PostprocessFailure::Skip;
+const DEFAULT_BATCH_POSTPROCESS_MAX_TOKENS: usize = 12_000;
+const DEFAULT_DOWNLOAD_FILENAME_TEMPLATE: &str = "{title}.{ext}";
+const DEFAULT_LOG_MAX_BYTES: u64 = 1_000_000;
+const DEFAULT_LOG_KEEP: usize = 5;
 const DEFAULT_POSTPROCESSING_MODEL: &str = "gpt-4o-mini";
 const DEFAULT_WHISPER_MODEL: &str = "whisper-1";
+const DEFAULT_DEEPGRAM_MODEL: &str = "nova-2";
+const DEFAULT_LOCAL_WHISPER_COMMAND: &str = "whisper-cli";
 
 #[derive(Deserialize)]
 pub struct LqcliConfig {
@@ -30,9 +51,125 @@ pub struct LqcliConfig {
     /// Settings for OpenAI
     pub openai: OpenaiConfig,
 
+    /// How to post-process transcripts: "openai" (the default), "local" to
+    /// use a local LLM configured in [local_llm], or "rules" for simple
+    /// offline cleanup without any LLM.
+    #[serde(default = "default_postprocess_via")]
+    pub postprocess_via: postprocess::PostprocessVia,
+
+    /// Transcripts shorter than this many characters are imported as-is,
+    /// whatever postprocess_via says, since post-processing a few sentences
+    /// isn't worth the time or money. Set to 0 to always post-process.
+    /// Defaults to 200.
+    #[serde(default = "default_postprocess_min_chars")]
+    pub postprocess_min_chars: usize,
+
+    /// What to do when post-processing an item's transcript fails (e.g. the
+    /// API is down or every key is rate limited): "skip" the item until the
+    /// next sync (the default), "import-raw" to import the transcript as it
+    /// came from transcription, or "error" to stop the run.
+    #[serde(default = "default_on_postprocess_failure")]
+    pub on_postprocess_failure: postprocess::PostprocessFailure,
+
+    /// Roughly how many tokens of transcript to post-process in one request
+    /// for sources with batch_postprocess. The reply is about as long as the
+    /// transcripts sent, so keep this under the model's output limit (16k
+    /// tokens for gpt-4o-mini). Defaults to 12000.
+    #[serde(default = "default_batch_postprocess_max_tokens")]
+    pub batch_postprocess_max_tokens: usize,
+
+    /// Settings for post-processing with a local LLM
+    pub local_llm: Option<LocalLlmConfig>,
+
+    /// Settings for transcribing with Deepgram, for sources with
+    /// transcript_via = "deepgram"
+    pub deepgram: Option<DeepgramConfig>,
+
+    /// Settings for transcribing on this machine with whisper.cpp, for
+    /// sources with transcript_via = "local"
+    pub local_whisper: Option<LocalWhisperConfig>,
+
+    /// A shell command to run after each lesson is imported
+    ///
+    /// Useful for sending notifications or logging. Details of the lesson
+    /// are passed in environment variables:
+    ///
+    /// - LQCLI_LESSON_TITLE: the lesson's title
+    /// - LQCLI_LESSON_ID: the lesson's ID, if LingQ told us
+    /// - LQCLI_LESSON_URL: the lesson's URL, if LingQ told us
+    /// - LQCLI_COURSE_ID: the course the lesson was imported into
+    /// - LQCLI_LANGUAGE: the lesson's language code
+    /// - LQCLI_SOURCE: the name of the source, or empty for one-off imports
+    ///
+    /// If the command fails, lqcli reports it and carries on.
+    pub post_import_command: Option<String>,
+
+    /// Limit how fast content is downloaded, in bytes per second, e.g.
+    /// "500K" or "2M" (K, M and G suffixes are understood). Useful on
+    /// metered or shared connections. Downloads are unlimited by default.
+    pub max_download_rate: Option<String>,
+
+    /// If set, keep the transcripts made during syncs in this directory, so
+    /// audio which has been transcribed before (with the same Whisper model)
+    /// isn't sent to the API again, e.g. when an import is retried. Off by
+    /// default.
+    pub transcript_cache_dir: Option<String>,
+
+    /// If set, keep a copy of all audio downloaded during syncs in this
+    /// directory.
+    pub download_dir: Option<String>,
+
+    /// How to name files saved to download_dir
+    ///
+    /// Can contain the placeholders {source} (the source's name), {title},
+    /// {date} (when the item was published, as YYYY-MM-DD), {guid} and
+    /// {ext}. Unsafe characters are replaced, overly long names are
+    /// truncated, and if the file already exists a counter is appended.
+    /// Defaults to "{title}.{ext}".
+    #[serde(default = "default_download_filename_template")]
+    pub download_filename_template: String,
+
+    /// What to do about an item whose audio is already in download_dir,
+    /// under the name download_filename_template gives it, for when that
+    /// directory is kept as the audio library:
+    ///
+    /// - "download": download it again anyway (the default)
+    /// - "reuse": use the file there instead of downloading it again
+    /// - "skip": take it as already imported, and leave the item out
+    #[serde(default)]
+    pub existing_downloads: fetch::ExistingDownloads,
+
+    /// If set, keep a log for each source in this directory, as
+    /// {source}.log, with everything syncs did with it: fetching its feed,
+    /// which items were considered and why any were skipped, and how each
+    /// download, transcription and import went. Handy for finding out what
+    /// went wrong in a run from cron. Off by default.
+    pub log_dir: Option<String>,
+
+    /// How big a source's log (see log_dir) can get, in bytes, before it's
+    /// rotated at the start of a sync: {source}.log becomes {source}.log.1,
+    /// that becomes {source}.log.2, and so on. Defaults to 1000000.
+    #[serde(default = "default_log_max_bytes")]
+    pub log_max_bytes: u64,
+
+    /// How many rotated logs to keep for each source. Defaults to 5.
+    #[serde(default = "default_log_keep")]
+    pub log_keep: usize,
+
     /// Sources are different ways of consuming content such as via RSS feeds
-    /// or websites to scrape.
+    /// or websites to scrape. There are none until some are added.
+    #[serde(default)]
     pub sources: Vec<source::Source>,
+
+    /// Named sets of overrides, selected with --profile
+    ///
+    /// Each [profiles.<name>] section can contain any of the settings above,
+    /// and replaces the top-level value when the profile is selected. Tables
+    /// like [profiles.work.lingq] are merged key by key, so a profile can
+    /// just give a different api_key. Lists (like sources) are replaced
+    /// entirely.
+    #[serde(default)]
+    pub profiles: BTreeMap<String, toml::Table>,
 }
 
 #[derive(Deserialize)]
@@ -48,9 +185,40 @@ pub struct LingqConfig {
     /// when importing multiple lessons.
     #[serde(default = "default_request_delay")]
     pub request_delay: u64,
+
+    /// Which version of the LingQ API to import lessons with ("v2" or "v3")
+    ///
+    /// Each version has its own endpoint and form field names. This only
+    /// exists as an escape hatch in case LingQ changes things around; the
+    /// default of "v3" is what the LingQ web app currently uses.
+    #[serde(default = "default_api_version")]
+    pub api_version: lingq::ApiVersion,
+
+    /// Check that each lesson really shows up in its course after importing
+    ///
+    /// LingQ has been known to accept an import but never create the lesson.
+    /// When this is set, lqcli re-fetches the course's lessons after each
+    /// import (waiting request_delay first) and complains if the new lesson
+    /// isn't there. Defaults to false.
+    #[serde(default)]
+    pub verify_imports: bool,
+
+    /// How long to wait for LingQ to finish an import (in seconds)
+    ///
+    /// LingQ can accept a lesson and carry on processing it (e.g. its
+    /// audio) afterwards. lqcli then checks on the lesson every
+    /// request_delay until it's ready, and treats the import as failed if
+    /// it still isn't after this long. Defaults to 300.
+    #[serde(default = "default_import_timeout")]
+    pub import_timeout: u64,
+
+    /// Base URL of the LingQ API, e.g. for a proxy or a test server
+    ///
+    /// Defaults to https://www.lingq.com/api.
+    pub api_base: Option<String>,
 }
 
-#[derive(Deserialize)]
+#[derive(Clone, Deserialize)]
 pub struct OpenaiConfig {
     /// Your OpenAI API key
     ///
@@ -61,6 +229,18 @@ pub struct OpenaiConfig {
     /// keys.
     pub api_key: String,
 
+    /// Additional OpenAI API keys to spread requests across
+    ///
+    /// When set, requests rotate round-robin between api_key and these keys.
+    /// A key which is rate limited (or out of quota) is set aside for a
+    /// minute and the request is retried with the next key.
+    pub api_keys: Option<Vec<String>>,
+
+    /// Base URL of the API, for OpenAI-compatible services
+    ///
+    /// Defaults to https://api.openai.com/v1.
+    pub api_base: Option<String>,
+
     /// Prompt for post-processing a transcript before it is imported.
     ///
     /// This is a GPT prompt sent to whichever model has been selected in
@@ -78,19 +258,106 @@ pub struct OpenaiConfig {
     #[serde(default = "default_postprocessing_model")]
     pub postprocessing_model: String,
 
-    /// The Whisper model to use for creating transcripts from audio.
-    ///
-    /// This currently uses the OpenAI API, but in the future will allow for
-    /// local processing using the open source Whisper models and whisper-rs.
-    /// Defaults to "whisper-1".
+    /// The Whisper model to use for creating transcripts from audio with the
+    /// OpenAI API. (To transcribe on this machine instead, see
+    /// [local_whisper].) Defaults to "whisper-1".
     #[serde(default = "default_whisper_model")]
     pub whisper_model: String,
+
+    /// The sampling temperature for Whisper, from 0.0 to 1.0. 0.0 is the
+    /// most deterministic; raising it a little can break the loops of
+    /// repeated phrases Whisper sometimes gets stuck in on noisy audio.
+    /// Left to OpenAI if not set. Sources can override it with their own
+    /// whisper_temperature.
+    pub whisper_temperature: Option<f32>,
+}
+
+#[derive(Deserialize)]
+pub struct LocalLlmConfig {
+    /// Base URL of an OpenAI-compatible API, such as the one served by
+    /// Ollama (http://localhost:11434/v1) or llama.cpp's server.
+    pub api_base: String,
+
+    /// The model to ask for, as the server knows it (e.g. "llama3.1")
+    pub model: String,
+
+    /// API key, if the server wants one
+    pub api_key: Option<String>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct DeepgramConfig {
+    /// Your Deepgram API key
+    pub api_key: String,
+
+    /// The Deepgram model to transcribe with. Defaults to "nova-2".
+    #[serde(default = "default_deepgram_model")]
+    pub model: String,
+
+    /// Label who is speaking (e.g. "Speaker 0:") at the start of each
+    /// paragraph. Pairs well with a speaker_pattern like "Speaker \\d+:".
+    /// Defaults to false.
+    #[serde(default)]
+    pub diarize: bool,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct LocalWhisperConfig {
+    /// Path to the whisper.cpp (GGML) model to transcribe with, e.g.
+    /// "~/models/ggml-large-v3.bin"
+    pub model: String,
+
+    /// The whisper.cpp program to run. Defaults to "whisper-cli" (older
+    /// builds call it "main"), looked for on the PATH. Not used when lqcli
+    /// is built with the `whisper-rs` feature, which runs the model itself.
+    #[serde(default = "default_local_whisper_command")]
+    pub command: String,
+
+    /// How many threads whisper.cpp should use. Left to whisper.cpp if not
+    /// set.
+    pub threads: Option<u32>,
 }
 
 fn default_request_delay() -> u64 {
     DEFAULT_REQUEST_DELAY
 }
 
+fn default_import_timeout() -> u64 {
+    DEFAULT_IMPORT_TIMEOUT
+}
+
+fn default_api_version() -> lingq::ApiVersion {
+    DEFAULT_API_VERSION
+}
+
+fn default_postprocess_via() -> postprocess::PostprocessVia {
+    DEFAULT_POSTPROCESS_VIA
+}
+
+fn default_postprocess_min_chars() -> usize {
+    DEFAULT_POSTPROCESS_MIN_CHARS
+}
+
+fn default_on_postprocess_failure() -> postprocess::PostprocessFailure {
+    DEFAULT_ON_POSTPROCESS_FAILURE
+}
+
+fn default_batch_postprocess_max_tokens() -> usize {
+    DEFAULT_BATCH_POSTPROCESS_MAX_TOKENS
+}
+
+fn default_download_filename_template() -> String {
+    DEFAULT_DOWNLOAD_FILENAME_TEMPLATE.to_string()
+}
+
+fn default_log_max_bytes() -> u64 {
+    DEFAULT_LOG_MAX_BYTES
+}
+
+fn default_log_keep() -> usize {
+    DEFAULT_LOG_KEEP
+}
+
 fn default_postprocessing_prompt() -> String {
     DEFAULT_POSTPROCESSING_PROMPT.to_string()
 }
@@ -103,28 +370,464 @@ fn default_whisper_model() -> String {
     DEFAULT_WHISPER_MODEL.to_string()
 }
 
+fn default_deepgram_model() -> String {
+    DEFAULT_DEEPGRAM_MODEL.to_string()
+}
+
+fn default_local_whisper_command() -> String {
+    DEFAULT_LOCAL_WHISPER_COMMAND.to_string()
+}
+
+impl OpenaiConfig {
+    /// api_key followed by any api_keys, without duplicates.
+    pub fn all_api_keys(&self) -> Vec<String> {
+        let mut keys = vec![self.api_key.clone()];
+        for key in self.api_keys.iter().flatten() {
+            if !keys.contains(key) {
+                keys.push(key.clone());
+            }
+        }
+        keys
+    }
+}
+
+/// Configuration files fetched from URLs during this run, so that checking
+/// one exists and then reading it only downloads it once.
+static REMOTE_CONFIGS: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+
+/// Whether the configuration "file" is really a URL.
+///
+/// Errors for URLs with a scheme other than http or https.
+pub fn is_remote(path: &str) -> Result<bool, std::io::Error> {
+    match path.split_once("://") {
+        Some(("http" | "https", _)) => Ok(true),
+        Some((scheme, _)) if !scheme.contains(['/', '\\']) => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Unsupported configuration URL scheme: {}", scheme),
+        )),
+        _ => Ok(false),
+    }
+}
+
+/// Fetch a configuration file from a URL (or the copy already fetched this
+/// run).
+async fn fetch_remote(url: &str) -> Result<String, std::io::Error> {
+    if let Some((_, toml)) = REMOTE_CONFIGS.lock().unwrap().iter().find(|(u, _)| u == url) {
+        return Ok(toml.clone());
+    }
+    let client = reqwest::Client::builder()
+        .timeout(REMOTE_CONFIG_TIMEOUT)
+        .build()
+        .map_err(std::io::Error::other)?;
+    let toml = client
+        .get(url)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(std::io::Error::other)?
+        .text()
+        .await
+        .map_err(std::io::Error::other)?;
+    REMOTE_CONFIGS.lock().unwrap().push((url.to_string(), toml.clone()));
+    Ok(toml)
+}
+
+/// Change a source's url in the configuration file at `path` from `old` to
+/// `new`, editing the text in place so comments and layout are kept. Only
+/// `url = ...` lines are changed, so the URL is left alone elsewhere (e.g.
+/// in comments). Returns whether `old` was found; it's an error if more
+/// than one line has it, as with rename_source. Configurations read from a
+/// URL can't be changed.
+pub fn replace_source_url(path: &str, old: &str, new: &str) -> Result<bool, std::io::Error> {
+    let normalized_path = writable_path(path)?;
+    let toml = std::fs::read_to_string(&normalized_path)?;
+    let pattern = Regex::new(&format!(
+        r#"(?m)^(\s*url\s*=\s*)(?:"{0}"|'{0}')(\s*(?:#.*)?)$"#,
+        regex::escape(old)
+    ))
+    .map_err(std::io::Error::other)?;
+    match pattern.find_iter(&toml).count() {
+        0 => return Ok(false),
+        1 => {}
+        found => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("found {} sources with the url \"{}\", expected one; change it by hand", found, old),
+            ));
+        }
+    }
+    let quoted = toml::Value::String(new.to_string()).to_string();
+    let updated = pattern.replace(&toml, |captures: &regex::Captures| {
+        format!("{}{}{}", &captures[1], quoted, &captures[2])
+    });
+    std::fs::write(&normalized_path, updated.as_ref())?;
+    Ok(true)
+}
+
+/// Rename a source in the configuration file at `path`, editing the text in
+/// place so comments and layout are kept. It's an error unless exactly one
+/// `name = ...` line has the old name, since anything else is too unusual
+/// to guess at.
+pub fn rename_source(path: &str, old: &str, new: &str) -> Result<(), std::io::Error> {
+    let normalized_path = writable_path(path)?;
+    let toml = std::fs::read_to_string(&normalized_path)?;
+    let pattern = Regex::new(&format!(
+        r#"(?m)^(\s*name\s*=\s*)(?:"{0}"|'{0}')(\s*(?:#.*)?)$"#,
+        regex::escape(old)
+    ))
+    .map_err(std::io::Error::other)?;
+    let found = pattern.find_iter(&toml).count();
+    if found != 1 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("found {} lines naming a source \"{}\", expected one; rename it by hand", found, old),
+        ));
+    }
+    let quoted = toml::Value::String(new.to_string()).to_string();
+    let updated = pattern.replace(&toml, |captures: &regex::Captures| {
+        format!("{}{}{}", &captures[1], quoted, &captures[2])
+    });
+    std::fs::write(&normalized_path, updated.as_ref())
+}
+
+/// A configuration file to start from, as written by `lqcli init`: the
+/// API keys to fill in, the usual defaults spelled out, and an example
+/// source to copy.
+pub fn starter_config() -> String {
+    format!(
+        r#"# lqcli configuration. Fill in your API keys below, then add sources with
+# `lqcli sources add` or by copying the example at the end.
+
+[lingq]
+# Your LingQ API key, from https://www.lingq.com/accounts/apikey/
+api_key = "YOUR-LINGQ-API-KEY"
+# Seconds to wait between requests to the LingQ API
+request_delay = {request_delay}
+
+[openai]
+# Your OpenAI API key, for transcribing and post-processing
+api_key = "YOUR-OPENAI-API-KEY"
+postprocessing_model = "{postprocessing_model}"
+whisper_model = "{whisper_model}"
+
+# [[sources]]
+# name = "My podcast"
+# url = "https://example.com/feed.xml"
+# course_id = 123456
+# language = "de"
+# tags = ["daily"]
+"#,
+        request_delay = DEFAULT_REQUEST_DELAY,
+        postprocessing_model = DEFAULT_POSTPROCESSING_MODEL,
+        whisper_model = DEFAULT_WHISPER_MODEL,
+    )
+}
+
+/// Write starter_config to `path`, readable only by its owner on Unix since
+/// it's where the API keys go. Unless `force` is given, an existing file is
+/// left alone and it's an error.
+pub fn write_starter_config(path: &str, force: bool) -> Result<(), std::io::Error> {
+    let normalized_path = writable_path(path)?;
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true);
+    if force {
+        options.create(true).truncate(true);
+    } else {
+        options.create_new(true);
+    }
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&normalized_path)?;
+    // The mode only applies to new files, so tighten an overwritten one too.
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+    std::io::Write::write_all(&mut file, starter_config().as_bytes())
+}
+
+/// A source to add to the configuration file with add_source: the settings
+/// every source needs, leaving the rest at their defaults.
+#[derive(Serialize)]
+pub struct NewSource {
+    pub name: String,
+    pub url: String,
+    pub course_id: u64,
+    pub language: Option<String>,
+    pub tags: Vec<String>,
+    /// As written in the configuration file, e.g. "syndication".
+    pub content_type: String,
+}
+
+/// Add a source to the end of the configuration file at `path`, leaving the
+/// rest of the file as it is. It's an error if there's already a source with
+/// the same name, or if the new one wouldn't be a valid source (e.g. an
+/// unknown content_type).
+pub fn add_source(path: &str, new: &NewSource) -> Result<(), std::io::Error> {
+    let normalized_path = writable_path(path)?;
+    let toml = std::fs::read_to_string(&normalized_path)?;
+    let invalid_data = |e: String| std::io::Error::new(std::io::ErrorKind::InvalidData, e);
+    let table: toml::Table = toml::from_str(&toml).map_err(|e| invalid_data(e.to_string()))?;
+    let exists = table
+        .get("sources")
+        .and_then(toml::Value::as_array)
+        .is_some_and(|sources| {
+            sources.iter().any(|source| source.get("name").and_then(toml::Value::as_str) == Some(new.name.as_str()))
+        });
+    if exists {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("there is already a source named {}", new.name),
+        ));
+    }
+    let body = toml::to_string(new).map_err(|e| invalid_data(e.to_string()))?;
+    toml::from_str::<source::Source>(&body).map_err(|e| invalid_data(e.message().to_string()))?;
+    let separator = if toml.is_empty() || toml.ends_with("\n\n") {
+        ""
+    } else if toml.ends_with('\n') {
+        "\n"
+    } else {
+        "\n\n"
+    };
+    std::fs::write(&normalized_path, format!("{}{}[[sources]]\n{}", toml, separator, body))
+}
+
+/// The local path of a configuration file lqcli is about to change, or an
+/// error if it was read from a URL.
+fn writable_path(path: &str) -> Result<String, std::io::Error> {
+    if is_remote(path)? {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "the configuration was read from a URL, so lqcli can't change it",
+        ));
+    }
+    Ok(shellexpand::tilde(path).to_string())
+}
+
+/// Which source is to blame when a configuration won't deserialize, and
+/// why, since errors from a table (rather than the text of the file) don't
+/// say where they are, e.g. "source news: unknown variant `lingqq`, ...".
+fn source_error(table: &toml::Table) -> Option<String> {
+    let sources = table.get("sources")?.as_array()?;
+    sources.iter().find_map(|source| {
+        let error = source.clone().try_into::<source::Source>().err()?;
+        let name = source.get("name").and_then(toml::Value::as_str).unwrap_or("(unnamed)");
+        Some(format!("source {}: {}", name, error.message()))
+    })
+}
+
+/// Overlay `overrides` onto `base`, recursing into tables so that only the
+/// keys given in `overrides` change.
+fn merge(base: &mut toml::Table, overrides: toml::Table) {
+    for (key, value) in overrides {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base_table)), toml::Value::Table(table)) => merge(base_table, table),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
 impl LqcliConfig {
-    pub fn read(path: &str) -> Result<Self, std::io::Error> {
-        let normalized_path = shellexpand::tilde(path).to_string();
-        let toml = std::fs::read_to_string(normalized_path)?;
-        toml::from_str(&toml).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    /// Read the configuration from a file, or from an http(s) URL, applying
+    /// the overrides from the given profile if there is one.
+    pub async fn read(path: &str, profile: Option<&str>) -> Result<Self, std::io::Error> {
+        let toml = if is_remote(path)? {
+            fetch_remote(path).await?
+        } else {
+            let normalized_path = shellexpand::tilde(path).to_string();
+            std::fs::read_to_string(normalized_path)?
+        };
+        let invalid_data = |e| std::io::Error::new(std::io::ErrorKind::InvalidData, e);
+        let mut table: toml::Table = toml::from_str(&toml).map_err(invalid_data)?;
+        if let Some(profile) = profile {
+            let overrides = table
+                .get("profiles")
+                .and_then(|profiles| profiles.get(profile))
+                .and_then(|overrides| overrides.as_table())
+                .cloned();
+            let Some(overrides) = overrides else {
+                let available: Vec<String> = table
+                    .get("profiles")
+                    .and_then(|profiles| profiles.as_table())
+                    .map(|profiles| profiles.keys().cloned().collect())
+                    .unwrap_or_default();
+                let available = if available.is_empty() {
+                    "there are none".to_string()
+                } else {
+                    format!("available: {}", available.join(", "))
+                };
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("no profile named \"{}\" ({})", profile, available),
+                ));
+            };
+            merge(&mut table, overrides);
+        }
+        table.clone().try_into().map_err(|e: toml::de::Error| {
+            let message = source_error(&table).unwrap_or_else(|| e.to_string());
+            std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+        })
     }
 
-    pub fn exists(path: &str) -> bool {
-        let normalized_path = shellexpand::tilde(path).to_string();
-        std::path::Path::new(&normalized_path).exists()
+    pub async fn exists(path: &str) -> bool {
+        match is_remote(path) {
+            Ok(true) => fetch_remote(path).await.is_ok(),
+            Ok(false) => {
+                let normalized_path = shellexpand::tilde(path).to_string();
+                std::path::Path::new(&normalized_path).exists()
+            }
+            Err(_) => false,
+        }
     }
 
-    pub fn filtered_sources(&self, tags: &[String]) -> Vec<&source::Source> {
-        if tags.is_empty() {
-            return self.sources.iter().collect();
+    /// Look for things in the configuration which are allowed, but probably
+    /// mistakes. Returns a warning message for each one found.
+    pub fn validate(&self) -> Vec<String> {
+        let mut warnings = vec![];
+
+        // Sources sharing a course is usually a copy-paste mistake.
+        let mut courses: Vec<((&str, u64), Vec<&str>)> = vec![];
+        for source in self
+            .sources
+            .iter()
+            .filter(|source| !source.allow_shared_course && source.course_id != lingq::NO_COURSE)
+        {
+            let key = (source.language.as_deref().unwrap_or("the feed's language"), source.course_id);
+            match courses.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, names)) => names.push(&source.name),
+                None => courses.push((key, vec![&source.name])),
+            }
         }
-        self.sources.iter().filter(|source| {
-            if let Some(source_tags) = &source.tags.0 {
-                source_tags.iter().any(|tag| tags.contains(tag))
-            } else {
-                false
+        for ((language, course_id), names) in courses {
+            if names.len() > 1 {
+                warnings.push(format!(
+                    "Sources {} all import into course {} ({}); set allow_shared_course = true on them if this is intended",
+                    names.join(", "),
+                    course_id,
+                    language,
+                ));
+            }
+        }
+
+        if self.download_dir.is_none() && self.existing_downloads != fetch::ExistingDownloads::Download {
+            warnings.push(
+                "existing_downloads is set but download_dir isn't, so there are no downloads to go by".to_string(),
+            );
+        }
+
+        if let Some(rate) = self.max_download_rate.as_deref().filter(|rate| !fetch::is_valid_rate(rate)) {
+            warnings.push(format!(
+                "max_download_rate \"{}\" isn't a rate like \"500K\" or \"2M\", ignoring it",
+                rate
+            ));
+        }
+
+        for source in &self.sources {
+            if source.transcript_via == transcribe::TranscriptVia::Deepgram && self.deepgram.is_none() {
+                warnings.push(format!(
+                    "Source {} has transcript_via = \"deepgram\" but there is no [deepgram] section, so its items can't be transcribed",
+                    source.name
+                ));
+            }
+            if source.transcript_via == transcribe::TranscriptVia::Local && self.local_whisper.is_none() {
+                warnings.push(format!(
+                    "Source {} has transcript_via = \"local\" but there is no [local_whisper] section, so its items can't be transcribed",
+                    source.name
+                ));
+            }
+            if source.course_id == lingq::NO_COURSE {
+                warnings.push(format!(
+                    "Source {} has course_id 0 (no course), so items can't be checked against existing lessons and will be imported again on every sync",
+                    source.name
+                ));
+            }
+            if let Err(e) = source.feed_headers() {
+                warnings.push(format!("Source {} has bad headers: {}", source.name, e));
+            }
+            if let Some(Err(e)) = source.language.as_deref().map(lingq::check_language) {
+                warnings.push(format!("Source {} has a bad language, so its items can't be imported: {}", source.name, e));
+            }
+            if let Some(temperature) = source.whisper_temperature.or(self.openai.whisper_temperature) {
+                if !(0.0..=1.0).contains(&temperature) {
+                    warnings.push(format!(
+                        "Source {} has a whisper_temperature of {}, outside the 0.0 to 1.0 OpenAI accepts",
+                        source.name, temperature
+                    ));
+                }
             }
+            match (source.transcript_via, &source.transcript_url_template) {
+                (transcribe::TranscriptVia::Url, None) => warnings.push(format!(
+                    "Source {} has transcript_via = \"url\" but no transcript_url_template, so its items will be transcribed with Whisper",
+                    source.name
+                )),
+                (via, Some(_)) if via != transcribe::TranscriptVia::Url => warnings.push(format!(
+                    "Source {} has a transcript_url_template, which is only used with transcript_via = \"url\"",
+                    source.name
+                )),
+                _ => {}
+            }
+            let lists_videos = matches!(source.content_type, source::ContentType::YoutubeChannel);
+            if lists_videos && !matches!(source.download_method, fetch::DownloadMethod::YtDlp) {
+                warnings.push(format!(
+                    "Source {} is a youtube-channel, whose items are video pages only yt-dlp can download; set download_method = \"yt-dlp\"",
+                    source.name
+                ));
+            }
+            if source.max_items == Some(0) {
+                warnings.push(format!("Source {} has max_items = 0, so nothing will be synced from it", source.name));
+            }
+            if let Some(level) = source.level.filter(|level| !lingq::LEVELS.contains(level)) {
+                warnings.push(format!(
+                    "Source {} has level {}, but LingQ's levels go from 1 to 6, ignoring it",
+                    source.name, level
+                ));
+            }
+            for name in source.lingq_fields.iter().flatten().map(|(name, _)| name) {
+                if !lingq::is_valid_extra_field(name) {
+                    warnings.push(format!(
+                        "Source {} has lingq_fields entry \"{}\", which isn't a field lqcli can send, ignoring it",
+                        source.name, name
+                    ));
+                }
+            }
+            if let Err(e) = source.speaker_regex() {
+                warnings.push(format!("Source {} has an invalid speaker_pattern, ignoring it: {}", source.name, e));
+            }
+        }
+
+        warnings
+    }
+
+    /// max_download_rate, if it's set and valid.
+    pub fn download_rate(&self) -> Option<&str> {
+        self.max_download_rate.as_deref().filter(|rate| fetch::is_valid_rate(rate))
+    }
+
+    /// The sources with any of `tags` (or all of them, if there are none)
+    /// whose names match `name`, if given.
+    pub fn filtered_sources(&self, tags: &[String], name: Option<&NamePattern>) -> Vec<&source::Source> {
+        self.sources.iter().filter(|source| {
+            let tagged = tags.is_empty() || match &source.tags.0 {
+                Some(source_tags) => source_tags.iter().any(|tag| tags.contains(tag)),
+                None => false,
+            };
+            tagged && name.is_none_or(|name| name.matches(&source.name))
         }).collect()
     }
 }
+
+/// A pattern for picking sources by name.
+pub enum NamePattern {
+    /// Names containing this, ignoring case.
+    Substring(String),
+    Regex(Regex),
+}
+
+impl NamePattern {
+    pub fn matches(&self, name: &str) -> bool {
+        match self {
+            NamePattern::Substring(part) => name.to_lowercase().contains(&part.to_lowercase()),
+            NamePattern::Regex(regex) => regex.is_match(name),
+        }
+    }
+}
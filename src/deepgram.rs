@@ -0,0 +1,91 @@
+//! Transcription through Deepgram's API, as an alternative to Whisper.
+
+use crate::config;
+use crate::openai::ApiError;
+use crate::transcribe::Transcriber;
+use reqwest::{header, Client};
+use serde::Deserialize;
+
+const LISTEN_URL: &str = "https://api.deepgram.com/v1/listen";
+
+pub struct Deepgram {
+    config: config::DeepgramConfig,
+    client: Client,
+}
+
+/// The parts of Deepgram's response we care about.
+#[derive(Deserialize)]
+struct ListenResponse {
+    results: ListenResults,
+}
+
+#[derive(Deserialize)]
+struct ListenResults {
+    channels: Vec<Channel>,
+}
+
+#[derive(Deserialize)]
+struct Channel {
+    alternatives: Vec<Alternative>,
+}
+
+#[derive(Deserialize)]
+struct Alternative {
+    transcript: String,
+    paragraphs: Option<Paragraphs>,
+}
+
+/// The transcript laid out in paragraphs (with speaker labels, when
+/// diarizing), which smart_format gives us.
+#[derive(Deserialize)]
+struct Paragraphs {
+    transcript: String,
+}
+
+impl Deepgram {
+    pub fn new(config: config::DeepgramConfig) -> Self {
+        Self { config, client: Client::new() }
+    }
+
+    async fn listen(&self, audio: Vec<u8>, language: &str) -> Result<String, reqwest::Error> {
+        let response = self
+            .client
+            .post(LISTEN_URL)
+            .header(header::AUTHORIZATION, format!("Token {}", self.config.api_key))
+            .header(header::CONTENT_TYPE, "audio/mpeg")
+            .query(&[
+                ("model", self.config.model.as_str()),
+                ("language", language),
+                ("smart_format", "true"),
+                ("diarize", if self.config.diarize { "true" } else { "false" }),
+            ])
+            .body(audio)
+            .send()
+            .await?
+            .error_for_status()?;
+        let response: ListenResponse = response.json().await?;
+        let transcript = response
+            .results
+            .channels
+            .into_iter()
+            .next()
+            .and_then(|channel| channel.alternatives.into_iter().next())
+            .map(|alternative| match alternative.paragraphs {
+                Some(paragraphs) => paragraphs.transcript,
+                None => alternative.transcript,
+            })
+            .unwrap_or_default();
+        Ok(transcript)
+    }
+}
+
+impl Transcriber for Deepgram {
+    async fn transcribe(&self, audio: Vec<u8>, language: &str) -> Result<String, ApiError> {
+        let transcript = self.listen(audio, language).await.map_err(ApiError::Deepgram)?;
+        Some(transcript.trim().to_string()).filter(|text| !text.is_empty()).ok_or(ApiError::Empty)
+    }
+
+    fn name(&self) -> String {
+        format!("deepgram/{}", self.config.model)
+    }
+}
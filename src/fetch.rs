@@ -1,20 +1,70 @@
 use serde::de::{IntoDeserializer, value};
 use serde::Deserialize;
+use futures::{Stream, StreamExt};
 use std::fmt::Display;
 use std::io;
-use std::io::Read;
-use std::fs::File;
-use std::process::Command;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::LazyLock;
+use std::time::{Duration, Instant};
+use regex::Regex;
 use tempfile::NamedTempFile;
+use tokio::process::Command;
 
-use crate::source::{Source, SourceItem, SourceError};
+use crate::audio;
+use crate::source::{self, SourceItem, SourceError};
+
+/// How much of a file to fetch first when only its start is needed, to work
+/// out its bitrate from.
+const PROBE_BYTES: u64 = 256 * 1024;
+
+/// Podcast analytics services which wrap the real audio URL in their own,
+/// e.g. https://chtbl.com/track/ABC123/traffic.example.com/episode.mp3. The
+/// real URL follows the matched prefix, with or without its scheme.
+static TRACKING_PREFIX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"^(https?)://(?:chtbl\.com/track/[^/]+|chrt\.fm/track/[^/]+|pdst\.fm/e|dts\.podtrac\.com/redirect\.[a-z0-9]+|www\.podtrac\.com/pts/redirect\.[a-z0-9]+|op3\.dev/e(?:,[^/]*)?|pfx\.vpixl\.com/[^/]+|arttrk\.com/p/[^/]+|prfx\.byspotify\.com/e|mgln\.ai/e/[^/]+|verifi\.podscribe\.com/rss/p|claritaspod\.com/measure|clrtpod\.com/m)/",
+    )
+    .unwrap()
+});
 
 #[derive(Clone, Debug, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub enum DownloadMethod {
     /// `yt-dlp` - Use yt-dlp to download the content.
     YtDlp,
+    /// `direct` - Download the link as it is with a plain HTTP request. Only
+    /// for links straight to an audio file, but when only the start of it
+    /// is needed (e.g. with --end), only that much is downloaded if the
+    /// server supports it.
+    Direct,
+}
+
+/// What to do about an item whose audio is already in download_dir.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExistingDownloads {
+    /// Download it again anyway (and save another copy).
+    #[default]
+    Download,
+    /// Use the file already there rather than downloading it again.
+    Reuse,
+    /// Take the item as already imported, and leave it out.
+    Skip,
+}
+
+/// How to download an item's content, beyond which method to use.
+#[derive(Clone, Copy, Default)]
+pub struct DownloadOptions<'a> {
+    /// Limit the download to this many bytes per second, e.g. "500K".
+    pub max_rate: Option<&'a str>,
+    /// Pick the audio track in this language (yt-dlp only).
+    pub audio_lang: Option<&'a str>,
+    /// Only the audio up to this many seconds in is needed (direct only).
+    pub until: Option<f64>,
+    /// Strip tracking prefixes from the link and follow its redirects before
+    /// downloading, and download the URL it ends up at.
+    pub unwrap_redirects: bool,
 }
 
 impl FromStr for DownloadMethod {
@@ -29,19 +79,165 @@ impl Display for DownloadMethod {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             DownloadMethod::YtDlp => write!(f, "yt-dlp"),
+            DownloadMethod::Direct => write!(f, "direct"),
+        }
+    }
+}
+
+/// Whether a download rate is in the form yt-dlp understands: a number of
+/// bytes per second, optionally with a K, M or G suffix (e.g. "500K").
+pub fn is_valid_rate(rate: &str) -> bool {
+    parse_rate(rate).is_some()
+}
+
+/// A download rate (see is_valid_rate) in bytes per second. As with yt-dlp,
+/// the suffixes are powers of 1024.
+pub fn parse_rate(rate: &str) -> Option<f64> {
+    let (number, scale) = match rate.chars().last()?.to_ascii_uppercase() {
+        'K' => (&rate[..rate.len() - 1], 1024.0),
+        'M' => (&rate[..rate.len() - 1], 1024.0 * 1024.0),
+        'G' => (&rate[..rate.len() - 1], 1024.0 * 1024.0 * 1024.0),
+        _ => (rate, 1.0),
+    };
+    number.parse::<f64>().ok().filter(|n| *n > 0.0).map(|n| n * scale)
+}
+
+/// Keeps a direct download under max_download_rate, by pausing after each
+/// chunk until the download as a whole is back under the rate.
+struct Throttle {
+    rate: Option<f64>,
+    started: Instant,
+    received: u64,
+}
+
+impl Throttle {
+    fn new(max_rate: Option<&str>) -> Self {
+        Self { rate: max_rate.and_then(parse_rate), started: Instant::now(), received: 0 }
+    }
+
+    async fn received(&mut self, bytes: usize) {
+        self.received += bytes as u64;
+        let Some(rate) = self.rate else { return };
+        let due = Duration::from_secs_f64(self.received as f64 / rate);
+        if let Some(wait) = due.checked_sub(self.started.elapsed()) {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// Read a response's body a chunk at a time, as fast as `throttle` allows.
+async fn read_body(response: reqwest::Response, throttle: &mut Throttle) -> Result<Vec<u8>, SourceError> {
+    let mut body = vec![];
+    let mut chunks = response.bytes_stream();
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk?;
+        body.extend_from_slice(&chunk);
+        throttle.received(chunk.len()).await;
+    }
+    Ok(body)
+}
+
+/// The lines of yt-dlp's stderr worth showing: its warnings and deprecation
+/// notices (e.g. falling back to a lower quality format), without repeats.
+fn yt_dlp_warnings(stderr: &str) -> Vec<&str> {
+    let mut warnings: Vec<&str> = vec![];
+    for line in stderr.lines().map(str::trim) {
+        let is_warning = line.starts_with("WARNING:") || line.starts_with("Deprecated Feature:");
+        if is_warning && !warnings.contains(&line) {
+            warnings.push(line);
         }
     }
+    warnings
+}
+
+/// The part of `yt-dlp --dump-json` output listing what can be downloaded.
+#[derive(Deserialize)]
+struct YtDlpInfo {
+    #[serde(default)]
+    formats: Vec<YtDlpFormat>,
+}
+
+#[derive(Deserialize)]
+struct YtDlpFormat {
+    language: Option<String>,
+    acodec: Option<String>,
+}
+
+/// Whether a track's language (e.g. "de-DE") is the given one (e.g. "de").
+fn is_language(track_language: &str, language: &str) -> bool {
+    let track_language = track_language.to_ascii_lowercase();
+    let language = language.to_ascii_lowercase();
+    track_language == language || track_language.starts_with(&format!("{}-", language))
+}
+
+/// Ask yt-dlp which languages the audio tracks of some content are in,
+/// without downloading it.
+async fn yt_dlp_audio_languages(url: &str) -> io::Result<Vec<String>> {
+    let output = Command::new("yt-dlp")
+        .arg("--dump-json")
+        .arg("--skip-download")
+        .arg("--no-playlist")
+        .arg(url)
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "yt-dlp failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        )));
+    }
+    let info: YtDlpInfo = serde_json::from_slice(&output.stdout)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let mut languages: Vec<String> = info
+        .formats
+        .into_iter()
+        .filter(|format| format.acodec.as_deref() != Some("none"))
+        .filter_map(|format| format.language)
+        .collect();
+    languages.sort();
+    languages.dedup();
+    Ok(languages)
 }
 
 /// Call `yt-dlp` to download the content.
 ///
-/// Download the content and return a Vec<u8> with the content.
-fn yt_dlp(url: &str) -> io::Result<Vec<u8>> {
+/// Download the content and return a Vec<u8> with the content. If
+/// `max_rate` is given, the download is limited to that many bytes per
+/// second. If `audio_lang` is given, the audio track in that language is
+/// downloaded rather than the default one, and it's an error if there isn't
+/// one. Any warnings yt-dlp prints are passed on, but don't fail the
+/// download.
+///
+/// yt-dlp runs as a child process, so other work carries on while it
+/// downloads.
+async fn yt_dlp(url: &str, max_rate: Option<&str>, audio_lang: Option<&str>) -> io::Result<Vec<u8>> {
+    let format = match audio_lang {
+        Some(language) => {
+            let languages = yt_dlp_audio_languages(url).await?;
+            if !languages.iter().any(|track| is_language(track, language)) {
+                let available = if languages.is_empty() {
+                    "none are labelled".to_string()
+                } else {
+                    format!("available: {}", languages.join(", "))
+                };
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no audio track in \"{}\" ({})", language, available),
+                ));
+            }
+            format!("bestaudio[language^={0}]/best[language^={0}]", language)
+        }
+        None => "bestaudio/best".to_string(),
+    };
     let tmpfile = NamedTempFile::with_suffix(".mp3")?;
     let tmpfile_path = tmpfile.path();
-    let output = Command::new("yt-dlp")
+    let mut command = Command::new("yt-dlp");
+    if let Some(max_rate) = max_rate {
+        command.arg("--limit-rate").arg(max_rate);
+    }
+    let output = command
         .arg("--format")
-        .arg("bestaudio/best")
+        .arg(format)
         .arg("-x")
         .arg("--audio-format")
         .arg("mp3")
@@ -49,22 +245,234 @@ fn yt_dlp(url: &str) -> io::Result<Vec<u8>> {
         .arg(tmpfile_path)
         .arg("--force-overwrites")
         .arg(url)
-        .output()?;
+        .output()
+        .await?;
     if !output.status.success() {
         return Err(io::Error::new(
             io::ErrorKind::Other,
             format!("yt-dlp failed: {}", String::from_utf8_lossy(&output.stderr)),
         ));
     }
-    let mut tmpfile_reopened = File::open(tmpfile_path)?;
-    let mut content = Vec::new();
-    tmpfile_reopened.read_to_end(&mut content)?;
-    Ok(content)
+    for warning in yt_dlp_warnings(&String::from_utf8_lossy(&output.stderr)) {
+        crate::report::warn("yt-dlp", None, format!("yt-dlp: {}", warning));
+    }
+    tokio::fs::read(tmpfile_path).await
+}
+
+/// Fetch part of a file with an HTTP range request, from `start` up to (not
+/// including) `end`. Returns None if the server doesn't do range requests.
+async fn get_range(
+    client: &reqwest::Client,
+    url: &str,
+    start: u64,
+    end: u64,
+    throttle: &mut Throttle,
+) -> Result<Option<Vec<u8>>, SourceError> {
+    let response = client
+        .get(url)
+        .header(reqwest::header::RANGE, format!("bytes={}-{}", start, end - 1))
+        .send()
+        .await?;
+    match response.status() {
+        reqwest::StatusCode::PARTIAL_CONTENT => Ok(Some(read_body(response, throttle).await?)),
+        // Starting past the end of the file.
+        reqwest::StatusCode::RANGE_NOT_SATISFIABLE => Ok(Some(vec![])),
+        // Anything else (usually 200 with the whole file) is dropped unread.
+        _ => Ok(None),
+    }
+}
+
+/// Download just enough of an audio file to cover its first `seconds`, going
+/// by its bitrate. Returns None if the server doesn't support range requests
+/// or the estimate fell short, in which case the whole file is needed.
+async fn direct_start(
+    client: &reqwest::Client,
+    url: &str,
+    seconds: f64,
+    throttle: &mut Throttle,
+) -> Result<Option<Vec<u8>>, SourceError> {
+    let Some(mut content) = get_range(client, url, 0, PROBE_BYTES, throttle).await? else {
+        return Ok(None);
+    };
+    if (content.len() as u64) < PROBE_BYTES {
+        // That was the whole file.
+        return Ok(Some(content));
+    }
+    let probe = content.clone();
+    let Ok(bit_rate) = audio::blocking(move || audio::bit_rate(&probe)).await else {
+        return Ok(None);
+    };
+    // A little extra, since the bitrate may vary.
+    let needed = (seconds * bit_rate / 8.0 * 1.1) as u64 + PROBE_BYTES;
+    let have = content.len() as u64;
+    if needed > have {
+        let Some(rest) = get_range(client, url, have, needed, throttle).await? else {
+            return Ok(None);
+        };
+        let complete = (rest.len() as u64) < needed - have;
+        content.extend(rest);
+        if complete {
+            return Ok(Some(content));
+        }
+    }
+    let probe = content.clone();
+    match audio::blocking(move || audio::duration(&probe)).await {
+        Ok(length) if length >= seconds => Ok(Some(content)),
+        _ => Ok(None),
+    }
+}
+
+/// Start downloading a link with a plain HTTP request, giving its body a
+/// chunk at a time as it arrives, e.g. to pass straight on to ffmpeg. If
+/// `max_rate` is given, chunks are held back to keep under it.
+pub async fn direct_stream(
+    url: &str,
+    max_rate: Option<&str>,
+) -> Result<impl Stream<Item = io::Result<Vec<u8>>> + Unpin, SourceError> {
+    let response = reqwest::Client::new().get(url).send().await?.error_for_status()?;
+    let chunks = futures::stream::unfold(
+        (response.bytes_stream(), Throttle::new(max_rate)),
+        |(mut chunks, mut throttle)| async move {
+            let chunk = match chunks.next().await? {
+                Ok(chunk) => chunk,
+                Err(e) => return Some((Err(io::Error::other(e)), (chunks, throttle))),
+            };
+            throttle.received(chunk.len()).await;
+            Some((Ok(chunk.to_vec()), (chunks, throttle)))
+        },
+    );
+    Ok(Box::pin(chunks))
+}
+
+/// Download a link with a plain HTTP request, only fetching the start of it
+/// if that's all that's needed and the server allows. If `max_rate` is
+/// given, the download is limited to that many bytes per second, as with
+/// yt-dlp.
+async fn direct(url: &str, until: Option<f64>, max_rate: Option<&str>) -> Result<Vec<u8>, SourceError> {
+    let client = reqwest::Client::new();
+    let mut throttle = Throttle::new(max_rate);
+    if let Some(seconds) = until {
+        if let Some(content) = direct_start(&client, url, seconds, &mut throttle).await? {
+            return Ok(content);
+        }
+    }
+    let response = client.get(url).send().await?.error_for_status()?;
+    read_body(response, &mut throttle).await
 }
 
-pub fn fetch(item: &SourceItem, method: DownloadMethod) -> Result<Vec<u8>, SourceError> {
-    let link = item.get_audio_link().unwrap();
+/// Strip any analytics prefixes (see TRACKING_PREFIX) from a link, leaving
+/// the URL they wrap. Services are often stacked, so this keeps going until
+/// none are left.
+pub fn strip_tracking_prefixes(url: &str) -> String {
+    let mut url = url.to_string();
+    while let Some(captures) = TRACKING_PREFIX.captures(&url) {
+        let scheme = captures[1].to_string();
+        let rest = &url[captures[0].len()..];
+        url = if rest.starts_with("http://") || rest.starts_with("https://") {
+            rest.to_string()
+        } else {
+            format!("{}://{}", scheme, rest)
+        };
+    }
+    url
+}
+
+/// Find where a link really leads: strip its tracking prefixes, then follow
+/// any redirects from there. If the redirects can't be followed (e.g. the
+/// server doesn't allow HEAD requests), the stripped link is used as it is.
+async fn unwrap_redirects(url: &str) -> String {
+    let stripped = strip_tracking_prefixes(url);
+    match reqwest::Client::new().head(&stripped).send().await {
+        Ok(response) if response.status().is_success() => response.url().to_string(),
+        _ => stripped,
+    }
+}
+
+pub async fn fetch(item: &SourceItem, method: DownloadMethod, options: DownloadOptions<'_>) -> Result<Vec<u8>, SourceError> {
+    let mut link = item.get_audio_link().unwrap();
+    if options.unwrap_redirects {
+        link = unwrap_redirects(&link).await;
+    }
     match method {
-        DownloadMethod::YtDlp => yt_dlp(&link).map_err(SourceError::from),
+        DownloadMethod::YtDlp => yt_dlp(&link, options.max_rate, options.audio_lang).await.map_err(SourceError::from),
+        DownloadMethod::Direct => direct(&link, options.until, options.max_rate).await,
+    }
+}
+
+/// Fill in the placeholders ({source}, {title}, {date}, {guid}, {ext}) of a
+/// download_filename_template for an item.
+fn render_filename(template: &str, source_name: &str, item: &SourceItem, ext: &str) -> String {
+    let date = item
+        .published()
+        .map(|date| date.format("%Y-%m-%d").to_string())
+        .unwrap_or_default();
+    // Each value is made safe on its own first so that, say, a slash in a
+    // title can't turn into a subdirectory.
+    template
+        .replace("{source}", &source::safe_filename(source_name))
+        .replace("{title}", &source::safe_filename(&item.title().unwrap_or_default()))
+        .replace("{date}", &date)
+        .replace("{guid}", &source::safe_filename(&item.guid().unwrap_or_default()))
+        .replace("{ext}", ext)
+}
+
+/// Where save_download would first try to save an item's audio, if there's
+/// a file there already, i.e. the item was downloaded into `dir` before.
+pub fn existing_download(dir: &str, template: &str, source_name: &str, item: &SourceItem) -> Option<PathBuf> {
+    let dir = shellexpand::tilde(dir).to_string();
+    let rendered = render_filename(template, source_name, item, "mp3");
+    let (stem, ext) = rendered.rsplit_once('.').unwrap_or((&rendered, "mp3"));
+    Some(Path::new(&dir).join(source::safe_filename_within(stem, ext))).filter(|path| path.is_file())
+}
+
+/// Save downloaded audio into `dir`, naming it using `template`.
+///
+/// If a file with that name already exists, a counter is appended
+/// ("Title-2.mp3", "Title-3.mp3", ...). Returns the path written to.
+pub fn save_download(
+    dir: &str,
+    template: &str,
+    source_name: &str,
+    item: &SourceItem,
+    content: &[u8],
+) -> io::Result<PathBuf> {
+    let dir = shellexpand::tilde(dir).to_string();
+    std::fs::create_dir_all(&dir)?;
+    let rendered = render_filename(template, source_name, item, "mp3");
+    let (stem, ext) = rendered.rsplit_once('.').unwrap_or((&rendered, "mp3"));
+    let mut path = Path::new(&dir).join(source::safe_filename_within(stem, ext));
+    let mut counter = 2;
+    while path.exists() {
+        let numbered = format!("{}-{}", stem, counter);
+        path = Path::new(&dir).join(source::safe_filename_within(&numbered, ext));
+        counter += 1;
+    }
+    std::fs::write(&path, content)?;
+    Ok(path)
+}
+
+/// Save several related files into `dir`, all named `stem` plus their own
+/// extension (e.g. "Title.mp3" and "Title.txt").
+///
+/// Unless `force` is set, nothing is written if any of the files already
+/// exist. Returns the paths written to.
+pub fn save_bundle(dir: &str, stem: &str, files: &[(&str, &[u8])], force: bool) -> io::Result<Vec<PathBuf>> {
+    let dir = shellexpand::tilde(dir).to_string();
+    std::fs::create_dir_all(&dir)?;
+    let paths: Vec<PathBuf> = files
+        .iter()
+        .map(|(ext, _)| Path::new(&dir).join(source::safe_filename_within(stem, ext)))
+        .collect();
+    if !force {
+        if let Some(existing) = paths.iter().find(|path| path.exists()) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists (use --force to overwrite)", existing.display()),
+            ));
+        }
+    }
+    for (path, (_, content)) in paths.iter().zip(files) {
+        std::fs::write(path, content)?;
     }
+    Ok(paths)
 }
@@ -0,0 +1,22 @@
+//! The pieces lqcli is built from. They live in a library, rather than only
+//! in the binary, so that they can be tested from tests/.
+
+pub mod audio;
+pub mod cache;
+pub mod chapters;
+pub mod config;
+pub mod deepgram;
+pub mod fetch;
+pub mod openai;
+pub mod opml;
+pub mod lingq;
+pub mod local_whisper;
+pub mod metrics;
+pub mod postprocess;
+pub mod report;
+pub mod runlog;
+pub mod source;
+pub mod state;
+pub mod subtitles;
+pub mod transcribe;
+pub mod transcript;
@@ -1,25 +1,288 @@
 //! Provides an interface to the LingQ API (or at least the parts we need).
 
 use crate::config;
+use crate::source::decode_title;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime};
 use reqwest::{Client, header};
 use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+/// How many times to look for a freshly imported lesson before deciding it
+/// never materialized.
+const VERIFY_ATTEMPTS: usize = 3;
+
+/// What LingQ calls a lesson's status while it's still importing it.
+const PENDING_STATUSES: &[&str] = &["pending", "processing", "queued", "importing"];
+
+/// Where the LingQ API is, unless lingq.api_base says otherwise.
+pub const DEFAULT_API_BASE: &str = "https://www.lingq.com/api";
+
+/// The course ID meaning "no course": lessons are imported without a
+/// collection, and LingQ files them wherever it puts loose imports.
+pub const NO_COURSE: u64 = 0;
+
+/// Which version of the LingQ API to import lessons with.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ApiVersion {
+    V2,
+    V3,
+}
+
+/// The multipart field names a version of the lesson import endpoint expects.
+///
+/// If LingQ renames a field, this is the only place that should need to
+/// change.
+struct ImportFields {
+    title: &'static str,
+    collection: &'static str,
+    text: &'static str,
+    audio: &'static str,
+    description: &'static str,
+    level: &'static str,
+    /// Whether to save the lesson right away, rather than leave it as a draft.
+    save: Option<&'static str>,
+}
+
+const V2_IMPORT_FIELDS: ImportFields = ImportFields {
+    title: "title",
+    collection: "collection",
+    text: "text",
+    audio: "audio",
+    description: "description",
+    level: "level",
+    save: None,
+};
+
+const V3_IMPORT_FIELDS: ImportFields = ImportFields {
+    title: "title",
+    collection: "collection",
+    text: "text",
+    audio: "audio",
+    description: "description",
+    level: "level",
+    save: Some("save"),
+};
+
+/// LingQ's lesson levels, from 1 (Beginner 1) to 6 (Advanced 2).
+pub const LEVELS: std::ops::RangeInclusive<u8> = 1..=6;
+
+/// A lesson to import into LingQ.
+pub struct LessonImport<'a> {
+    /// The course to import into, or NO_COURSE.
+    pub course_id: u64,
+    pub title: &'a str,
+    pub text: &'a str,
+    pub description: Option<&'a str>,
+    /// One of LEVELS, or None to leave it to LingQ.
+    pub level: Option<u8>,
+    /// Extra import fields (see a source's lingq_fields).
+    pub extra_fields: &'a HashMap<String, String>,
+}
+
+/// Whether `name` can be sent as an extra import field (see a source's
+/// lingq_fields): a plain field name which isn't one lqcli already fills in
+/// itself.
+pub fn is_valid_extra_field(name: &str) -> bool {
+    let is_plain = !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    let is_own = [&V2_IMPORT_FIELDS, &V3_IMPORT_FIELDS].iter().any(|fields| {
+        [fields.title, fields.collection, fields.text, fields.audio, fields.description, fields.level]
+            .contains(&name)
+            || fields.save == Some(name)
+    });
+    is_plain && !is_own
+}
+
+/// Check that `language` looks like a LingQ language code, i.e. two
+/// letters like "de", so a lesson isn't posted to some other language's
+/// endpoint (or a nonexistent one).
+pub fn check_language(language: &str) -> Result<(), String> {
+    if language.len() == 2 && language.chars().all(|c| c.is_ascii_lowercase()) {
+        Ok(())
+    } else {
+        Err(format!("\"{}\" isn't a LingQ language code (two lowercase letters, like \"de\")", language))
+    }
+}
+
+/// Sort lessons oldest first: by when LingQ says they were created, then by
+/// ID, since LingQ hands those out in order. Lessons it says neither about
+/// count as the oldest.
+pub fn sort_by_creation(lessons: &mut [LingqLesson]) {
+    lessons.sort_by_key(|lesson| (lesson.created_at(), lesson.id));
+}
+
+impl ApiVersion {
+    fn import_url(&self, api_base: &str, language: &str) -> String {
+        match self {
+            ApiVersion::V2 => format!("{}/v2/{}/lessons/", api_base, language),
+            ApiVersion::V3 => format!("{}/v3/{}/lessons/import/", api_base, language),
+        }
+    }
+
+    fn lesson_url(&self, api_base: &str, language: &str, lesson_id: u64) -> String {
+        match self {
+            ApiVersion::V2 => format!("{}/v2/{}/lessons/{}/", api_base, language, lesson_id),
+            ApiVersion::V3 => format!("{}/v3/{}/lessons/{}/", api_base, language, lesson_id),
+        }
+    }
+
+    fn import_fields(&self) -> &'static ImportFields {
+        match self {
+            ApiVersion::V2 => &V2_IMPORT_FIELDS,
+            ApiVersion::V3 => &V3_IMPORT_FIELDS,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct LingqClient {
     client: Client,
+
+    /// Base URL of the API, without a trailing slash.
+    api_base: String,
+
+    /// Which API version to import lessons with.
+    api_version: ApiVersion,
+
+    /// Minimum time between two requests to the LingQ API.
+    request_delay: Duration,
+
+    /// How long to wait for an import LingQ is still processing.
+    import_timeout: Duration,
+
+    /// When the most recent request was (or is scheduled to be) sent. This is
+    /// shared between clones so that per-source clients with a different
+    /// delay still space themselves out against each other.
+    last_request: Arc<Mutex<Option<Instant>>>,
+
+    /// Whether LingQ accepted the API key, once we've asked (or why we
+    /// couldn't find out). Shared between clones, so it's asked at most once
+    /// per run.
+    key_check: Arc<tokio::sync::OnceCell<Result<bool, String>>>,
 }
 
+/// A page of a course's lessons. Big courses come a page at a time, each
+/// linking to the `next`; pages of the lessons list call them "results".
 #[derive(Debug, Deserialize)]
-pub struct LingqCourse {
-    pub pk: u64,
-    pub url: String,
-    pub title: String,
+pub struct LessonPage {
+    #[serde(alias = "results")]
     pub lessons: Vec<LingqLesson>,
+    #[serde(default)]
+    pub next: Option<String>,
+}
+
+/// What LingQ tells us about a lesson it just created. All the fields are
+/// optional since we only need them for reporting and to tell whether the
+/// import is done.
+#[derive(Debug, Default, Deserialize)]
+pub struct CreatedLesson {
+    pub id: Option<u64>,
+    pub url: Option<String>,
+    /// Where LingQ is with the import, if it says.
+    #[serde(default)]
+    pub status: Option<String>,
+}
+
+impl CreatedLesson {
+    /// Whether LingQ is still working on the import, so the lesson may not
+    /// be there yet.
+    pub fn is_pending(&self) -> bool {
+        self.status
+            .as_deref()
+            .is_some_and(|status| PENDING_STATUSES.contains(&status.to_ascii_lowercase().as_str()))
+    }
+}
+
+/// Why a lesson couldn't be imported.
+#[derive(Debug)]
+pub enum LingqError {
+    /// The lesson's language isn't a LingQ language code.
+    Language(String),
+    /// The audio to upload couldn't be read.
+    Audio(io::Error),
+    /// The request failed, or LingQ turned it down.
+    Request(reqwest::Error),
+    /// LingQ was still processing the lesson with this ID when we gave up
+    /// waiting, after this many seconds.
+    Timeout(u64, u64),
+}
+
+impl LingqError {
+    /// A short, stable name for the kind of error, for machine-readable
+    /// output.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            LingqError::Language(_) => "config",
+            LingqError::Audio(_) => "io",
+            LingqError::Request(_) => "lingq",
+            LingqError::Timeout(..) => "lingq-timeout",
+        }
+    }
+}
+
+impl From<reqwest::Error> for LingqError {
+    fn from(err: reqwest::Error) -> Self {
+        LingqError::Request(err)
+    }
+}
+
+impl Display for LingqError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LingqError::Language(msg) => write!(f, "{}", msg),
+            LingqError::Audio(err) => write!(f, "Error reading the audio to upload: {}", err),
+            LingqError::Request(err) => write!(f, "{}", err),
+            LingqError::Timeout(id, seconds) => {
+                write!(f, "LingQ was still processing lesson {} after {}s", id, seconds)
+            }
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct LingqLesson {
+    #[serde(default)]
+    pub id: Option<u64>,
     pub title: String,
     pub url: String,
+    /// When the lesson was created, as LingQ gives it (a date, or a date
+    /// and time). Not every listing has it.
+    #[serde(default, alias = "pubDate", alias = "creationDate")]
+    pub created: Option<String>,
+}
+
+impl LingqLesson {
+    /// When the lesson was created, if LingQ said in a form we understand.
+    pub fn created_at(&self) -> Option<NaiveDateTime> {
+        let created = self.created.as_deref()?.trim();
+        DateTime::parse_from_rfc3339(created)
+            .map(|date| date.naive_utc())
+            .or_else(|_| NaiveDateTime::parse_from_str(created, "%Y-%m-%dT%H:%M:%S%.f"))
+            .or_else(|_| NaiveDate::parse_from_str(created, "%Y-%m-%d").map(|date| date.and_time(NaiveTime::MIN)))
+            .ok()
+    }
+
+    /// Whether this is the lesson that was just created with `title`: the
+    /// same lesson ID if LingQ gave us both, otherwise the same title once
+    /// LingQ's HTML encoding of it is undone.
+    pub fn is(&self, created: &CreatedLesson, title: &str) -> bool {
+        match (self.id, created.id) {
+            (Some(id), Some(created_id)) => id == created_id,
+            _ => decode_title(&self.title) == decode_title(title),
+        }
+    }
+}
+
+/// An existing lesson's title and text.
+#[derive(Debug, Deserialize)]
+pub struct LessonText {
+    pub title: String,
+    pub text: String,
 }
 
 impl LingqClient {
@@ -31,31 +294,216 @@ impl LingqClient {
             .default_headers(headers)
             .build()
             .unwrap();
-        Self { client }
+        let api_base = lingq_config.api_base.as_deref().unwrap_or(DEFAULT_API_BASE);
+        Self {
+            client,
+            api_base: api_base.trim_end_matches('/').to_string(),
+            api_version: lingq_config.api_version,
+            request_delay: Duration::from_secs(lingq_config.request_delay),
+            import_timeout: Duration::from_secs(lingq_config.import_timeout),
+            last_request: Arc::new(Mutex::new(None)),
+            key_check: Arc::new(tokio::sync::OnceCell::new()),
+        }
     }
 
+    /// Check that LingQ accepts our API key, with a cheap request for the
+    /// languages the account studies. Returns Ok(false) if the key is invalid
+    /// or expired, or an error if LingQ couldn't be asked at all.
+    pub async fn check_api_key(&self) -> Result<bool, String> {
+        self.key_check
+            .get_or_init(|| async {
+                self.throttle().await;
+                let response = self
+                    .client
+                    .get(format!("{}/v2/contexts/", self.api_base))
+                    .send()
+                    .await
+                    .map_err(|e| e.to_string())?;
+                match response.status() {
+                    reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN => Ok(false),
+                    _ => response.error_for_status().map(|_| true).map_err(|e| e.to_string()),
+                }
+            })
+            .await
+            .clone()
+    }
+
+    /// Returns a client which shares this one's connection pool and request
+    /// history, but waits `seconds` between requests instead.
+    ///
+    /// Used for sources that override lingq.request_delay.
+    pub fn with_request_delay(&self, seconds: u64) -> Self {
+        Self {
+            request_delay: Duration::from_secs(seconds),
+            ..self.clone()
+        }
+    }
+
+    /// Wait until at least request_delay has passed since the last request.
+    async fn throttle(&self) {
+        let wait = {
+            let mut last_request = self.last_request.lock().unwrap();
+            let now = Instant::now();
+            let next = match *last_request {
+                Some(last) => (last + self.request_delay).max(now),
+                None => now,
+            };
+            *last_request = Some(next);
+            next - now
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// The titles of the lessons in a course, from every page of them.
+    /// Without a course (NO_COURSE) there is nothing to list, so this is
+    /// always empty.
     pub async fn get_lesson_titles(&self, language: &str, course_id: u64) -> Result<Vec<String>, reqwest::Error> {
-        let url = format!("https://www.lingq.com/api/v2/{}/collections/{}/", language, course_id);
+        let lessons = self.get_lessons(language, course_id).await?;
+        Ok(lessons.into_iter().map(|lesson| lesson.title).collect())
+    }
+
+    /// The lessons in a course, from every page of them (see
+    /// get_lesson_titles), in the order LingQ lists them.
+    pub async fn get_lessons(&self, language: &str, course_id: u64) -> Result<Vec<LingqLesson>, reqwest::Error> {
+        if course_id == NO_COURSE {
+            return Ok(vec![]);
+        }
+        let mut lessons = vec![];
+        let mut url = Some(format!("{}/v2/{}/collections/{}/", self.api_base, language, course_id));
+        while let Some(page_url) = url {
+            self.throttle().await;
+            let response = self.client.get(&page_url).send().await?;
+            response.error_for_status_ref()?;
+            let page: LessonPage = response.json().await?;
+            lessons.extend(page.lessons);
+            // A page which links to itself would never end.
+            url = page.next.filter(|next| *next != page_url);
+        }
+        Ok(lessons)
+    }
+
+    /// Get an existing lesson's title and text.
+    pub async fn get_lesson_text(&self, language: &str, lesson_id: u64) -> Result<LessonText, LingqError> {
+        let url = format!("{}/v2/{}/lessons/{}/", self.api_base, language, lesson_id);
+        self.throttle().await;
         let response = self.client.get(&url).send().await?;
         response.error_for_status_ref()?;
-        let json: LingqCourse = response.json().await?;
-        let lessons = json.lessons;
-        let titles: Vec<String> = lessons.into_iter().map(|lesson| lesson.title).collect();
-        Ok(titles)
+        Ok(response.json().await?)
+    }
+
+    /// Replace an existing lesson's text, leaving everything else about it
+    /// (audio, course, title) alone.
+    pub async fn update_lesson_text(&self, language: &str, lesson_id: u64, text: &str) -> Result<(), LingqError> {
+        let url = format!("{}/v2/{}/lessons/{}/", self.api_base, language, lesson_id);
+        self.throttle().await;
+        let response = self
+            .client
+            .patch(&url)
+            .json(&serde_json::json!({ "text": text }))
+            .send()
+            .await?;
+        response.error_for_status()?;
+        Ok(())
+    }
+
+    /// Check whether a lesson just created (see LingqLesson::is) exists in
+    /// the course.
+    ///
+    /// LingQ doesn't always show a new lesson immediately, so this asks up to
+    /// VERIFY_ATTEMPTS times (each respecting request_delay) before giving up.
+    pub async fn verify_lesson(
+        &self,
+        language: &str,
+        course_id: u64,
+        created: &CreatedLesson,
+        title: &str,
+    ) -> Result<bool, reqwest::Error> {
+        for _ in 0..VERIFY_ATTEMPTS {
+            let lessons = self.get_lessons(language, course_id).await?;
+            if lessons.iter().any(|lesson| lesson.is(created, title)) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
     }
 
-    pub async fn create_lesson(&self, course_id: u64, title: &str, text: &str, mp3: Option<Vec<u8>>) -> Result<(), reqwest::Error> {
-        let url = "https://www.lingq.com/api/v3/de/lessons/import/";
+    /// Import a lesson in `language`, which must pass check_language, with
+    /// the audio from the MP3 file at `mp3` if there is one.
+    pub async fn create_lesson(
+        &self,
+        language: &str,
+        lesson: &LessonImport<'_>,
+        mp3: Option<&Path>,
+    ) -> Result<CreatedLesson, LingqError> {
+        check_language(language).map_err(LingqError::Language)?;
+        let url = self.api_version.import_url(&self.api_base, language);
+        let fields = self.api_version.import_fields();
         let mut form = reqwest::multipart::Form::new()
-            .text("title", title.to_string())
-            .text("collection", course_id.to_string())
-            .text("save", "true".to_string())
-            .text("text", text.to_string());
-        if let Some(mp3) = mp3 {
-            form = form.part("audio", reqwest::multipart::Part::bytes(mp3).file_name("audio.mp3"));
-        }
-        let response = self.client.post(url).multipart(form).send().await?;
+            .text(fields.title, lesson.title.to_string())
+            .text(fields.text, lesson.text.to_string());
+        if lesson.course_id != NO_COURSE {
+            form = form.text(fields.collection, lesson.course_id.to_string());
+        }
+        if let Some(description) = lesson.description {
+            form = form.text(fields.description, description.to_string());
+        }
+        if let Some(level) = lesson.level {
+            form = form.text(fields.level, level.to_string());
+        }
+        if let Some(save) = fields.save {
+            form = form.text(save, "true".to_string());
+        }
+        for (name, value) in lesson.extra_fields {
+            form = form.text(name.clone(), value.clone());
+        }
+        if let Some(path) = mp3 {
+            let mp3 = tokio::fs::File::open(path).await.map_err(LingqError::Audio)?;
+            // Stream the audio from disk rather than holding it all in
+            // memory, giving the length up front when we can so the upload
+            // isn't chunked.
+            let length = mp3.metadata().await.ok().map(|metadata| metadata.len());
+            let body = reqwest::Body::from(mp3);
+            let part = match length {
+                Some(length) => reqwest::multipart::Part::stream_with_length(body, length),
+                None => reqwest::multipart::Part::stream(body),
+            };
+            form = form.part(fields.audio, part.file_name("audio.mp3"));
+        }
+        self.throttle().await;
+        let response = self.client.post(&url).multipart(form).send().await?;
         response.error_for_status_ref()?;
-        Ok(())
+        let body = response.text().await?;
+        let created: CreatedLesson = serde_json::from_str(&body).unwrap_or_default();
+        self.wait_for_import(language, created).await
+    }
+
+    /// Wait for LingQ to finish an import it's still processing, checking
+    /// on the lesson every request_delay, and return the finished lesson.
+    /// It's an error if it isn't done within import_timeout.
+    async fn wait_for_import(&self, language: &str, mut created: CreatedLesson) -> Result<CreatedLesson, LingqError> {
+        let started = Instant::now();
+        while created.is_pending() {
+            // Without an ID there's nothing to check on.
+            let Some(id) = created.id else {
+                break;
+            };
+            if started.elapsed() >= self.import_timeout {
+                return Err(LingqError::Timeout(id, self.import_timeout.as_secs()));
+            }
+            self.throttle().await;
+            let url = self.api_version.lesson_url(&self.api_base, language, id);
+            let response = self.client.get(&url).send().await?;
+            response.error_for_status_ref()?;
+            let lesson: CreatedLesson = response.json().await?;
+            // The lesson itself may not repeat everything the import said.
+            created = CreatedLesson {
+                id: lesson.id.or(created.id),
+                url: lesson.url.or(created.url),
+                status: lesson.status,
+            };
+        }
+        Ok(created)
     }
 }
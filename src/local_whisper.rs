@@ -0,0 +1,294 @@
+//! Transcription on this machine with whisper.cpp, as an alternative to
+//! the Whisper API that doesn't cost anything per hour of audio.
+//!
+//! By default whisper.cpp's command-line program is run for each
+//! transcript. Built with the `whisper-rs` feature, lqcli loads the model
+//! and runs whisper.cpp itself through whisper-rs instead, which needs a C++
+//! compiler and cmake to build but no separate whisper.cpp install.
+
+use crate::audio;
+use crate::config;
+use crate::openai::{whisper_language, ApiError};
+use crate::transcribe::Transcriber;
+use futures::{Stream, StreamExt};
+use std::io;
+use std::path::Path;
+use std::process::{Output, Stdio};
+use tokio::io::AsyncWriteExt;
+use tokio::process::{Child, ChildStdin, Command};
+#[cfg(feature = "whisper-rs")]
+use {
+    std::sync::Arc,
+    tokio::sync::OnceCell,
+    whisper_rs::{FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters},
+};
+
+pub struct LocalWhisper {
+    config: config::LocalWhisperConfig,
+    /// The model, once it's been loaded.
+    #[cfg(feature = "whisper-rs")]
+    model: OnceCell<Arc<WhisperContext>>,
+}
+
+impl LocalWhisper {
+    pub fn new(config: config::LocalWhisperConfig) -> Self {
+        Self {
+            config,
+            #[cfg(feature = "whisper-rs")]
+            model: OnceCell::new(),
+        }
+    }
+
+    /// Transcribe audio as it arrives, e.g. straight from a download,
+    /// returning the whole of the audio along with its transcript.
+    ///
+    /// The audio is piped through ffmpeg into whisper.cpp as it comes, so
+    /// nothing is written to disk. If that fails (whisper.cpp builds from
+    /// before it could read standard input can't take audio this way), the
+    /// audio is transcribed from a file as usual once it's all arrived.
+    pub async fn transcribe_stream(
+        &self,
+        audio: impl Stream<Item = io::Result<Vec<u8>>> + Unpin,
+        language: &str,
+    ) -> io::Result<(Vec<u8>, String)> {
+        let (audio, piped) = self.pipe(audio, language).await?;
+        let transcript = match piped {
+            Ok(transcript) => transcript,
+            Err(e) => {
+                eprintln!("Couldn't pipe the audio into whisper.cpp ({}), transcribing it from a file", e);
+                let wav = {
+                    let audio = audio.clone();
+                    audio::blocking(move || audio::to_wav(&audio)).await?
+                };
+                self.transcribe_file(wav.path(), language).await?
+            }
+        };
+        Ok((audio, transcript))
+    }
+}
+
+#[cfg(not(feature = "whisper-rs"))]
+impl LocalWhisper {
+    /// The whisper.cpp command to transcribe `input` (a path, or "-" for
+    /// standard input) in the given language, printing just the text.
+    fn command(&self, input: &str, language: &str) -> Command {
+        let model = shellexpand::tilde(&self.config.model).to_string();
+        // whisper.cpp wants a two-letter code, or to detect the language.
+        let language = whisper_language(language).unwrap_or_else(|| "auto".to_string());
+        let mut command = Command::new(&self.config.command);
+        command
+            .arg("--model")
+            .arg(model)
+            .arg("--language")
+            .arg(language)
+            .args(["--no-timestamps", "--no-prints"])
+            .arg("--file")
+            .arg(input);
+        if let Some(threads) = self.config.threads {
+            command.arg("--threads").arg(threads.to_string());
+        }
+        command
+    }
+
+    /// Transcribe a 16 kHz WAV file (see audio::to_wav).
+    pub async fn transcribe_file(&self, wav: &Path, language: &str) -> io::Result<String> {
+        let output = self.command(&wav.to_string_lossy(), language).output().await?;
+        self.transcript(output)
+    }
+
+    /// Feed audio through ffmpeg into whisper.cpp, returning the audio and,
+    /// separately, whether it could be transcribed that way. Only failing to
+    /// get the audio at all is an error.
+    async fn pipe(
+        &self,
+        mut audio: impl Stream<Item = io::Result<Vec<u8>>> + Unpin,
+        language: &str,
+    ) -> io::Result<(Vec<u8>, io::Result<String>)> {
+        let (mut ffmpeg, whisper) = match spawn_pipeline(self.command("-", language)) {
+            Ok(children) => children,
+            Err(e) => return Ok((receive(audio, None).await?, Err(e))),
+        };
+        let input = ffmpeg.stdin.take();
+        let (received, converted, transcribed) =
+            tokio::join!(receive(&mut audio, input), ffmpeg.wait_with_output(), whisper.wait_with_output());
+        let transcript = converted_wav(converted).and_then(|_| transcribed.and_then(|output| self.transcript(output)));
+        Ok((received?, transcript))
+    }
+
+    /// The transcript whisper.cpp printed, or why it didn't.
+    fn transcript(&self, output: Output) -> io::Result<String> {
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "{} failed: {}",
+                self.config.command,
+                String::from_utf8_lossy(&output.stderr)
+            )));
+        }
+        // Some builds cut characters in half between segments; rather than
+        // import a transcript with holes in it, say so.
+        let text = String::from_utf8(output.stdout).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} printed a transcript which isn't UTF-8: {}", self.config.command, e),
+            )
+        })?;
+        Ok(transcript_text(&text))
+    }
+}
+
+#[cfg(feature = "whisper-rs")]
+impl LocalWhisper {
+    /// The model, loaded (off the async runtime, since it can take a while)
+    /// the first time it's needed.
+    async fn model(&self) -> io::Result<Arc<WhisperContext>> {
+        let model = self
+            .model
+            .get_or_try_init(|| {
+                let path = shellexpand::tilde(&self.config.model).to_string();
+                audio::blocking(move || {
+                    // Otherwise whisper.cpp logs every step to stderr.
+                    whisper_rs::install_logging_hooks();
+                    WhisperContext::new_with_params(&path, WhisperContextParameters::default())
+                        .map(Arc::new)
+                        .map_err(|e| io::Error::other(format!("couldn't load the model {}: {}", path, e)))
+                })
+            })
+            .await?;
+        Ok(model.clone())
+    }
+
+    /// Transcribe a 16 kHz WAV file (see audio::to_wav).
+    pub async fn transcribe_file(&self, wav: &Path, language: &str) -> io::Result<String> {
+        let wav = tokio::fs::read(wav).await?;
+        self.transcribe_wav(wav, language).await
+    }
+
+    /// Transcribe 16 kHz WAV audio with the model.
+    async fn transcribe_wav(&self, wav: Vec<u8>, language: &str) -> io::Result<String> {
+        let model = self.model().await?;
+        // whisper.cpp wants a two-letter code, or to detect the language.
+        let language = whisper_language(language);
+        let threads = self.config.threads;
+        audio::blocking(move || {
+            let samples = audio::wav_samples(&wav)?;
+            let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+            params.set_language(Some(language.as_deref().unwrap_or("auto")));
+            if let Some(threads) = threads {
+                params.set_n_threads(threads as i32);
+            }
+            params.set_print_progress(false);
+            params.set_print_realtime(false);
+            params.set_print_timestamps(false);
+            let mut state = model.create_state().map_err(io::Error::other)?;
+            state.full(params, &samples).map_err(io::Error::other)?;
+            let segments = state.full_n_segments().map_err(io::Error::other)?;
+            // Segments which cut a character in half aren't UTF-8; rather
+            // than import a transcript with holes in it, say so.
+            let lines = (0..segments)
+                .map(|segment| state.full_get_segment_text(segment))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("whisper.cpp made a transcript which isn't UTF-8: {}", e)))?;
+            Ok(transcript_text(&lines.join("\n")))
+        })
+        .await
+    }
+
+    /// Feed audio through ffmpeg and transcribe the WAV it makes, returning
+    /// the audio and, separately, whether it could be transcribed that way.
+    /// Only failing to get the audio at all is an error.
+    async fn pipe(
+        &self,
+        mut audio: impl Stream<Item = io::Result<Vec<u8>>> + Unpin,
+        language: &str,
+    ) -> io::Result<(Vec<u8>, io::Result<String>)> {
+        let mut ffmpeg = match spawn_ffmpeg() {
+            Ok(ffmpeg) => ffmpeg,
+            Err(e) => return Ok((receive(audio, None).await?, Err(e))),
+        };
+        let input = ffmpeg.stdin.take();
+        let (received, converted) = tokio::join!(receive(&mut audio, input), ffmpeg.wait_with_output());
+        let received = received?;
+        let transcript = match converted_wav(converted) {
+            Ok(wav) => self.transcribe_wav(wav, language).await,
+            Err(e) => Err(e),
+        };
+        Ok((received, transcript))
+    }
+}
+
+/// Collect all of the audio, writing it to `input` (ffmpeg's) as it comes.
+/// Closing the input once it's all written lets ffmpeg finish.
+async fn receive(
+    mut audio: impl Stream<Item = io::Result<Vec<u8>>> + Unpin,
+    mut input: Option<ChildStdin>,
+) -> io::Result<Vec<u8>> {
+    let mut received = vec![];
+    while let Some(chunk) = audio.next().await {
+        let chunk = chunk?;
+        // If ffmpeg has given up, keep the rest of the audio anyway for
+        // transcribing from a file.
+        if let Some(pipe) = input.as_mut() {
+            if pipe.write_all(&chunk).await.is_err() {
+                input = None;
+            }
+        }
+        received.extend(chunk);
+    }
+    Ok(received)
+}
+
+/// The WAV ffmpeg made, or why it didn't.
+fn converted_wav(converted: io::Result<Output>) -> io::Result<Vec<u8>> {
+    let output = converted?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!("ffmpeg failed: {}", String::from_utf8_lossy(&output.stderr))));
+    }
+    Ok(output.stdout)
+}
+
+/// Start ffmpeg turning whatever is written to its input into 16 kHz WAV on
+/// its output.
+fn spawn_ffmpeg() -> io::Result<Child> {
+    Command::new("ffmpeg")
+        .args(["-v", "error", "-i", "pipe:0", "-ar", "16000", "-ac", "1", "-c:a", "pcm_s16le", "-f", "wav", "pipe:1"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+}
+
+/// Start ffmpeg (see spawn_ffmpeg) with its WAV piped straight into
+/// `whisper` (which reads its audio from standard input).
+#[cfg(not(feature = "whisper-rs"))]
+fn spawn_pipeline(mut whisper: Command) -> io::Result<(Child, Child)> {
+    let mut ffmpeg = spawn_ffmpeg()?;
+    let wav: Stdio = ffmpeg.stdout.take().ok_or_else(|| io::Error::other("ffmpeg has no output"))?.try_into()?;
+    let whisper = whisper.stdin(wav).stdout(Stdio::piped()).stderr(Stdio::piped()).kill_on_drop(true).spawn()?;
+    Ok((ffmpeg, whisper))
+}
+
+/// whisper.cpp prints each segment of the transcript on its own line;
+/// join them back up into running text.
+fn transcript_text(output: &str) -> String {
+    output
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl Transcriber for LocalWhisper {
+    async fn transcribe(&self, audio: Vec<u8>, language: &str) -> Result<String, ApiError> {
+        let wav = audio::blocking(move || audio::to_wav(&audio)).await.map_err(ApiError::Local)?;
+        let transcript = self.transcribe_file(wav.path(), language).await.map_err(ApiError::Local)?;
+        Some(transcript).filter(|text| !text.is_empty()).ok_or(ApiError::Empty)
+    }
+
+    fn name(&self) -> String {
+        // Transcripts depend on the model, wherever it's kept.
+        let model = Path::new(&self.config.model).file_stem().map(|stem| stem.to_string_lossy().into_owned());
+        format!("whisper.cpp/{}", model.unwrap_or_else(|| self.config.model.clone()))
+    }
+}
@@ -1,14 +1,16 @@
-mod config;
-mod fetch;
-mod openai;
-mod lingq;
-mod source;
+use lqcli::{audio, cache, chapters, config, deepgram, fetch, lingq, local_whisper, metrics, openai, opml, postprocess, report, runlog, source, state, subtitles, transcribe, transcript};
 
 use clap::{
     builder::styling::{AnsiColor, Effects, Styles},
-    Args, Parser, Subcommand,
+    Args, Parser, Subcommand, ValueEnum,
 };
+use futures::StreamExt;
+use postprocess::Postprocessor;
+use transcribe::Transcriber;
 use serde::Deserialize;
+use serde::Serialize;
+use report::fatal;
+use std::collections::HashMap;
 use tabled::{
     settings::{
         style::HorizontalLine,
@@ -17,6 +19,7 @@ use tabled::{
         Style,
     },
     Table,
+    Tabled,
 };
 
 fn styles() -> Styles {
@@ -27,15 +30,63 @@ fn styles() -> Styles {
         .placeholder(AnsiColor::Green.on_default())
 }
 
+/// One row of `sources audio-links` output.
+#[derive(Serialize, Tabled)]
+struct AudioLinkRow {
+    source: String,
+    /// The source's latest item, or None if its items couldn't be had.
+    #[tabled(display_with = "display_or_none")]
+    title: Option<String>,
+    /// The audio link found in the latest item, if any.
+    #[tabled(display_with = "display_or_none")]
+    audio_link: Option<String>,
+}
+
+fn display_or_none(value: &Option<String>) -> String {
+    value.clone().unwrap_or("NONE".to_string())
+}
+
+/// One row of `sources sync --dry-run --explain` output.
+#[derive(Tabled)]
+struct ItemDecision {
+    source: String,
+    title: String,
+    decision: String,
+}
+
 #[derive(Parser, Debug)]
 #[command(version, styles = styles())]
 /// Command-line interface to import content into language-learning platforms
 /// such as LingQ.
 struct Cli {
-    /// Path to the configuration file to create or read from
+    /// Path to the configuration file to create or read from. This can also
+    /// be an http(s) URL, in which case it is read-only.
     #[arg(short, long, default_value = "~/.lqcli.toml")]
     config_file: String,
 
+    /// Apply the overrides in this [profiles.<name>] section of the
+    /// configuration file
+    #[arg(short, long)]
+    profile: Option<String>,
+
+    /// Report fatal errors as a JSON object on stderr, e.g. {"error":
+    /// "...", "kind": "config", "source": null}, for scripts
+    #[arg(long, global = true)]
+    json_errors: bool,
+
+    /// Treat warnings as errors, stopping with a non-zero exit status at the
+    /// first one. That covers: problems found in the configuration file,
+    /// feed items skipped for having no audio or no title (or for not
+    /// parsing at all), feeds which only parse after sanitizing
+    /// (lenient_parsing) or have permanently moved, warnings printed by
+    /// yt-dlp, failing to check the LingQ API key, failing to write to the
+    /// transcript cache and importing a raw transcript because
+    /// post-processing failed (on_postprocess_failure = "import-raw"). A
+    /// sync also exits with an error once it's done if any item couldn't be
+    /// downloaded, transcribed or imported
+    #[arg(long, global = true)]
+    strict: bool,
+
     /// The category of action to perform
     #[command(subcommand)]
     subcommand: MainSubcommand,
@@ -50,8 +101,97 @@ enum MainSubcommand {
     /// Transcribe a single piece of content
     Transcribe(TranscribeSubcommand),
 
+    /// Transcribe the latest items of a feed into text files, without
+    /// importing anything
+    TranscribeFeed(TranscribeFeedSubcommand),
+
     /// Import a single piece of content
     Adhoc(AdhocSubcommand),
+
+    /// Post-process a transcript with both the configured prompt and a
+    /// candidate prompt, and show how the results differ
+    DiffPrompt(DiffPromptSubcommand),
+
+    /// Post-process lessons already in LingQ again with the current prompt,
+    /// updating their text in place (their audio isn't needed)
+    Reprocess(ReprocessSubcommand),
+
+    /// Inspect the configuration
+    #[command(subcommand)]
+    Config(ConfigSubcommand),
+
+    /// Write a starter configuration file (to --config-file) to fill in
+    Init {
+        /// Overwrite the configuration file if it already exists
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum ConfigSubcommand {
+    /// Show a summary of the configuration in use (without any secrets)
+    Show,
+}
+
+/// Which part of some content to use, for commands handling a single item.
+#[derive(Args, Debug)]
+struct TimeRange {
+    /// Only use the content from this point on, as seconds, M:SS or
+    /// H:MM:SS. The audio is clipped with ffmpeg before transcription.
+    #[arg(long, value_parser = audio::parse_time)]
+    start: Option<f64>,
+    /// Only use the content up to this point, as seconds, M:SS or H:MM:SS
+    #[arg(long, value_parser = audio::parse_time)]
+    end: Option<f64>,
+}
+
+/// Picking sources by name, alongside (or instead of) by tag.
+#[derive(Args, Debug)]
+struct NameFilter {
+    /// Only use sources whose names contain this (ignoring case)
+    #[arg(long)]
+    name: Option<String>,
+    /// Treat --name as a regular expression instead
+    #[arg(long, requires = "name")]
+    regex: bool,
+}
+
+impl NameFilter {
+    /// The pattern given, if any, exiting if it's an invalid regex.
+    fn pattern(&self) -> Option<config::NamePattern> {
+        let name = self.name.clone()?;
+        if !self.regex {
+            return Some(config::NamePattern::Substring(name));
+        }
+        match regex::Regex::new(&name) {
+            Ok(regex) => Some(config::NamePattern::Regex(regex)),
+            Err(e) => {
+                fatal("usage", None, format!("Invalid --name pattern: {}", e));
+            }
+        }
+    }
+}
+
+impl TimeRange {
+    /// Whether no range was given, so all of the content is used.
+    fn is_whole(&self) -> bool {
+        self.start.is_none() && self.end.is_none()
+    }
+
+    /// Clip audio to the range, if one was given, exiting if that fails.
+    async fn clip(&self, audio: Vec<u8>) -> Vec<u8> {
+        if self.is_whole() {
+            return audio;
+        }
+        let (start, end) = (self.start, self.end);
+        match audio::blocking(move || audio::clip(&audio, start, end)).await {
+            Ok(clipped) => clipped,
+            Err(e) => {
+                fatal("audio", None, format!("Error clipping audio: {}", e));
+            }
+        }
+    }
 }
 
 #[derive(Args, Debug)]
@@ -63,6 +203,54 @@ struct TranscribeSubcommand {
     /// How to download the content. Usually the default of "yt-dlp" is fine.
     #[arg(long, short = 'm', default_value = "yt-dlp")]
     download_method: fetch::DownloadMethod,
+    /// After transcribing and post-processing, also import the result (and
+    /// the audio) into LingQ, like the adhoc command does. The language given
+    /// above is used for the import as well.
+    #[arg(long, requires_all = ["course_id", "name"])]
+    import: bool,
+    /// The course ID to import the content into, or 0 for no course. Only
+    /// used with --import.
+    #[arg(long, requires = "import")]
+    course_id: Option<u64>,
+    /// The title of the lesson to create. Only used with --import.
+    #[arg(long, requires = "import")]
+    name: Option<String>,
+    /// Also save the audio, the raw transcript and the post-processed
+    /// transcript into this directory, as <name>.mp3, <name>.raw.txt and
+    /// <name>.txt. The name is --name if given, or else taken from the URL.
+    #[arg(long)]
+    output_dir: Option<String>,
+    /// With --output-dir, overwrite files which already exist
+    #[arg(long, requires = "output_dir")]
+    force: bool,
+    /// Transcribe on this machine with whisper.cpp (see [local_whisper])
+    /// rather than with OpenAI's API. With --download-method direct, the
+    /// audio goes straight from the download through ffmpeg into whisper.cpp,
+    /// without being written to disk first.
+    #[arg(long)]
+    local: bool,
+    #[command(flatten)]
+    range: TimeRange,
+}
+
+#[derive(Args, Debug)]
+struct TranscribeFeedSubcommand {
+    /// The URL of the RSS or Atom feed
+    url: String,
+    /// The language code of the content
+    language: String,
+    /// Directory to write transcripts to, one {index}-{title}.txt per item
+    output_dir: String,
+    /// How many of the latest items to transcribe
+    #[arg(long, short = 'n', default_value = "5")]
+    count: usize,
+    /// Take the "newest" items (the default) or the "oldest" ones, and go
+    /// through them in that order
+    #[arg(long, default_value = "newest")]
+    order: source::ItemOrder,
+    /// How to download the content. Usually the default of "yt-dlp" is fine.
+    #[arg(long, short = 'm', default_value = "yt-dlp")]
+    download_method: fetch::DownloadMethod,
 }
 
 #[derive(Args, Debug)]
@@ -73,15 +261,63 @@ struct AdhocSubcommand {
     title: String,
     /// The language code of the content to import
     language: String,
-    /// The course ID to import the content into
+    /// The course ID to import the content into, or 0 for no course
     course_id: u64,
     /// Whether to transcribe and post-process the content with OpenAI.
     /// Transcription is required for some platforms, but not for LingQ.
     #[arg(long, short = 's', default_value = "false")]
     skip_transcribe: bool,
+    /// The LingQ level to import the lesson at, from 1 (Beginner 1) to 6
+    /// (Advanced 2)
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=6))]
+    level: Option<u8>,
+    /// Use these subtitles (a WebVTT or SubRip file or URL) as the
+    /// transcript instead of transcribing the audio, keeping their timings
+    /// as anchors in the text
+    #[arg(long, conflicts_with_all = ["skip_transcribe", "start", "end"])]
+    subtitles: Option<String>,
     /// How to download the content. Usually the default of "yt-dlp" is fine.
     #[arg(long, short = 'm', default_value = "yt-dlp")]
     download_method: fetch::DownloadMethod,
+    #[command(flatten)]
+    range: TimeRange,
+}
+
+#[derive(Args, Debug)]
+#[command(group(clap::ArgGroup::new("input").required(true).args(["transcript", "url"])))]
+struct DiffPromptSubcommand {
+    /// File containing the candidate post-processing prompt
+    #[arg(long)]
+    prompt_file: String,
+    /// File containing a transcript to post-process
+    #[arg(long)]
+    transcript: Option<String>,
+    /// Download and transcribe this (preferably short) content instead of
+    /// reading a transcript file
+    #[arg(long)]
+    url: Option<String>,
+    /// How to download --url. Usually the default of "yt-dlp" is fine.
+    #[arg(long, short = 'm', default_value = "yt-dlp")]
+    download_method: fetch::DownloadMethod,
+}
+
+#[derive(Args, Debug)]
+struct ReprocessSubcommand {
+    /// The language code of the lessons
+    language: String,
+    /// The IDs of the lessons to reprocess
+    #[arg(required = true)]
+    lesson_ids: Vec<u64>,
+    /// Print the reprocessed text instead of updating the lessons
+    #[arg(short, long)]
+    dry_run: bool,
+}
+
+/// How to print a report.
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum OutputFormat {
+    Table,
+    Json,
 }
 
 #[derive(Debug, Subcommand)]
@@ -92,9 +328,180 @@ enum SourcesSubcommand {
         #[arg(short, long)]
         tags: Option<Vec<String>>,
 
+        #[command(flatten)]
+        name: NameFilter,
+
+        /// Only import feed items in one of these categories (as given by
+        /// the feed itself, e.g. RSS <category>)
+        #[arg(long)]
+        category: Option<Vec<String>>,
+
         /// Don't actually do anything, just list the sources
         #[arg(short, long, default_value = "false")]
         dry_run: bool,
+
+        /// With --dry-run, print a table of every feed item considered and
+        /// whether it would be imported or skipped (and why)
+        #[arg(long, requires = "dry_run")]
+        explain: bool,
+
+        /// Import at most this many new items from each source, instead of
+        /// its max_items
+        #[arg(short = 'n', long, visible_alias = "limit")]
+        count: Option<usize>,
+
+        /// Import a feed's "newest" new items first (the default), or its
+        /// "oldest", e.g. to backfill a series in order. With "oldest" the
+        /// whole feed is fetched, so every item not yet imported is a
+        /// candidate, not just the latest few. Local directories always go
+        /// by their directory_order.
+        #[arg(long, default_value = "newest")]
+        order: source::ItemOrder,
+
+        /// Only synchronize sources whose poll_interval has passed since
+        /// they were last synchronized (sources without one always are)
+        #[arg(long)]
+        due: bool,
+
+        /// When a source's feed has permanently moved (a 301 or 308
+        /// redirect), change its url in the configuration file to the new
+        /// location, rather than only warning about it
+        #[arg(long)]
+        update_redirected_urls: bool,
+
+        /// Instead of importing anything, transcribe just the start (this
+        /// many seconds, 60 if not given) of each source's first new item
+        /// and print it, to check a new source is set up right
+        #[arg(long, value_name = "SECONDS", num_args = 0..=1, default_missing_value = "60")]
+        preview: Option<u64>,
+
+        /// At the end, print counts and timings for the run (items, bytes
+        /// downloaded, API usage, time spent in each phase) as a table or
+        /// as JSON
+        #[arg(long, value_name = "FORMAT", num_args = 0..=1, default_missing_value = "table")]
+        metrics: Option<OutputFormat>,
+
+        /// Synchronize up to this many sources at once. Requests to LingQ
+        /// are still spaced out by request_delay; use 1 to go one source at
+        /// a time, e.g. to keep each source's output together
+        #[arg(short, long, default_value_t = 3, value_parser = clap::value_parser!(u8).range(1..))]
+        jobs: u8,
+    },
+
+    /// Download and transcribe every source's new items into the transcript
+    /// cache (transcript_cache_dir) without importing them, e.g. to do the
+    /// expensive part at off-peak hours. A later sync imports them without
+    /// transcribing them again.
+    Prefetch {
+        /// Only prefetch from sources with these tags
+        #[arg(short, long)]
+        tags: Option<Vec<String>>,
+
+        #[command(flatten)]
+        name: NameFilter,
+
+        /// Only prefetch feed items in one of these categories
+        #[arg(long)]
+        category: Option<Vec<String>>,
+    },
+
+    /// Check the state file against the lessons in LingQ, reporting anything
+    /// out of step (failures of items which have lessons after all, unused
+    /// prefetched transcripts, imported items whose GUIDs weren't recorded,
+    /// sources which no longer exist) and fixing it
+    Reconcile {
+        /// Only check sources with these tags
+        #[arg(short, long)]
+        tags: Option<Vec<String>>,
+
+        #[command(flatten)]
+        name: NameFilter,
+
+        /// Only report what's out of step, without changing the state file
+        #[arg(short, long)]
+        dry_run: bool,
+    },
+
+    /// Process a single item which failed during an earlier sync again,
+    /// without re-running the rest of its source
+    Retry {
+        /// The title or GUID of the failed item
+        item: String,
+
+        /// The source the item is from, if items with that title failed in
+        /// more than one
+        #[arg(short, long)]
+        source: Option<String>,
+    },
+
+    /// Rename a source, in the configuration file and the state file both,
+    /// so it keeps its sync history and failed items
+    Rename {
+        /// The source's current name
+        old: String,
+        /// The name to give it
+        new: String,
+    },
+
+    /// Add a source to the configuration file, with the settings every
+    /// source needs. Anything else can be set by editing the file afterwards.
+    Add {
+        /// What to call the source
+        #[arg(long)]
+        name: String,
+
+        /// The feed or page to get the source's content from
+        #[arg(long)]
+        url: String,
+
+        /// The LingQ course to import the source's lessons into
+        #[arg(long)]
+        course_id: u64,
+
+        /// The two-letter language code of the content, if the feed doesn't
+        /// declare it (or gets it wrong)
+        #[arg(long)]
+        language: Option<String>,
+
+        /// Tags to group the source with others by
+        #[arg(short, long)]
+        tags: Option<Vec<String>>,
+
+        /// How to find the source's content, as for content_type in the
+        /// configuration file
+        #[arg(long, default_value = "syndication")]
+        content_type: String,
+    },
+
+    /// Write the sources' feeds to an OPML file, e.g. to subscribe to them
+    /// in a podcast app or keep as a backup
+    ExportOpml {
+        /// Where to write the OPML file
+        file: String,
+
+        /// Only export sources with these tags
+        #[arg(short, long)]
+        tags: Option<Vec<String>>,
+
+        #[command(flatten)]
+        name: NameFilter,
+    },
+
+    /// Fetch every source's feed and show the audio link found in its
+    /// latest item (or NONE), to check how audio links are being picked out
+    /// across all sources without syncing anything
+    #[command(alias = "dump-audio-links")]
+    AudioLinks {
+        /// Only check sources with these tags
+        #[arg(short, long)]
+        tags: Option<Vec<String>>,
+
+        #[command(flatten)]
+        name: NameFilter,
+
+        /// Print the links as a table or as JSON
+        #[arg(long, value_name = "FORMAT", default_value = "table")]
+        format: OutputFormat,
     },
 
     /// List sources, possibly filtered by tags
@@ -102,133 +509,2227 @@ enum SourcesSubcommand {
         /// Only list sources with these tags
         #[arg(short, long)]
         tags: Option<Vec<String>>,
+
+        #[command(flatten)]
+        name: NameFilter,
+
+        /// Only list sources whose recent feed items include one of these
+        /// categories. This fetches each source's feed.
+        #[arg(long)]
+        category: Option<Vec<String>>,
     },
 }
 
-#[tokio::main]
-async fn main() {
-    let cli = Cli::parse();
+/// A lesson to create in LingQ.
+struct NewLesson<'a> {
+    /// The source the lesson came from, if any.
+    source: Option<&'a str>,
+    language: &'a str,
+    course_id: u64,
+    title: &'a str,
+    text: &'a str,
+    description: Option<&'a str>,
+    /// LingQ level (see a source's level).
+    level: Option<u8>,
+    /// Extra fields for LingQ's import endpoint (see a source's lingq_fields).
+    extra_fields: &'a HashMap<String, String>,
+}
 
-    // First make sure the configuration file exists
-    if !config::LqcliConfig::exists(&cli.config_file) {
-        eprintln!("Configuration file {} does not exist", cli.config_file);
-        std::process::exit(1);
+/// Move audio out of memory into a temporary file to upload from, exiting if
+/// we can't.
+fn audio_file(audio: Vec<u8>) -> tempfile::NamedTempFile {
+    audio::temp_mp3(&audio).unwrap_or_else(|e| {
+        fatal("io", None, format!("Error saving the audio to upload: {}", e));
+    })
+}
+
+/// Create a lesson in LingQ and report how it went.
+///
+/// If lingq.verify_imports is set, also check that the lesson actually shows
+/// up in the course afterwards, and if post_import_command is set, run it.
+/// If the lesson couldn't be created, the error is left to the caller to
+/// report.
+async fn import_lesson(
+    lingq_client: &lingq::LingqClient,
+    config: &config::LqcliConfig,
+    lesson: &NewLesson<'_>,
+    audio: Option<&std::path::Path>,
+) -> Result<(), lingq::LingqError> {
+    let import = lingq::LessonImport {
+        course_id: lesson.course_id,
+        title: lesson.title,
+        text: lesson.text,
+        description: lesson.description,
+        level: lesson.level,
+        extra_fields: lesson.extra_fields,
+    };
+    let result = lingq_client.create_lesson(lesson.language, &import, audio).await;
+    let created = match result {
+        Ok(created) => {
+            println!("Lesson created successfully: {:?}", created);
+            if let Some(source) = lesson.source {
+                runlog::log(source, format!("Imported {}: {:?}", lesson.title, created));
+            }
+            created
+        }
+        Err(e) => {
+            if let Some(source) = lesson.source {
+                runlog::log(source, format!("Error importing {}: {}", lesson.title, e));
+            }
+            return Err(e);
+        }
+    };
+    if config.lingq.verify_imports && lesson.course_id == lingq::NO_COURSE {
+        println!("Not verifying the lesson since it wasn't imported into a course");
+    } else if config.lingq.verify_imports {
+        match lingq_client.verify_lesson(lesson.language, lesson.course_id, &created, lesson.title).await {
+            Ok(true) => println!("Verified lesson exists in course {}", lesson.course_id),
+            Ok(false) => {
+                let message = format!(
+                    "Verification failed: LingQ accepted \"{}\" but it doesn't appear in course {}",
+                    lesson.title, lesson.course_id
+                );
+                eprintln!("{}", message);
+                if let Some(source) = lesson.source {
+                    runlog::log(source, message);
+                }
+            }
+            Err(e) => eprintln!("Error verifying lesson: {}", e),
+        }
     }
+    if let Some(command) = &config.post_import_command {
+        run_post_import_command(command, lesson, &created).await;
+    }
+    Ok(())
+}
 
-    // Try to read the configuration file
-    let config = match config::LqcliConfig::read(&cli.config_file) {
-        Ok(config) => config,
+/// Run the user's post_import_command for a newly created lesson.
+async fn run_post_import_command(command: &str, lesson: &NewLesson<'_>, created: &lingq::CreatedLesson) {
+    let status = tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("LQCLI_LESSON_TITLE", lesson.title)
+        .env("LQCLI_LESSON_ID", created.id.map(|id| id.to_string()).unwrap_or_default())
+        .env("LQCLI_LESSON_URL", created.url.clone().unwrap_or_default())
+        .env("LQCLI_COURSE_ID", lesson.course_id.to_string())
+        .env("LQCLI_LANGUAGE", lesson.language)
+        .env("LQCLI_SOURCE", lesson.source.unwrap_or_default())
+        .status()
+        .await;
+    match status {
+        Ok(status) if status.success() => {}
+        Ok(status) => eprintln!("post_import_command failed for \"{}\" ({})", lesson.title, status),
+        Err(e) => eprintln!("Error running post_import_command for \"{}\": {}", lesson.title, e),
+    }
+}
+
+/// Make sure LingQ accepts the API key before doing anything expensive,
+/// exiting if it doesn't. If LingQ can't be reached, just warn, since the
+/// real requests will report it if it's still a problem.
+async fn check_lingq_api_key(lingq_client: &lingq::LingqClient) {
+    match lingq_client.check_api_key().await {
+        Ok(true) => {}
+        Ok(false) => {
+            fatal("lingq-auth", None, "LingQ API key is invalid or expired; get a new one at https://www.lingq.com/en/accounts/apikey/");
+        }
+        Err(e) => report::warn("lingq", None, format!("couldn't check the LingQ API key: {}", e)),
+    }
+}
+
+/// The chapters of the content at `link`, or None if it has none (or we
+/// can't find out), in which case it's transcribed as a whole.
+async fn chapters_of(link: &str) -> Option<Vec<chapters::Chapter>> {
+    match chapters::fetch(link).await {
+        Ok(chapters) if !chapters.is_empty() => Some(chapters),
+        Ok(_) => None,
+        Err(e) => {
+            eprintln!("Error getting chapters for {}: {}", link, e);
+            None
+        }
+    }
+}
+
+/// Read an item's subtitles into timed segments of a transcript. Returns
+/// None once we've said why they couldn't be used, so the caller can
+/// transcribe the audio instead.
+async fn subtitle_segments(title: &str, link: &str) -> Option<Vec<openai::Segment>> {
+    println!("Reading the subtitles for {}...", title);
+    let segments = subtitles::read(link).await.and_then(|content| subtitles::parse(&content));
+    let segments = match segments {
+        Ok(segments) => segments,
         Err(e) => {
-            eprintln!("Error reading configuration file: {}", e);
-            std::process::exit(1);
+            eprintln!("Error reading the subtitles for {} ({}), transcribing it instead: {}", title, link, e);
+            return None;
         }
     };
+    if postprocess::segments_text(&segments).is_err() {
+        eprintln!("The subtitles for {} ({}) are empty, transcribing it instead", title, link);
+        return None;
+    }
+    Some(segments)
+}
 
-    let lingq_client = lingq::LingqClient::new(&config.lingq);
+/// Post-process timed segments of a transcript chapter by chapter, with a
+/// heading for each, if there are `chapters`, or else a block at a time,
+/// each starting with an anchor giving its time in the audio (see
+/// preserve_timestamps). If that fails, the transcript is post-processed as
+/// a whole instead, as if it had no timings, rather than being made again.
+async fn postprocess_segments(
+    context: &SyncContext<'_>,
+    source: &source::Source,
+    speaker_regex: Option<&regex::Regex>,
+    title: &str,
+    segments: &[openai::Segment],
+    chapters: Option<&[chapters::Chapter]>,
+) -> Result<Transcribed, String> {
+    let raw = match postprocess::segments_text(segments) {
+        Ok(raw) => raw,
+        Err(openai::ApiError::Empty) => {
+            return Err(format!("the transcript of {} is empty (is the audio silent?)", title));
+        }
+        Err(e) => return Err(e.to_string()),
+    };
+    let text = match chapters {
+        Some(chapters) => postprocess::postprocess_chapters(context.postprocessor, speaker_regex, segments, chapters).await,
+        None => postprocess::postprocess_timestamped(context.postprocessor, speaker_regex, segments).await,
+    };
+    match text {
+        Ok(text) => Ok(Transcribed { raw, text, postprocessed: true }),
+        Err(e) => {
+            eprintln!("Error post-processing {} a part at a time ({}), post-processing it as a whole", title, e);
+            let transcript = match speaker_regex {
+                Some(regex) => postprocess::split_speaker_turns(&raw, regex),
+                None => raw.clone(),
+            };
+            postprocess_transcript(context, source, title, raw, transcript).await
+        }
+    }
+}
 
-    match cli.subcommand {
-        MainSubcommand::Transcribe(args) => {
-            let item = source::SourceItem::from_url_and_title(&args.url, "Unknown");
-            let audio = item.download_audio(args.download_method).await.unwrap();
-            // TODO: language is currently unused
-            let client = openai::OpenAI::new(config.openai);
-            let transcript = client.transcribe(audio).await.unwrap();
-            let postprocessed = client
-                .postprocess(&transcript)
-                .await
-                .unwrap();
-            println!("{postprocessed}");
+/// Fetch an item's transcript from where its site publishes it (see
+/// transcript_url_template), to use as the lesson text as it is. Returns
+/// None once we've said why it couldn't be had, so the caller can
+/// transcribe the audio instead.
+async fn transcript_from_url(title: &str, url: &str) -> Option<Transcribed> {
+    println!("Fetching the transcript of {}...", title);
+    match source::fetch_transcript(url).await {
+        Ok(text) if !text.trim().is_empty() => Some(Transcribed { raw: text.clone(), text, postprocessed: true }),
+        Ok(_) => {
+            eprintln!("The transcript of {} at {} is empty, transcribing it instead", title, url);
+            None
         }
-        MainSubcommand::Adhoc(args) => {
-            println!("We ride!");
-            let item = source::SourceItem::from_url_and_title(&args.url, &args.title);
-            println!("Downloading audio...");
-            let audio = item.download_audio(args.download_method).await.unwrap();
-            println!("Houston, we have audio.");
-            let client = openai::OpenAI::new(config.openai);
-            let transcript = if args.skip_transcribe {
-                "".to_string()
-            } else {
-                println!("Throwing audio at OpenAI...");
-                let transcript = client.transcribe(audio.clone()).await.unwrap();
-                println!("We have a transcript.");
-                println!("Post-processing transcript...");
-                let postprocessed = client.postprocess(&transcript).await.unwrap();
-                println!("We've post-processed it.");
-                postprocessed
+        Err(e) => {
+            eprintln!("Error fetching the transcript of {} ({}), transcribing it instead: {}", title, url, e);
+            None
+        }
+    }
+}
+
+/// Fetch the transcript an item's site publishes on its episode page (see
+/// transcript_via), to use as the lesson text as it is. Returns None once
+/// we've warned why it couldn't be had, so the caller can transcribe the
+/// audio instead.
+async fn published_transcript(source: &source::Source, title: &str, item: &source::SourceItem) -> Option<Transcribed> {
+    println!("Fetching the published transcript of {}...", title);
+    // from_source already checked the headers.
+    let headers = source.feed_headers().unwrap_or_default();
+    match transcript::fetch(item, headers).await {
+        Ok(text) => Some(Transcribed { raw: text.clone(), text, postprocessed: true }),
+        Err(e) => {
+            report::warn(
+                "transcript",
+                Some(&source.name),
+                format!("couldn't get the published transcript of {} ({}), transcribing it instead", title, e),
+            );
+            None
+        }
+    }
+}
+
+/// An item's transcript, straight from Whisper and post-processed.
+struct Transcribed {
+    raw: String,
+    text: String,
+    /// False if post-processing failed and `text` is the raw transcript (see
+    /// on_postprocess_failure), or if it hasn't been post-processed yet
+    /// because it's waiting for the rest of its batch (see
+    /// batch_postprocess).
+    postprocessed: bool,
+}
+
+/// A lesson whose items have been transcribed, waiting to be finished off
+/// for import.
+struct TranscribedLesson {
+    lesson: LessonItems,
+    /// The lesson's audio, or None if it's an item with only text.
+    audio: Option<Vec<u8>>,
+    /// Each item's title and transcript, in lesson order.
+    transcripts: Vec<(String, Transcribed)>,
+}
+
+impl TranscribedLesson {
+    /// Roughly how many tokens of this lesson's transcripts are still to be
+    /// post-processed.
+    fn unprocessed_tokens(&self) -> usize {
+        self.transcripts
+            .iter()
+            .filter(|(_, transcribed)| !transcribed.postprocessed)
+            .map(|(_, transcribed)| postprocess::estimate_tokens(&transcribed.text))
+            .sum()
+    }
+}
+
+/// A lesson ready to import: its items, text, anything else for its
+/// description, its audio and whether the text was post-processed.
+type ReadyLesson = (LessonItems, String, Vec<String>, Option<tempfile::NamedTempFile>, bool);
+
+/// How many of a source's latest items are looked at, and imported at most,
+/// unless it has a max_items or a sync is given a --count.
+const LATEST_ITEMS: usize = 5;
+
+/// Up to `count` of a source's latest items, and the language its content
+/// declares (if any), or None once we've said why they couldn't be had.
+/// Local directories give every item, however many are asked for.
+async fn source_items(source: &source::Source, count: usize) -> Option<(Vec<source::SourceItem>, Option<String>)> {
+    match source.content_type {
+        source::ContentType::Syndication => {
+            let mut feed = match source::Feed::from_source(source).await {
+                Ok(feed) => feed,
+                Err(e) => {
+                    eprintln!("Error getting items for {}: {}", source.name, e);
+                    runlog::log(&source.name, format!("Error fetching {}: {}", source.url, e));
+                    return None;
+                }
             };
-            let course_id = args.course_id;
-            let result = lingq_client
-                .create_lesson(course_id, &args.title, &transcript, Some(audio))
-                .await;
-            match result {
-                Ok(response) => println!("Lesson created successfully: {:?}", response),
-                Err(e) => eprintln!("Error creating lesson: {}", e),
+            // from_source already checked the headers.
+            let headers = source.feed_headers().unwrap_or_default();
+            if let Err(e) = feed.fetch_older_pages(&source.url, headers, count).await {
+                eprintln!("Error getting older items for {}: {}", source.name, e);
+                runlog::log(&source.name, format!("Error fetching older items from {}: {}", source.url, e));
             }
+            let items = feed.items(count);
+            runlog::log(&source.name, format!("Fetched {} item(s) from {}", items.len(), source.url));
+            Some((items, feed.language()))
         }
-        MainSubcommand::Sources(subcommand) => match subcommand {
-            SourcesSubcommand::List { tags } => {
-                let filtered_sources = config.filtered_sources(&tags.unwrap_or_default());
-                let mut table = Table::new(filtered_sources.clone());
-                let style = Style::modern()
-                    .horizontals([(1, HorizontalLine::inherit(Style::modern()).horizontal('═'))]);
-                table.with(style)
-                    .modify(Rows::first(), Color::BOLD);
-                println!("{}", table);
+        source::ContentType::LocalDirectory => match source::local_items(source).await {
+            Ok(items) => {
+                runlog::log(&source.name, format!("Found {} file(s) in {}", items.len(), source.url));
+                Some((items, None))
             }
-            SourcesSubcommand::Sync { tags, dry_run } => {
-                // Get the filtered sources by tags
-                // source.tags will be a Tags(Option<Vec<String>>)
-                let filtered_sources = config.filtered_sources(&tags.unwrap_or_default());
-
-                for source in filtered_sources {
-                    println!("Syncing source: {}", source.name);
-
-                    let lesson_titles = lingq_client
-                        .get_lesson_titles(&source.language, source.course_id)
-                        .await
-                        .unwrap_or_else(|e| {
-                            eprintln!("Error getting lesson titles for {}: {}", source.name, e);
-                            vec![]
-                        });
-
-                    // Latest 5 items (this number should be configurable)
-                    // TODO: Don't use Feed directly; support other content types
-                    let items = match source::Feed::from_source(&source).await {
-                        Ok(feed) => feed.items(5),
-                        Err(e) => {
-                            eprintln!("Error getting items for {}: {}", source.name, e);
-                            continue;
-                        }
-                    };
-                    for item in items {
-                        // If the item is already in LingQ, skip it
-                        match &item.title() {
-                            Some(title) => {
-                                if lesson_titles.contains(title) {
-                                    println!("Skipping existing lesson: {}", title);
-                                    continue;
-                                }
-                            }
-                            None => {
-                                eprintln!("No title found for item in {}", source.name);
-                                continue;
-                            }
-                        }
-                        let audio_link = item.get_audio_link();
-                        if let Some(audio_link) = audio_link {
-                            println!("{}: {}", item.title().unwrap_or("<unknown>".to_string()), audio_link);
-                        } else {
-                            eprintln!("No audio link found for {}", source.name);
-                        }
-                    }
+            Err(e) => {
+                eprintln!("Error getting items for {}: {}", source.name, e);
+                runlog::log(&source.name, format!("Error reading {}: {}", source.url, e));
+                None
+            }
+        },
+        source::ContentType::YoutubeChannel => match source::youtube_items(source, count).await {
+            Ok(items) => {
+                runlog::log(&source.name, format!("Listed {} video(s) from {}", items.len(), source.url));
+                Some((items, None))
+            }
+            Err(e) => {
+                eprintln!("Error getting items for {}: {}", source.name, e);
+                runlog::log(&source.name, format!("Error listing {}: {}", source.url, e));
+                None
+            }
+        },
+    }
+}
+
+/// Titles of the lessons already imported from a source, to tell which of its
+/// items are new, along with `merged_titles` (from the state file).
+async fn existing_titles(
+    lingq_client: &lingq::LingqClient,
+    source: &source::Source,
+    language: &str,
+    merged_titles: &[String],
+) -> Vec<String> {
+    let mut lessons = lingq_client
+        .get_lessons(language, source.course_id)
+        .await
+        .unwrap_or_else(|e| {
+            eprintln!("Error getting lesson titles for {}: {}", source.name, e);
+            runlog::log(&source.name, format!("Error getting lesson titles from course {}: {}", source.course_id, e));
+            vec![]
+        });
+    // Courses can be reordered in LingQ, so go by when lessons were created
+    // to find the most recent.
+    if let Some(window) = source.dedup_window {
+        lingq::sort_by_creation(&mut lessons);
+        lessons.drain(..lessons.len().saturating_sub(window));
+    }
+    let mut lesson_titles: Vec<String> = lessons.into_iter().map(|lesson| lesson.title).collect();
+    // Items merged into another lesson don't have one of their own.
+    lesson_titles.extend_from_slice(merged_titles);
+    lesson_titles
+}
+
+/// Why an item shouldn't be imported from a source, if it shouldn't.
+fn item_skip_reason(
+    config: &config::LqcliConfig,
+    source: &source::Source,
+    item: &source::SourceItem,
+    lesson_titles: &[String],
+    categories: &[String],
+    state: &state::State,
+) -> Option<source::SkipReason> {
+    // Titles only matter for items without a GUID to go by. Local files are
+    // looked for both ways: a lesson is only titled by the filename when the
+    // file had no title of its own.
+    let is_local = matches!(item, source::SourceItem::Local(_));
+    let guid = item.guid().filter(|_| source.dedup == source::Dedup::Guid || is_local);
+    let lesson_titles = if guid.is_some() && !is_local { &[] } else { lesson_titles };
+    item.skip_reason(lesson_titles, categories, source.require_audio)
+        .or_else(|| {
+            let guid = guid?;
+            state.is_imported(&source.name, &guid).then_some(source::SkipReason::GuidMatch(guid))
+        })
+        .or_else(|| {
+            let title = item.title()?;
+            (!source.title_allowed(&title)).then_some(source::SkipReason::TitleFiltered)
+        })
+        .or_else(|| {
+            if config.existing_downloads != fetch::ExistingDownloads::Skip {
+                return None;
+            }
+            let download_dir = config.download_dir.as_ref()?;
+            let path = fetch::existing_download(download_dir, &config.download_filename_template, &source.name, item)?;
+            Some(source::SkipReason::Downloaded(path))
+        })
+}
+
+/// Download and transcribe a source's new items into the transcript cache,
+/// without importing them, returning how many were transcribed.
+async fn prefetch_items(
+    context: &SyncContext<'_>,
+    cache: &cache::TranscriptCache,
+    source: &source::Source,
+    language: &str,
+    new_items: Vec<source::SourceItem>,
+    state: &mut state::State,
+) -> usize {
+    let transcriber = match transcriber(context, source) {
+        Ok(transcriber) => transcriber,
+        Err(e) => {
+            eprintln!("Skipping {}: {}", source.name, e);
+            return 0;
+        }
+    };
+    let mut transcribed = 0;
+    for item in new_items {
+        let title = item.title().unwrap_or_default();
+        let Some(link) = item.get_audio_link() else {
+            // Nothing to transcribe.
+            continue;
+        };
+        let already = state.prefetched_key(&source.name, &link);
+        if already.is_some_and(|key| cache.get(key).is_some()) {
+            println!("Already transcribed {}", title);
+            continue;
+        }
+        println!("Downloading {}...", title);
+        let options = fetch::DownloadOptions {
+            max_rate: context.config.download_rate(),
+            audio_lang: source.yt_dlp_audio_lang.as_deref(),
+            unwrap_redirects: source.unwrap_redirects,
+            ..Default::default()
+        };
+        let audio = match item.download_audio(source.download_method.clone(), options).await {
+            Ok(audio) => audio,
+            Err(e) => {
+                eprintln!("Error downloading {}: {}", title, e);
+                continue;
+            }
+        };
+        println!("Transcribing {}...", title);
+        let key = cache::TranscriptCache::key(&audio, &transcriber.name());
+        match cache.get_or_transcribe(&key, || transcriber.transcribe(audio, language)).await {
+            Ok(_) => {}
+            Err(openai::ApiError::Empty) => {
+                eprintln!("Skipping {}: the transcript is empty (is the audio silent?)", title);
+                continue;
+            }
+            Err(e) => {
+                eprintln!("Skipping {}: transcribing it failed: {}", title, e);
+                continue;
+            }
+        }
+        state.record_prefetched(&source.name, &link, &key);
+        if let Err(e) = state.write(context.state_path) {
+            eprintln!("Error writing state file {}: {}", context.state_path, e);
+        }
+        transcribed += 1;
+    }
+    transcribed
+}
+
+/// Transcribe the first `seconds` of an item and print the raw transcript,
+/// without post-processing or importing it.
+async fn preview_item(
+    config: &config::LqcliConfig,
+    openai_client: &openai::OpenAI,
+    source: &source::Source,
+    item: &source::SourceItem,
+    seconds: u64,
+) {
+    let title = item.title().unwrap_or_default();
+    let Some(link) = item.get_audio_link() else {
+        println!("Preview of {} ({}, text only):\n{}", title, source.name, item.text().unwrap_or_default());
+        return;
+    };
+    println!("Downloading {} ({})...", title, link);
+    let options = fetch::DownloadOptions {
+        max_rate: config.download_rate(),
+        audio_lang: source.yt_dlp_audio_lang.as_deref(),
+        unwrap_redirects: source.unwrap_redirects,
+        until: Some(seconds as f64),
+    };
+    let audio = item.download_audio(source.download_method.clone(), options);
+    let audio = match audio.await {
+        Ok(audio) => audio,
+        Err(e) => {
+            eprintln!("Error downloading {}: {}", title, e);
+            return;
+        }
+    };
+    // Clipping past the end is an error, so stop at the end of short items.
+    let clipped = audio::blocking(move || {
+        audio::duration(&audio).and_then(|length| audio::clip(&audio, None, Some(length.min(seconds as f64))))
+    });
+    let clipped = match clipped.await {
+        Ok(clipped) => clipped,
+        Err(e) => {
+            eprintln!("Error clipping {}: {}", title, e);
+            return;
+        }
+    };
+    let whisper = transcribe::Whisper {
+        client: openai_client,
+        model: source.whisper_model(openai_client.whisper_model()),
+        temperature: source.whisper_temperature(openai_client.whisper_temperature()),
+    };
+    match whisper.transcribe(clipped, source.language.as_deref().unwrap_or_default()).await {
+        Ok(transcript) => println!("Preview of {} ({}):\n{}", title, source.name, transcript),
+        Err(openai::ApiError::Empty) => {
+            println!("The first {}s of {} transcribed to nothing (is it silent?)", seconds, title)
+        }
+        Err(e) => eprintln!("Error transcribing the preview of {}: {}", title, e),
+    }
+}
+
+/// Everything needed to import new items during a sync.
+struct SyncContext<'a> {
+    config: &'a config::LqcliConfig,
+    lingq_client: &'a lingq::LingqClient,
+    openai_client: &'a openai::OpenAI,
+    postprocessor: &'a postprocess::AnyPostprocessor,
+    transcript_cache: Option<&'a cache::TranscriptCache>,
+    deepgram: Option<&'a deepgram::Deepgram>,
+    local_whisper: Option<&'a local_whisper::LocalWhisper>,
+    metrics: &'a std::cell::RefCell<metrics::RunMetrics>,
+    state_path: &'a str,
+}
+
+/// How many finished items each pipeline stage can get ahead of the next.
+const PIPELINE_DEPTH: usize = 1;
+
+/// The feed items making up one lesson: usually just one, but several short
+/// ones can be merged (see merge_window).
+struct LessonItems {
+    title: String,
+    /// Oldest first, the order they appear in the lesson.
+    items: Vec<source::SourceItem>,
+    /// How long the lesson spent being downloaded, and being transcribed and
+    /// post-processed, not counting any time waiting its turn.
+    downloading: std::time::Duration,
+    transcribing: std::time::Duration,
+}
+
+/// Group a source's new items (in the order they're to be imported) into
+/// lessons, merging items published within `merge_window` of each other.
+fn group_items(items: Vec<source::SourceItem>, merge_window: Option<chrono::Duration>) -> Vec<LessonItems> {
+    let mut groups: Vec<Vec<source::SourceItem>> = vec![];
+    for item in items {
+        let mergeable = |item: &source::SourceItem| item.get_audio_link().is_some() && item.published().is_some();
+        let joins_last = match (merge_window, groups.last()) {
+            (Some(window), Some(group)) if mergeable(&item) && mergeable(&group[0]) => {
+                let (first, this) = (group[0].published().unwrap(), item.published().unwrap());
+                (first - this).abs() <= window
+            }
+            _ => false,
+        };
+        match groups.last_mut() {
+            Some(group) if joins_last => group.push(item),
+            _ => groups.push(vec![item]),
+        }
+    }
+    groups
+        .into_iter()
+        .map(|mut items| {
+            // Only items with a date are ever merged.
+            items.sort_by_key(|item| item.published());
+            let title = items[0].title().unwrap_or_default();
+            let title = match items.len() {
+                1 => title,
+                n => format!("{} (+{} more)", title, n - 1),
+            };
+            LessonItems { title, items, downloading: Default::default(), transcribing: Default::default() }
+        })
+        .collect()
+}
+
+/// The transcription service a source uses (see its transcript_via), or why
+/// there isn't one.
+fn transcriber<'a>(
+    context: &SyncContext<'a>,
+    source: &'a source::Source,
+) -> Result<transcribe::AnyTranscriber<'a>, String> {
+    match (source.transcript_via, context.deepgram) {
+        (transcribe::TranscriptVia::Deepgram, Some(deepgram)) => Ok(transcribe::AnyTranscriber::Deepgram(deepgram)),
+        (transcribe::TranscriptVia::Deepgram, None) => Err(format!(
+            "can't transcribe {} with Deepgram: there is no [deepgram] section",
+            source.name
+        )),
+        (transcribe::TranscriptVia::Local, _) => match context.local_whisper {
+            Some(local) => Ok(transcribe::AnyTranscriber::Local(local)),
+            None => Err(format!(
+                "can't transcribe {} with whisper.cpp: there is no [local_whisper] section",
+                source.name
+            )),
+        },
+        (transcribe::TranscriptVia::Lingq, _) => Err(format!("LingQ transcribes {} itself when it's imported", source.name)),
+        _ => Ok(transcribe::AnyTranscriber::Whisper(transcribe::Whisper {
+            client: context.openai_client,
+            model: source.whisper_model(context.openai_client.whisper_model()),
+            temperature: source.whisper_temperature(context.openai_client.whisper_temperature()),
+        })),
+    }
+}
+
+/// Transcribe and post-process one item's audio, or say why it can't be.
+/// If `prefetched` is the cache key of a transcript `sources prefetch`
+/// already made of the item, that transcript is used rather than
+/// transcribing the audio again.
+///
+/// When the source has batch_postprocess, the transcript is left for
+/// postprocess_lessons to post-process with the rest of its batch.
+async fn transcribe_item(
+    context: &SyncContext<'_>,
+    source: &source::Source,
+    speaker_regex: Option<&regex::Regex>,
+    language: &str,
+    item: &source::SourceItem,
+    audio: Vec<u8>,
+    prefetched: Option<&str>,
+) -> Result<Transcribed, String> {
+    if source.transcript_via == transcribe::TranscriptVia::Lingq {
+        // The lesson is imported with just its audio, for LingQ to fill in.
+        return Ok(Transcribed { raw: String::new(), text: String::new(), postprocessed: true });
+    }
+    let title = item.title().unwrap_or_default();
+    let transcript_template =
+        source.transcript_url_template.as_deref().filter(|_| source.transcript_via == transcribe::TranscriptVia::Url);
+    if let Some(template) = transcript_template {
+        if let Some(transcribed) = transcript_from_url(&title, &item.transcript_url(template, &source.name)).await {
+            return Ok(transcribed);
+        }
+    }
+    if matches!(source.transcript_via, transcribe::TranscriptVia::EasyGerman | transcribe::TranscriptVia::SuperEasyGerman) {
+        if let Some(transcribed) = published_transcript(source, &title, item).await {
+            return Ok(transcribed);
+        }
+    }
+    if let Some(link) = item.subtitles_link().filter(|_| source.use_subtitles) {
+        if let Some(segments) = subtitle_segments(&title, &link).await {
+            return postprocess_segments(context, source, speaker_regex, &title, &segments, None).await;
+        }
+    }
+    let transcriber = transcriber(context, source)?;
+    let whisper = match &transcriber {
+        transcribe::AnyTranscriber::Whisper(whisper) => Some(whisper),
+        _ => None,
+    };
+    let chapters = match (whisper.filter(|_| source.include_chapters), item.get_audio_link()) {
+        (Some(_), Some(link)) => chapters_of(&link).await,
+        _ => None,
+    };
+    if let Some(whisper) = whisper.filter(|_| chapters.is_some() || source.preserve_timestamps) {
+        let segments = match whisper.transcribe_segments(audio, language).await {
+            Ok(segments) => segments,
+            Err(openai::ApiError::Empty) => {
+                return Err(format!("the transcript of {} is empty (is the audio silent?)", title));
+            }
+            Err(e) => return Err(format!("transcribing {} failed: {}", title, e)),
+        };
+        return postprocess_segments(context, source, speaker_regex, &title, &segments, chapters.as_deref()).await;
+    }
+    let prefetched = match (context.transcript_cache, prefetched) {
+        (Some(cache), Some(key)) => cache.get(key),
+        _ => None,
+    };
+    let raw = match (prefetched, context.transcript_cache) {
+        (Some(raw), _) => Ok(raw),
+        (None, Some(cache)) => {
+            let key = cache::TranscriptCache::key(&audio, &transcriber.name());
+            cache.get_or_transcribe(&key, || transcriber.transcribe(audio, language)).await
+        }
+        (None, None) => transcriber.transcribe(audio, language).await,
+    };
+    let raw = match raw {
+        Ok(raw) => raw,
+        Err(openai::ApiError::Empty) => {
+            return Err(format!("the transcript of {} is empty (is the audio silent?)", title));
+        }
+        Err(e) => return Err(format!("transcribing {} failed: {}", title, e)),
+    };
+    let transcript = match speaker_regex {
+        Some(regex) => postprocess::split_speaker_turns(&raw, regex),
+        None => raw.clone(),
+    };
+    if source.batch_postprocess {
+        return Ok(Transcribed { raw, text: transcript, postprocessed: false });
+    }
+    postprocess_transcript(context, source, &title, raw, transcript).await
+}
+
+/// Post-process an item's transcript on its own, dealing with a failure as
+/// on_postprocess_failure says.
+async fn postprocess_transcript(
+    context: &SyncContext<'_>,
+    source: &source::Source,
+    title: &str,
+    raw: String,
+    transcript: String,
+) -> Result<Transcribed, String> {
+    match context.postprocessor.postprocess(&transcript).await {
+        Ok(text) => Ok(Transcribed { raw, text, postprocessed: true }),
+        Err(e) => match context.config.on_postprocess_failure {
+            postprocess::PostprocessFailure::Skip => Err(format!("post-processing {} failed: {}", title, e)),
+            postprocess::PostprocessFailure::ImportRaw => {
+                report::warn(
+                    e.kind(),
+                    Some(&source.name),
+                    format!("post-processing {} failed ({}), importing the raw transcript", title, e),
+                );
+                Ok(Transcribed { raw, text: transcript, postprocessed: false })
+            }
+            postprocess::PostprocessFailure::Error => {
+                fatal(e.kind(), Some(&source.name), format!("Post-processing {} failed: {}", title, e));
+            }
+        },
+    }
+}
+
+/// Transcribe each of a lesson's items from its downloaded audio (`parts`,
+/// one per item), or None once we've said why it can't be and recorded it
+/// in `failures`.
+#[allow(clippy::too_many_arguments)]
+async fn transcribe_lesson(
+    context: &SyncContext<'_>,
+    source: &source::Source,
+    speaker_regex: Option<&regex::Regex>,
+    language: &str,
+    failures: &std::cell::RefCell<Vec<state::FailedItem>>,
+    prefetched: &HashMap<String, String>,
+    lesson: LessonItems,
+    parts: Vec<Vec<u8>>,
+) -> Option<TranscribedLesson> {
+    if parts.is_empty() {
+        let Some(text) = lesson.items[0].text() else {
+            eprintln!("Skipping {}: it has neither audio nor text", lesson.title);
+            runlog::log(&source.name, format!("Skipped {}: it has neither audio nor text", lesson.title));
+            return None;
+        };
+        let transcribed = Transcribed { raw: text.clone(), text, postprocessed: true };
+        let transcripts = vec![(lesson.title.clone(), transcribed)];
+        return Some(TranscribedLesson { lesson, audio: None, transcripts });
+    }
+    let audio = if parts.len() == 1 {
+        parts[0].clone()
+    } else {
+        let joining = parts.clone();
+        match audio::blocking(move || audio::concat(&joining)).await {
+            Ok(audio) => audio,
+            Err(e) => {
+                eprintln!("Error joining the audio for {}: {}", lesson.title, e);
+                runlog::log(&source.name, format!("Error joining the audio for {}: {}", lesson.title, e));
+                record_failed_lesson(failures, &lesson, language, &e.to_string());
+                return None;
+            }
+        }
+    };
+    let mut transcripts = vec![];
+    for (item, part) in lesson.items.iter().zip(parts) {
+        let title = item.title().unwrap_or_default();
+        println!("Transcribing {}...", title);
+        let started = std::time::Instant::now();
+        let prefetched = item.get_audio_link().and_then(|link| prefetched.get(&link));
+        let transcribed =
+            transcribe_item(context, source, speaker_regex, language, item, part, prefetched.map(String::as_str))
+                .await;
+        context.metrics.borrow_mut().transcribe_seconds += started.elapsed().as_secs_f64();
+        match transcribed {
+            Ok(transcribed) => {
+                let seconds = started.elapsed().as_secs_f64();
+                runlog::log(&source.name, format!("Transcribed {} in {:.1}s", title, seconds));
+                transcripts.push((title, transcribed));
+            }
+            Err(reason) => {
+                eprintln!("Skipping {}: {}", lesson.title, reason);
+                runlog::log(&source.name, format!("Skipped {}: {}", lesson.title, reason));
+                record_failed_lesson(failures, &lesson, language, &reason);
+                return None;
+            }
+        }
+    }
+    Some(TranscribedLesson { lesson, audio: Some(audio), transcripts })
+}
+
+/// Post-process the transcripts which batch_postprocess left for later, all
+/// in one request, falling back to one at a time if that fails. Lessons with
+/// an item which couldn't be post-processed even then are left out, as they
+/// would be without batching, and recorded in `failures`.
+async fn postprocess_lessons(
+    context: &SyncContext<'_>,
+    source: &source::Source,
+    language: &str,
+    failures: &std::cell::RefCell<Vec<state::FailedItem>>,
+    lessons: Vec<TranscribedLesson>,
+) -> Vec<TranscribedLesson> {
+    let episodes: Vec<(String, String)> = lessons
+        .iter()
+        .flat_map(|lesson| &lesson.transcripts)
+        .filter(|(_, transcribed)| !transcribed.postprocessed)
+        .map(|(title, transcribed)| (title.clone(), transcribed.text.clone()))
+        .collect();
+    let mut batched = None;
+    if episodes.len() > 1 {
+        println!("Post-processing {} items of {} together...", episodes.len(), source.name);
+        let started = std::time::Instant::now();
+        batched = context.postprocessor.postprocess_batch(&episodes).await.map(Vec::into_iter);
+        context.metrics.borrow_mut().transcribe_seconds += started.elapsed().as_secs_f64();
+        if batched.is_none() {
+            report::warn(
+                "postprocess",
+                Some(&source.name),
+                "post-processing the batch failed, post-processing its items one at a time",
+            );
+        }
+    }
+    let mut finished = vec![];
+    'lessons: for mut lesson in lessons {
+        for (title, transcribed) in lesson.transcripts.iter_mut().filter(|(_, transcribed)| !transcribed.postprocessed) {
+            if let Some(text) = batched.as_mut().and_then(Iterator::next) {
+                transcribed.text = text;
+                transcribed.postprocessed = true;
+                continue;
+            }
+            let raw = std::mem::take(&mut transcribed.raw);
+            let transcript = std::mem::take(&mut transcribed.text);
+            match postprocess_transcript(context, source, title, raw, transcript).await {
+                Ok(postprocessed) => *transcribed = postprocessed,
+                Err(reason) => {
+                    eprintln!("Skipping {}: {}", lesson.lesson.title, reason);
+                    runlog::log(&source.name, format!("Skipped {}: {}", lesson.lesson.title, reason));
+                    record_failed_lesson(failures, &lesson.lesson, language, &reason);
+                    continue 'lessons;
+                }
+            }
+        }
+        finished.push(lesson);
+    }
+    finished
+}
+
+/// Put a lesson's transcripts together into its text and description, and
+/// save its audio for upload, or None once we've said why it can't be and
+/// recorded it in `failures`.
+async fn finish_lesson(
+    context: &SyncContext<'_>,
+    source: &source::Source,
+    language: &str,
+    failures: &std::cell::RefCell<Vec<state::FailedItem>>,
+    lesson: TranscribedLesson,
+) -> Option<ReadyLesson> {
+    let TranscribedLesson { lesson, audio, transcripts } = lesson;
+    let Some(audio) = audio else {
+        let (_, transcribed) = transcripts.into_iter().next()?;
+        return Some((lesson, transcribed.text, vec![], None, true));
+    };
+    let mut text = String::new();
+    let mut raw = String::new();
+    let mut postprocessed = true;
+    for (title, transcribed) in &transcripts {
+        postprocessed &= transcribed.postprocessed;
+        if lesson.items.len() > 1 {
+            text.push_str(&format!("## {}\n\n", title));
+            raw.push_str(&format!("## {}\n\n", title));
+        }
+        text.push_str(transcribed.text.trim());
+        text.push_str("\n\n");
+        raw.push_str(transcribed.raw.trim());
+        raw.push_str("\n\n");
+    }
+    let text = text.trim_end().to_string();
+    // Anything else to put in the lesson's description.
+    let mut notes = vec![];
+    if source.keep_raw_transcript {
+        notes.push(raw.trim_end().to_string());
+    }
+    // With transcript_via = "lingq" there's nothing to translate yet.
+    if let Some(language) = source.generate_translation_to.as_ref().filter(|_| !text.is_empty()) {
+        println!("Translating {} into {}...", lesson.title, language);
+        match context.openai_client.translate(&text, language).await {
+            Ok(translation) => notes.push(translation.trim().to_string()),
+            Err(e) => {
+                let message = format!("Couldn't translate {} ({}), importing it without", lesson.title, e);
+                eprintln!("{}", message);
+                runlog::log(&source.name, message);
+            }
+        }
+    }
+    // Keep the audio on disk rather than in memory until it's uploaded.
+    let audio = match audio::temp_mp3(&audio) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Error saving the audio for {}: {}", lesson.title, e);
+            runlog::log(&source.name, format!("Error saving the audio for {}: {}", lesson.title, e));
+            record_failed_lesson(failures, &lesson, language, &e.to_string());
+            return None;
+        }
+    };
+    Some((lesson, text, notes, Some(audio), postprocessed))
+}
+
+/// Download, transcribe and import a source's new items, returning how many
+/// lessons were imported.
+///
+/// The three steps run as a pipeline: while one item is being transcribed,
+/// the next one is already downloading, and so on. Each step still handles
+/// one item at a time, in order, so lessons are created in feed order and
+/// each service sees no more load than before. With batch_postprocess,
+/// transcribed lessons wait to be post-processed together before they move
+/// on to be imported.
+///
+/// `state` may be shared with other sources syncing at the same time, so it
+/// is only ever borrowed between awaits.
+async fn sync_new_items(
+    context: &SyncContext<'_>,
+    source: &source::Source,
+    language: &str,
+    new_items: Vec<source::SourceItem>,
+    state: &std::cell::RefCell<state::State>,
+) -> usize {
+    let config = context.config;
+    // An invalid pattern has already been warned about by validate().
+    let speaker_regex = source.speaker_regex().ok().flatten();
+    let speaker_regex = speaker_regex.as_ref();
+    let failures = std::cell::RefCell::new(vec![]);
+    let failures = &failures;
+    let (downloaded_tx, mut downloaded_rx) = tokio::sync::mpsc::channel(PIPELINE_DEPTH);
+    let (transcribed_tx, mut transcribed_rx) = tokio::sync::mpsc::channel(PIPELINE_DEPTH);
+    let merge_window = source.merge_window.map(|hours| chrono::Duration::hours(hours as i64));
+    let lessons = group_items(new_items, merge_window);
+    let total = lessons.len();
+    let prefetched = state
+        .borrow()
+        .sources
+        .get(&source.name)
+        .map(|source_state| source_state.prefetched.clone())
+        .unwrap_or_default();
+    let prefetched = &prefetched;
+    if let Some(eta) = state.borrow().estimate(&source.name, total) {
+        println!("{} new item(s) for {}, {} remaining", total, source.name, state::format_eta(eta));
+    }
+
+    let download = async move {
+        'lessons: for mut lesson in lessons {
+            if lesson.items.len() == 1 && lesson.items[0].get_audio_link().is_none() {
+                // skip_reason() only lets these through if the source
+                // doesn't require audio.
+                if downloaded_tx.send((lesson, vec![])).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+            let started = std::time::Instant::now();
+            let mut parts = vec![];
+            for item in &lesson.items {
+                let title = item.title().unwrap_or_default();
+                let existing = match (&config.download_dir, config.existing_downloads) {
+                    (Some(download_dir), fetch::ExistingDownloads::Reuse) => {
+                        fetch::existing_download(download_dir, &config.download_filename_template, &source.name, item)
+                    }
+                    _ => None,
+                };
+                if let Some(path) = existing {
+                    match tokio::fs::read(&path).await {
+                        Ok(audio) => {
+                            println!("Using the audio already downloaded for {}: {}", title, path.display());
+                            runlog::log(&source.name, format!("Reused {} for {}", path.display(), title));
+                            parts.push(audio);
+                            continue;
+                        }
+                        Err(e) => eprintln!("Error reading {}, downloading {} again: {}", path.display(), title, e),
+                    }
+                }
+                println!("Downloading {}...", title);
+                let started = std::time::Instant::now();
+                let options = fetch::DownloadOptions {
+                    max_rate: config.download_rate(),
+                    audio_lang: source.yt_dlp_audio_lang.as_deref(),
+                    unwrap_redirects: source.unwrap_redirects,
+                    ..Default::default()
+                };
+                let audio = item.download_audio(source.download_method.clone(), options);
+                let audio = audio.await;
+                context.metrics.borrow_mut().download_seconds += started.elapsed().as_secs_f64();
+                let audio = match audio {
+                    Ok(audio) => {
+                        let link = item.get_audio_link().unwrap_or_default();
+                        runlog::log(&source.name, format!("Downloaded {} ({} bytes from {})", title, audio.len(), link));
+                        audio
+                    }
+                    Err(e) => {
+                        eprintln!("Error downloading {}: {}", title, e);
+                        runlog::log(&source.name, format!("Error downloading {}: {}", title, e));
+                        record_failed_lesson(failures, &lesson, language, &e.to_string());
+                        continue 'lessons;
+                    }
+                };
+                if let Some(download_dir) = &config.download_dir {
+                    match fetch::save_download(
+                        download_dir,
+                        &config.download_filename_template,
+                        &source.name,
+                        item,
+                        &audio,
+                    ) {
+                        Ok(path) => println!("Saved audio to {}", path.display()),
+                        Err(e) => {
+                            eprintln!("Error saving audio for {}: {}", title, e);
+                            runlog::log(&source.name, format!("Error saving audio for {}: {}", title, e));
+                        }
+                    }
+                }
+                context.metrics.borrow_mut().bytes_downloaded += audio.len() as u64;
+                parts.push(audio);
+            }
+            lesson.downloading = started.elapsed();
+            if downloaded_tx.send((lesson, parts)).await.is_err() {
+                break;
+            }
+        }
+    };
+
+    let transcribe = async move {
+        // Lessons waiting to be post-processed together, and roughly how
+        // many tokens of transcript they have between them.
+        let mut batch = vec![];
+        let mut batch_tokens = 0;
+        loop {
+            let received = downloaded_rx.recv().await;
+            let done = received.is_none();
+            let transcribed = match received {
+                Some((lesson, parts)) => {
+                    let started = std::time::Instant::now();
+                    let transcribed =
+                        transcribe_lesson(context, source, speaker_regex, language, failures, prefetched, lesson, parts)
+                            .await;
+                    transcribed.map(|mut transcribed| {
+                        transcribed.lesson.transcribing += started.elapsed();
+                        transcribed
+                    })
+                }
+                None => None,
+            };
+            let mut ready = vec![];
+            if source.batch_postprocess {
+                let tokens = transcribed.as_ref().map_or(0, TranscribedLesson::unprocessed_tokens);
+                if done || batch_tokens + tokens > config.batch_postprocess_max_tokens {
+                    let started = std::time::Instant::now();
+                    ready = postprocess_lessons(context, source, language, failures, std::mem::take(&mut batch)).await;
+                    // The batch was post-processed together, so share the time out.
+                    let share = started.elapsed() / ready.len().max(1) as u32;
+                    for lesson in &mut ready {
+                        lesson.lesson.transcribing += share;
+                    }
+                    batch_tokens = 0;
+                }
+                batch_tokens += tokens;
+                batch.extend(transcribed);
+            } else {
+                ready.extend(transcribed);
+            }
+            for lesson in ready {
+                let started = std::time::Instant::now();
+                let Some(mut lesson) = finish_lesson(context, source, language, failures, lesson).await else {
+                    continue;
+                };
+                lesson.0.transcribing += started.elapsed();
+                if transcribed_tx.send(lesson).await.is_err() {
+                    return;
+                }
+            }
+            if done {
+                break;
+            }
+        }
+    };
+
+    let import = async {
+        let mut remaining = total;
+        let mut imported_count = 0;
+        let extra_fields = source.lingq_fields();
+        while let Some((lesson, text, notes, audio, postprocessed)) = transcribed_rx.recv().await {
+            let description = match lesson.items.as_slice() {
+                [item] => item.description().filter(|_| source.include_description),
+                _ => None,
+            };
+            let description: Vec<String> = description.into_iter().chain(notes).collect();
+            let description = Some(description.join("\n\n")).filter(|description| !description.is_empty());
+            let new_lesson = NewLesson {
+                source: Some(&source.name),
+                language,
+                course_id: source.course_id,
+                title: &lesson.title,
+                text: &text,
+                description: description.as_deref(),
+                level: source.level(),
+                extra_fields: &extra_fields,
+            };
+            let audio_path = audio.as_ref().map(|file| file.path());
+            let started = std::time::Instant::now();
+            let imported = import_lesson(context.lingq_client, config, &new_lesson, audio_path).await;
+            let importing = started.elapsed();
+            context.metrics.borrow_mut().import_seconds += importing.as_secs_f64();
+            remaining -= 1;
+            match imported {
+                Ok(()) => {
+                    imported_count += 1;
+                    context.metrics.borrow_mut().items_imported += lesson.items.len();
+                    if !postprocessed {
+                        context.metrics.borrow_mut().raw_imports.push(lesson.title.clone());
+                    }
+                    let mut state = state.borrow_mut();
+                    for item in &lesson.items {
+                        let link = item.get_audio_link().unwrap_or_default();
+                        state.clear_failure(&source.name, &link);
+                        state.clear_prefetched(&source.name, &link);
+                    }
+                    state.record_imported(&source.name, lesson.items.iter().filter_map(|item| item.guid()));
+                    if lesson.items.len() > 1 {
+                        state.record_merged(&source.name, lesson.items.iter().filter_map(|item| item.title()));
+                    }
+                    // Lessons go through the steps side by side, so each adds
+                    // about as much to a sync as its slowest step took. Only
+                    // the lesson's own work is timed, not other sources'.
+                    let slowest_step = lesson.downloading.max(lesson.transcribing).max(importing);
+                    state.record_item_time(&source.name, slowest_step);
+                    if let Err(e) = state.write(context.state_path) {
+                        eprintln!("Error writing state file {}: {}", context.state_path, e);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Error creating lesson: {}", e);
+                    record_failed_lesson(failures, &lesson, language, &e.to_string());
+                }
+            }
+            if let Some(eta) = state.borrow().estimate(&source.name, remaining).filter(|_| remaining > 0) {
+                println!("{} new item(s) left for {}, {} remaining", remaining, source.name, state::format_eta(eta));
+            }
+        }
+        imported_count
+    };
+
+    let ((), (), imported_count) = tokio::join!(download, transcribe, import);
+    runlog::log(&source.name, format!("Imported {} of {} new lesson(s)", imported_count, total));
+    let failures = failures.take();
+    context.metrics.borrow_mut().items_failed += failures.len();
+    if !failures.is_empty() {
+        let mut state = state.borrow_mut();
+        for failure in failures {
+            state.record_failure(&source.name, failure);
+        }
+        if let Err(e) = state.write(context.state_path) {
+            eprintln!("Error writing state file {}: {}", context.state_path, e);
+        }
+    }
+    imported_count
+}
+
+/// Record that none of a lesson's items could be imported, and why, so they
+/// can be retried later.
+fn record_failed_lesson(
+    failures: &std::cell::RefCell<Vec<state::FailedItem>>,
+    lesson: &LessonItems,
+    language: &str,
+    error: &str,
+) {
+    let mut failures = failures.borrow_mut();
+    for item in &lesson.items {
+        let title = item.title().unwrap_or_default();
+        failures.push(failed_item(item, &title, language, error.to_string()));
+    }
+}
+
+/// Record what we need to retry an item later.
+fn failed_item(item: &source::SourceItem, title: &str, language: &str, error: String) -> state::FailedItem {
+    state::FailedItem {
+        title: title.to_string(),
+        guid: item.guid(),
+        url: item.get_audio_link().unwrap_or_default(),
+        error,
+        language: Some(language.to_string()),
+    }
+}
+
+/// The total cost so far of the API clients used for a sync, or None if any
+/// of them used a model we don't know the price of.
+fn sync_cost(openai_client: &openai::OpenAI, postprocessor: &postprocess::AnyPostprocessor) -> Option<f64> {
+    let postprocessing_cost = match postprocessor.openai_client() {
+        Some(client) => client.cost()?,
+        None => 0.0,
+    };
+    Some(openai_client.cost()? + postprocessing_cost)
+}
+
+/// Print what a sync actually used, next to what we expected it to cost.
+fn print_usage_summary(
+    openai_client: &openai::OpenAI,
+    postprocessor: &postprocess::AnyPostprocessor,
+    estimated_cost: Option<f64>,
+) {
+    let transcription = openai_client.usage();
+    let postprocessing = postprocessor.openai_client().map(|client| client.usage()).unwrap_or_default();
+    println!("Usage summary:");
+    println!("  Audio transcribed: {:.1} minutes", transcription.audio_seconds / 60.0);
+    println!(
+        "  Postprocessing tokens: {} prompt, {} completion",
+        postprocessing.prompt_tokens, postprocessing.completion_tokens
+    );
+    match estimated_cost {
+        Some(cost) => println!("  Estimated cost: ${:.4}", cost),
+        None => println!("  Estimated cost: unknown (no history yet)"),
+    }
+    match sync_cost(openai_client, postprocessor) {
+        Some(cost) => println!("  Actual cost: ${:.4}", cost),
+        None => println!("  Actual cost: unknown (no prices for the configured models)"),
+    }
+}
+
+/// A minimal line-based diff of two texts, in the style of `diff -u` without
+/// the hunk headers.
+fn line_diff(old: &str, new: &str) -> Vec<String> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    // lcs[i][j] is the length of the longest common subsequence of old[i..]
+    // and new[j..].
+    let mut lcs = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut lines = vec![];
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            lines.push(format!(" {}", old[i]));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            lines.push(format!("-{}", old[i]));
+            i += 1;
+        } else {
+            lines.push(format!("+{}", new[j]));
+            j += 1;
+        }
+    }
+    lines
+}
+
+/// Describe the tokens (and cost, if known) a post-processing request used.
+fn describe_usage(postprocessed: &openai::Postprocessed, model: &str) -> String {
+    match (&postprocessed.usage, postprocessed.cost(model)) {
+        (Some(usage), Some(cost)) => format!(
+            "{} prompt + {} completion tokens, ~${:.4}",
+            usage.prompt_tokens, usage.completion_tokens, cost
+        ),
+        (Some(usage), None) => format!(
+            "{} prompt + {} completion tokens",
+            usage.prompt_tokens, usage.completion_tokens
+        ),
+        (None, _) => "unknown token usage".to_string(),
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    report::set_strict(cli.strict);
+    if cli.json_errors {
+        report::set_json_errors(true);
+        // Anything we didn't see coming still ends up as an error object.
+        std::panic::set_hook(Box::new(|info| {
+            fatal("internal", None, info);
+        }));
+    }
+
+    if let MainSubcommand::Init { force } = cli.subcommand {
+        if let Err(e) = config::write_starter_config(&cli.config_file, force) {
+            let hint = match e.kind() {
+                std::io::ErrorKind::AlreadyExists => " (use --force to overwrite it)",
+                _ => "",
+            };
+            fatal("config", None, format!("Error writing {}: {}{}", cli.config_file, e, hint));
+        }
+        println!("Wrote {}; fill in your API keys there, then add some sources", cli.config_file);
+        return;
+    }
+
+    // First make sure the configuration file exists
+    if let Err(e) = config::is_remote(&cli.config_file) {
+        fatal("config", None, format!("Error reading configuration file: {}", e));
+    }
+    if !config::LqcliConfig::exists(&cli.config_file).await {
+        fatal(
+            "config",
+            None,
+            format!("Configuration file {} does not exist (run `lqcli init` to create one)", cli.config_file),
+        );
+    }
+
+    // Try to read the configuration file
+    let config = match config::LqcliConfig::read(&cli.config_file, cli.profile.as_deref()).await {
+        Ok(config) => config,
+        Err(e) => {
+            fatal("config", None, format!("Error reading configuration file: {}", e));
+        }
+    };
+
+    for warning in config.validate() {
+        report::warn("config", None, warning);
+    }
+    if let Some(dir) = &config.log_dir {
+        runlog::set_dir(shellexpand::tilde(dir).as_ref(), config.log_max_bytes, config.log_keep);
+    }
+
+    let lingq_client = lingq::LingqClient::new(&config.lingq);
+    let postprocessor = match postprocess::AnyPostprocessor::from_config(&config) {
+        Ok(postprocessor) => postprocessor,
+        Err(e) => {
+            fatal("config", None, format!("Error in configuration file: {}", e));
+        }
+    };
+    let transcript_cache = config
+        .transcript_cache_dir
+        .as_ref()
+        .map(|dir| cache::TranscriptCache::new(shellexpand::tilde(dir).as_ref()));
+    let deepgram = config.deepgram.clone().map(deepgram::Deepgram::new);
+    let local_whisper = config.local_whisper.clone().map(local_whisper::LocalWhisper::new);
+
+    match cli.subcommand {
+        MainSubcommand::Transcribe(args) => {
+            if args.import {
+                check_lingq_api_key(&lingq_client).await;
+            }
+            let local = match (args.local, local_whisper.as_ref()) {
+                (true, None) => fatal("config", None, "--local needs a [local_whisper] section in the configuration"),
+                (true, Some(local)) => Some(local),
+                (false, _) => None,
+            };
+            let streamed = matches!(args.download_method, fetch::DownloadMethod::Direct) && args.range.is_whole();
+            let (audio, transcript) = match local {
+                Some(local) if streamed => {
+                    let download = match fetch::direct_stream(&args.url, config.download_rate()).await {
+                        Ok(download) => download,
+                        Err(e) => {
+                            fatal(e.kind(), None, format!("Error downloading {}: {}", args.url, e));
+                        }
+                    };
+                    match local.transcribe_stream(download, &args.language).await {
+                        Ok((audio, transcript)) if transcript.is_empty() => (audio, Err(openai::ApiError::Empty)),
+                        Ok((audio, transcript)) => (audio, Ok(transcript)),
+                        Err(e) => {
+                            fatal("transcribe", None, format!("Error transcribing {}: {}", args.url, e));
+                        }
+                    }
+                }
+                _ => {
+                    let item = source::SourceItem::from_url_and_title(&args.url, "Unknown");
+                    let options = fetch::DownloadOptions {
+                        max_rate: config.download_rate(),
+                        until: args.range.end,
+                        ..Default::default()
+                    };
+                    let audio = match item.download_audio(args.download_method, options).await {
+                        Ok(audio) => audio,
+                        Err(e) => {
+                            fatal(e.kind(), None, format!("Error downloading {}: {}", args.url, e));
+                        }
+                    };
+                    let audio = args.range.clip(audio).await;
+                    let transcript = match local {
+                        Some(local) => local.transcribe(audio.clone(), &args.language).await,
+                        None => {
+                            let client = openai::OpenAI::new(config.openai.clone());
+                            client.transcribe(audio.clone(), None, None, Some(&args.language)).await
+                        }
+                    };
+                    (audio, transcript)
+                }
+            };
+            let transcript = match transcript {
+                Ok(transcript) => transcript,
+                Err(e @ openai::ApiError::Empty) => {
+                    fatal(e.kind(), None, "The transcript is empty (is the audio silent?)");
+                }
+                Err(e) => {
+                    fatal(e.kind(), None, format!("Error transcribing {}: {}", args.url, e));
+                }
+            };
+            let postprocessed = match postprocessor.postprocess(&transcript).await {
+                Ok(postprocessed) => postprocessed,
+                Err(e) => {
+                    fatal(e.kind(), None, format!("Post-processing the transcript failed: {}", e));
+                }
+            };
+            println!("{postprocessed}");
+            if let Some(output_dir) = &args.output_dir {
+                let stem = args.name.clone().unwrap_or_else(|| {
+                    let path = args.url.split(['?', '#']).next().unwrap_or_default();
+                    let last = path.trim_end_matches('/').rsplit('/').next().unwrap_or_default();
+                    let stem = last.rsplit_once('.').map(|(stem, _)| stem).unwrap_or(last);
+                    if stem.is_empty() { "transcript".to_string() } else { stem.to_string() }
+                });
+                let files: [(&str, &[u8]); 3] = [
+                    ("mp3", &audio),
+                    ("raw.txt", transcript.as_bytes()),
+                    ("txt", postprocessed.as_bytes()),
+                ];
+                match fetch::save_bundle(output_dir, &stem, &files, args.force) {
+                    Ok(paths) => {
+                        for path in paths {
+                            eprintln!("Wrote {}", path.display());
+                        }
+                    }
+                    Err(e) => {
+                        fatal("io", None, format!("Error saving to {}: {}", output_dir, e));
+                    }
+                }
+            }
+            if let (true, Some(course_id), Some(name)) = (args.import, args.course_id, &args.name) {
+                println!("Importing into LingQ...");
+                let lesson = NewLesson {
+                    source: None,
+                    language: &args.language,
+                    course_id,
+                    title: name,
+                    text: &postprocessed,
+                    description: None,
+                    level: None,
+                    extra_fields: &HashMap::new(),
+                };
+                let audio = audio_file(audio);
+                if let Err(e) = import_lesson(&lingq_client, &config, &lesson, Some(audio.path())).await {
+                    fatal(e.kind(), None, format!("Error creating lesson: {}", e));
+                }
+            }
+        }
+        MainSubcommand::TranscribeFeed(args) => {
+            let mut feed = match source::Feed::from_url(&args.url).await {
+                Ok(feed) => feed,
+                Err(e) => {
+                    fatal(e.kind(), None, format!("Error getting items for {}: {}", args.url, e));
+                }
+            };
+            let wanted = match args.order {
+                source::ItemOrder::Newest => args.count,
+                source::ItemOrder::Oldest => usize::MAX,
+            };
+            if let Err(e) = feed.fetch_older_pages(&args.url, Default::default(), wanted).await {
+                eprintln!("Error getting older items for {}: {}", args.url, e);
+            }
+            let mut items = feed.items(wanted);
+            source::sort_items(&mut items, args.order);
+            items.truncate(args.count);
+            let output_dir = shellexpand::tilde(&args.output_dir).to_string();
+            if let Err(e) = std::fs::create_dir_all(&output_dir) {
+                fatal("io", None, format!("Error creating {}: {}", output_dir, e));
+            }
+            let client = openai::OpenAI::new(config.openai.clone());
+            for (index, item) in items.into_iter().enumerate() {
+                let title = item.title().unwrap_or("untitled".to_string());
+                if item.get_audio_link().is_none() {
+                    eprintln!("Skipping {} (no audio)", title);
+                    continue;
+                }
+                println!("Transcribing {}...", title);
+                let options = fetch::DownloadOptions { max_rate: config.download_rate(), ..Default::default() };
+                let audio = match item.download_audio(args.download_method.clone(), options).await {
+                    Ok(audio) => audio,
+                    Err(e) => {
+                        eprintln!("Error downloading {}: {}", title, e);
+                        continue;
+                    }
+                };
+                let transcript = match client.transcribe(audio, None, None, Some(&args.language)).await {
+                    Ok(transcript) => transcript,
+                    Err(openai::ApiError::Empty) => {
+                        eprintln!("Skipping {}: the transcript is empty (is the audio silent?)", title);
+                        continue;
+                    }
+                    Err(e) => {
+                        eprintln!("Error transcribing {}: {}", title, e);
+                        continue;
+                    }
+                };
+                let postprocessed = match postprocessor.postprocess(&transcript).await {
+                    Ok(postprocessed) => postprocessed,
+                    Err(e) => {
+                        eprintln!("Skipping {}: post-processing failed: {}", title, e);
+                        continue;
+                    }
+                };
+                let path = std::path::Path::new(&output_dir)
+                    .join(format!("{}-{}.txt", index + 1, source::safe_filename(&title)));
+                match std::fs::write(&path, postprocessed) {
+                    Ok(()) => println!("Wrote {}", path.display()),
+                    Err(e) => eprintln!("Error writing {}: {}", path.display(), e),
+                }
+            }
+        }
+        MainSubcommand::DiffPrompt(args) => {
+            let candidate_prompt = match std::fs::read_to_string(shellexpand::tilde(&args.prompt_file).as_ref()) {
+                Ok(prompt) => prompt,
+                Err(e) => {
+                    fatal("io", None, format!("Error reading {}: {}", args.prompt_file, e));
+                }
+            };
+            let client = openai::OpenAI::new(config.openai.clone());
+            let transcript = match (&args.transcript, &args.url) {
+                (Some(path), _) => match std::fs::read_to_string(shellexpand::tilde(path).as_ref()) {
+                    Ok(transcript) => transcript,
+                    Err(e) => {
+                        fatal("io", None, format!("Error reading {}: {}", path, e));
+                    }
+                },
+                (None, Some(url)) => {
+                    let item = source::SourceItem::from_url_and_title(url, "Unknown");
+                    let options = fetch::DownloadOptions { max_rate: config.download_rate(), ..Default::default() };
+                    let audio = match item.download_audio(args.download_method, options).await {
+                        Ok(audio) => audio,
+                        Err(e) => {
+                            fatal(e.kind(), None, format!("Error downloading {}: {}", url, e));
+                        }
+                    };
+                    match client.transcribe(audio, None, None, None).await {
+                        Ok(transcript) => transcript,
+                        Err(e @ openai::ApiError::Empty) => {
+                            fatal(e.kind(), None, "The transcript is empty (is the audio silent?)");
+                        }
+                        Err(e) => {
+                            fatal(e.kind(), None, format!("Error transcribing {}: {}", url, e));
+                        }
+                    }
+                }
+                (None, None) => unreachable!("clap requires --transcript or --url"),
+            };
+            let model = client.postprocessing_model().to_string();
+            let current = client
+                .postprocess_with_prompt(&transcript, client.postprocessing_prompt())
+                .await;
+            let candidate = client
+                .postprocess_with_prompt(&transcript, &candidate_prompt)
+                .await;
+            for (prompt, postprocessed) in [("current", &current), ("candidate", &candidate)] {
+                if let Err(e) = &postprocessed.text {
+                    eprintln!("Error post-processing with the {} prompt: {}", prompt, e);
+                }
+            }
+            let current_text = current.text.as_deref().unwrap_or_default();
+            let candidate_text = candidate.text.as_deref().unwrap_or_default();
+            println!("--- current prompt ({})", describe_usage(&current, &model));
+            println!("+++ candidate prompt ({})", describe_usage(&candidate, &model));
+            for line in line_diff(current_text, candidate_text) {
+                println!("{}", line);
+            }
+        }
+        MainSubcommand::Adhoc(args) => {
+            check_lingq_api_key(&lingq_client).await;
+            println!("We ride!");
+            let item = source::SourceItem::from_url_and_title(&args.url, &args.title);
+            println!("Downloading audio...");
+            let options = fetch::DownloadOptions {
+                max_rate: config.download_rate(),
+                until: args.range.end,
+                ..Default::default()
+            };
+            let audio = match item.download_audio(args.download_method, options).await {
+                Ok(audio) => audio,
+                Err(e) => {
+                    fatal(e.kind(), None, format!("Error downloading {}: {}", args.url, e));
+                }
+            };
+            let audio = args.range.clip(audio).await;
+            println!("Houston, we have audio.");
+            let client = openai::OpenAI::new(config.openai.clone());
+            let transcript = if args.skip_transcribe {
+                "".to_string()
+            } else if let Some(link) = &args.subtitles {
+                let Some(segments) = subtitle_segments(&args.title, link).await else {
+                    fatal("subtitles", None, format!("Couldn't use the subtitles from {}, not importing it", link));
+                };
+                match postprocess::postprocess_timestamped(&postprocessor, None, &segments).await {
+                    Ok(text) => text,
+                    Err(e) => {
+                        fatal(e.kind(), None, format!("Post-processing the subtitles failed ({}), not importing it", e));
+                    }
+                }
+            } else {
+                println!("Throwing audio at OpenAI...");
+                let transcript = match client.transcribe(audio.clone(), None, None, Some(&args.language)).await {
+                    Ok(transcript) => transcript,
+                    Err(e @ openai::ApiError::Empty) => {
+                        fatal(e.kind(), None, "The transcript is empty (is the audio silent?), not importing it");
+                    }
+                    Err(e) => {
+                        fatal(e.kind(), None, format!("Error transcribing {}: {}, not importing it", args.url, e));
+                    }
+                };
+                println!("We have a transcript.");
+                println!("Post-processing transcript...");
+                let postprocessed = match postprocessor.postprocess(&transcript).await {
+                    Ok(postprocessed) => postprocessed,
+                    Err(e) => {
+                        fatal(e.kind(), None, format!("Post-processing the transcript failed ({}), not importing it", e));
+                    }
+                };
+                println!("We've post-processed it.");
+                postprocessed
+            };
+            let lesson = NewLesson {
+                source: None,
+                language: &args.language,
+                course_id: args.course_id,
+                title: &args.title,
+                text: &transcript,
+                description: None,
+                level: args.level,
+                extra_fields: &HashMap::new(),
+            };
+            let audio = audio_file(audio);
+            if let Err(e) = import_lesson(&lingq_client, &config, &lesson, Some(audio.path())).await {
+                fatal(e.kind(), None, format!("Error creating lesson: {}", e));
+            }
+        }
+        MainSubcommand::Reprocess(args) => {
+            check_lingq_api_key(&lingq_client).await;
+            // The kind of the last thing to go wrong, if anything did.
+            let mut failed = None;
+            for lesson_id in args.lesson_ids {
+                let lesson = match lingq_client.get_lesson_text(&args.language, lesson_id).await {
+                    Ok(lesson) => lesson,
+                    Err(e) => {
+                        eprintln!("Error getting lesson {}: {}", lesson_id, e);
+                        failed = Some(e.kind());
+                        continue;
+                    }
+                };
+                println!("Post-processing {}...", lesson.title);
+                let text = match postprocessor.postprocess(&lesson.text).await {
+                    Ok(text) => text,
+                    Err(e) => {
+                        eprintln!("Error post-processing {}, leaving it alone: {}", lesson.title, e);
+                        failed = Some(e.kind());
+                        continue;
+                    }
+                };
+                if args.dry_run {
+                    println!("{}", text);
+                    continue;
+                }
+                match lingq_client.update_lesson_text(&args.language, lesson_id, &text).await {
+                    Ok(()) => println!("Updated {}", lesson.title),
+                    Err(e) => {
+                        eprintln!("Error updating {}: {}", lesson.title, e);
+                        failed = Some(e.kind());
+                    }
+                }
+            }
+            if let Some(kind) = failed {
+                fatal(kind, None, "Not every lesson could be reprocessed");
+            }
+        }
+        MainSubcommand::Config(ConfigSubcommand::Show) => {
+            println!("Configuration file: {}", cli.config_file);
+            println!("Profile: {}", cli.profile.as_deref().unwrap_or("(none)"));
+            if config.profiles.is_empty() {
+                println!("Available profiles: (none)");
+            } else {
+                println!("Available profiles:");
+                for name in config.profiles.keys() {
+                    let marker = if cli.profile.as_deref() == Some(name.as_str()) { " (active)" } else { "" };
+                    println!("  {}{}", name, marker);
+                }
+            }
+            println!("LingQ API version: {:?}", config.lingq.api_version);
+            println!("Post-processing via: {:?}", config.postprocess_via);
+            println!("Sources: {}", config.sources.len());
+        }
+        MainSubcommand::Init { .. } => unreachable!("init is handled before the configuration is read"),
+        MainSubcommand::Sources(subcommand) => match subcommand {
+            SourcesSubcommand::Rename { old, new } => {
+                if !config.sources.iter().any(|source| source.name == old) {
+                    fatal("not-found", Some(&old), format!("There is no source named {}", old));
+                }
+                if config.sources.iter().any(|source| source.name == new) {
+                    fatal("config", Some(&new), format!("There is already a source named {}", new));
+                }
+                let state_path = state::State::path_for(&cli.config_file);
+                let mut state = match state::State::read(&state_path) {
+                    Ok(state) => state,
+                    Err(e) => {
+                        fatal("state", None, format!("Error reading state file {}: {}", state_path, e));
+                    }
+                };
+                if let Err(e) = config::rename_source(&cli.config_file, &old, &new) {
+                    fatal("config", Some(&old), format!("Error renaming {} in {}: {}", old, cli.config_file, e));
+                }
+                state.rename_source(&old, &new);
+                if let Err(e) = state.write(&state_path) {
+                    // Put the configuration back, so the two still agree.
+                    let undone = config::rename_source(&cli.config_file, &new, &old);
+                    let config_note = match undone {
+                        Ok(()) => "the configuration file is unchanged".to_string(),
+                        Err(undo_error) => format!("{} is now called {} in the configuration file ({})", old, new, undo_error),
+                    };
+                    fatal("state", Some(&old), format!("Error writing state file {}: {}; {}", state_path, e, config_note));
+                }
+                println!("Renamed {} to {}", old, new);
+            }
+            SourcesSubcommand::Add { name, url, course_id, language, tags, content_type } => {
+                if let Some(Err(e)) = language.as_deref().map(lingq::check_language) {
+                    fatal("config", Some(&name), format!("Can't add {}: {}", name, e));
+                }
+                let new_source = config::NewSource {
+                    name: name.clone(),
+                    url,
+                    course_id,
+                    language,
+                    tags: tags.unwrap_or_default(),
+                    content_type,
+                };
+                if let Err(e) = config::add_source(&cli.config_file, &new_source) {
+                    fatal("config", Some(&name), format!("Error adding {} to {}: {}", name, cli.config_file, e));
+                }
+                println!("Added {} to {}", name, cli.config_file);
+            }
+            SourcesSubcommand::ExportOpml { file, tags, name } => {
+                let sources = config.filtered_sources(&tags.unwrap_or_default(), name.pattern().as_ref());
+                let path = shellexpand::tilde(&file).to_string();
+                if let Err(e) = std::fs::write(&path, opml::export(&sources)) {
+                    fatal("io", None, format!("Error writing {}: {}", path, e));
+                }
+                println!("Wrote {}", path);
+            }
+            SourcesSubcommand::AudioLinks { tags, name, format } => {
+                let mut rows = vec![];
+                for source in config.filtered_sources(&tags.unwrap_or_default(), name.pattern().as_ref()) {
+                    let latest = source_items(source, 1).await.and_then(|(items, _)| items.into_iter().next());
+                    rows.push(AudioLinkRow {
+                        source: source.name.clone(),
+                        title: latest.as_ref().and_then(|item| item.title()),
+                        audio_link: latest.as_ref().and_then(|item| item.get_audio_link()),
+                    });
+                }
+                match format {
+                    OutputFormat::Table => {
+                        let mut table = Table::new(rows);
+                        table.with(Style::modern())
+                            .modify(Rows::first(), Color::BOLD);
+                        println!("{}", table);
+                    }
+                    OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&rows).unwrap()),
+                }
+            }
+            SourcesSubcommand::List { tags, name, category } => {
+                let mut filtered_sources = config.filtered_sources(&tags.unwrap_or_default(), name.pattern().as_ref());
+                if let Some(categories) = category {
+                    let mut matching = vec![];
+                    for source in filtered_sources {
+                        if let Some((items, _)) = source_items(source, source.max_items(LATEST_ITEMS)).await {
+                            if items.iter().any(|item| item.has_any_category(&categories)) {
+                                matching.push(source);
+                            }
+                        }
+                    }
+                    filtered_sources = matching;
+                }
+                let mut table = Table::new(filtered_sources.clone());
+                let style = Style::modern()
+                    .horizontals([(1, HorizontalLine::inherit(Style::modern()).horizontal('═'))]);
+                table.with(style)
+                    .modify(Rows::first(), Color::BOLD);
+                println!("{}", table);
+            }
+            SourcesSubcommand::Retry { item, source } => {
+                check_lingq_api_key(&lingq_client).await;
+                let state_path = state::State::path_for(&cli.config_file);
+                let state = match state::State::read(&state_path) {
+                    Ok(state) => state,
+                    Err(e) => {
+                        fatal("state", None, format!("Error reading state file {}: {}", state_path, e));
+                    }
+                };
+                let mut failures = state.find_failures(&item);
+                failures.retain(|(name, _)| source.as_deref().is_none_or(|source| source == *name));
+                let (source_name, failure) = match failures.as_slice() {
+                    [] => fatal("not-found", None, format!("No failed item with the title or GUID \"{}\"", item)),
+                    [found] => *found,
+                    _ => {
+                        let names: Vec<&str> = failures.iter().map(|(name, _)| *name).collect();
+                        fatal(
+                            "ambiguous",
+                            None,
+                            format!(
+                                "Items called \"{}\" failed in more than one source ({}); say which with --source",
+                                item,
+                                names.join(", ")
+                            ),
+                        );
+                    }
+                };
+                let Some(source) = config.sources.iter().find(|source| source.name == source_name) else {
+                    fatal("not-found", Some(source_name), format!("Source {} is no longer in the configuration", source_name));
+                };
+                let Some(language) = failure.language.clone().or(source.language.clone()) else {
+                    fatal("config", Some(&source.name), format!("Don't know what language {} is in; set language for {}", failure.title, source.name));
+                };
+                println!("Retrying {} from {} (failed with: {})", failure.title, source.name, failure.error);
+                let retry_item = match source.content_type {
+                    source::ContentType::Syndication | source::ContentType::YoutubeChannel => {
+                        source::SourceItem::from_url_and_title(&failure.url, &failure.title)
+                    }
+                    source::ContentType::LocalDirectory => {
+                        source::SourceItem::from_file(std::path::Path::new(&failure.url))
+                    }
+                };
+
+                let lingq_client = lingq_client.with_request_delay(
+                    source.request_delay.unwrap_or(config.lingq.request_delay),
+                );
+                let openai_client = openai::OpenAI::new(config.openai.clone());
+                let postprocessor = postprocessor.with_prompt(source.postprocessing_prompt.as_deref());
+                let context = SyncContext {
+                    config: &config,
+                    lingq_client: &lingq_client,
+                    openai_client: &openai_client,
+                    postprocessor: &postprocessor,
+                    transcript_cache: transcript_cache.as_ref(),
+                    deepgram: deepgram.as_ref(),
+                    local_whisper: local_whisper.as_ref(),
+                    metrics: &Default::default(),
+                    state_path: &state_path,
+                };
+                let state = std::cell::RefCell::new(state);
+                if sync_new_items(&context, source, &language, vec![retry_item], &state).await == 0 {
+                    // What went wrong has been reported, and recorded again.
+                    let error = state
+                        .borrow()
+                        .find_failures(&item)
+                        .into_iter()
+                        .find(|(name, _)| *name == source.name)
+                        .map(|(_, failure)| failure.error.clone());
+                    let error = error.unwrap_or_else(|| "it wasn't imported".to_string());
+                    fatal("retry", Some(&source.name), format!("Retrying {} failed: {}", item, error));
+                }
+            }
+            SourcesSubcommand::Prefetch { tags, name, category } => {
+                let Some(cache) = transcript_cache.as_ref() else {
+                    fatal("config", None, "Prefetching needs somewhere to keep transcripts; set transcript_cache_dir");
+                };
+                check_lingq_api_key(&lingq_client).await;
+                let categories = category.unwrap_or_default();
+                let state_path = state::State::path_for(&cli.config_file);
+                let mut state = match state::State::read(&state_path) {
+                    Ok(state) => state,
+                    Err(e) => {
+                        fatal("state", None, format!("Error reading state file {}: {}", state_path, e));
+                    }
+                };
+                let openai_client = openai::OpenAI::new(config.openai.clone());
+                let mut transcribed = 0;
+                for source in config.filtered_sources(&tags.unwrap_or_default(), name.pattern().as_ref()) {
+                    println!("Prefetching source: {}", source.name);
+                    let limit = source.max_items(LATEST_ITEMS);
+                    let Some((items, declared_language)) = source_items(source, limit).await else {
+                        continue;
+                    };
+                    let Some(language) = source.language.clone().or(declared_language) else {
+                        eprintln!(
+                            "Skipping {}: it doesn't declare a language, so set language for it",
+                            source.name
+                        );
+                        runlog::log(&source.name, "Skipped: it doesn't declare a language, so set language for it");
+                        continue;
+                    };
+                    let merged_titles = state.merged_titles(&source.name);
+                    let lesson_titles = existing_titles(&lingq_client, source, &language, merged_titles).await;
+                    let mut new_items: Vec<source::SourceItem> = items
+                        .into_iter()
+                        .filter(|item| item_skip_reason(&config, source, item, &lesson_titles, &categories, &state).is_none())
+                        .collect();
+                    // As many as a sync would import.
+                    new_items.truncate(limit);
+                    let postprocessor = postprocessor.with_prompt(source.postprocessing_prompt.as_deref());
+                    let context = SyncContext {
+                        config: &config,
+                        lingq_client: &lingq_client,
+                        openai_client: &openai_client,
+                        postprocessor: &postprocessor,
+                        transcript_cache: Some(cache),
+                        deepgram: deepgram.as_ref(),
+                        local_whisper: local_whisper.as_ref(),
+                        metrics: &Default::default(),
+                        state_path: &state_path,
+                    };
+                    transcribed += prefetch_items(&context, cache, source, &language, new_items, &mut state).await;
+                }
+                println!("Transcribed {} item(s), ready for the next sync", transcribed);
+                if transcribed > 0 {
+                    print_usage_summary(&openai_client, &postprocessor, None);
+                }
+            }
+            SourcesSubcommand::Reconcile { tags, name, dry_run } => {
+                check_lingq_api_key(&lingq_client).await;
+                let state_path = state::State::path_for(&cli.config_file);
+                let mut state = match state::State::read(&state_path) {
+                    Ok(state) => state,
+                    Err(e) => {
+                        fatal("state", None, format!("Error reading state file {}: {}", state_path, e));
+                    }
+                };
+                let mut discrepancies = vec![];
+                // Sources left out by --tags or --name aren't unknown.
+                if tags.is_none() && name.name.is_none() {
+                    let names: Vec<&str> = config.sources.iter().map(|source| source.name.as_str()).collect();
+                    for unknown in state.unknown_sources(&names) {
+                        discrepancies.push((unknown, state::Discrepancy::UnknownSource));
+                    }
+                }
+                for source in config.filtered_sources(&tags.unwrap_or_default(), name.pattern().as_ref()) {
+                    println!("Checking source: {}", source.name);
+                    let Some((items, declared_language)) = source_items(source, source.max_items(LATEST_ITEMS)).await else {
+                        continue;
+                    };
+                    let Some(language) = source.language.clone().or(declared_language) else {
+                        eprintln!(
+                            "Skipping {}: it doesn't declare a language, so set language for it",
+                            source.name
+                        );
+                        continue;
+                    };
+                    let lesson_titles = match lingq_client.get_lesson_titles(&language, source.course_id).await {
+                        Ok(lesson_titles) => lesson_titles,
+                        Err(e) => {
+                            // Without them everything would look out of step.
+                            eprintln!("Skipping {}: error getting lesson titles: {}", source.name, e);
+                            continue;
+                        }
+                    };
+                    for discrepancy in state.reconcile(&source.name, &lesson_titles, &items) {
+                        // Once a source goes by GUID, a lesson with the same
+                        // title may well be a different item.
+                        if source.dedup == source::Dedup::Guid && matches!(discrepancy, state::Discrepancy::GuidNotRecorded { .. }) {
+                            continue;
+                        }
+                        discrepancies.push((source.name.clone(), discrepancy));
+                    }
+                }
+                for (source_name, discrepancy) in &discrepancies {
+                    println!("{}: {}", source_name, discrepancy);
+                }
+                if discrepancies.is_empty() {
+                    println!("The state file is in step with LingQ");
+                } else if dry_run {
+                    println!("Found {} discrepancy(ies); run without --dry-run to fix them", discrepancies.len());
+                } else {
+                    for (source_name, discrepancy) in &discrepancies {
+                        state.repair(source_name, discrepancy);
+                        runlog::log(source_name, format!("Reconciled: {}", discrepancy));
+                    }
+                    if let Err(e) = state.write(&state_path) {
+                        fatal("state", None, format!("Error writing state file {}: {}", state_path, e));
+                    }
+                    println!("Fixed {} discrepancy(ies) in {}", discrepancies.len(), state_path);
+                }
+            }
+            SourcesSubcommand::Sync {
+                tags,
+                name,
+                category,
+                dry_run,
+                explain,
+                count,
+                order,
+                due,
+                update_redirected_urls,
+                preview,
+                metrics: metrics_format,
+                jobs,
+            } => {
+                let started = std::time::Instant::now();
+                let run_metrics = std::cell::RefCell::new(metrics::RunMetrics::default());
+                check_lingq_api_key(&lingq_client).await;
+                let categories = category.unwrap_or_default();
+                let state_path = state::State::path_for(&cli.config_file);
+                let state = match state::State::read(&state_path) {
+                    Ok(state) => state,
+                    Err(e) => {
+                        fatal("state", None, format!("Error reading state file {}: {}", state_path, e));
+                    }
+                };
+                let openai_client = openai::OpenAI::new(config.openai.clone());
+                let state = std::cell::RefCell::new(state);
+                let decisions = std::cell::RefCell::new(vec![]);
+                let estimated_cost = std::cell::Cell::new(None);
+                let imported_any = std::cell::Cell::new(false);
+                // Get the filtered sources by tags
+                // source.tags will be a Tags(Option<Vec<String>>)
+                let filtered_sources = config.filtered_sources(&tags.unwrap_or_default(), name.pattern().as_ref());
+
+                // Sources sync side by side, up to --jobs at a time. They all
+                // share the LingQ client's request delay.
+                {
+                    let (config, openai_client, state_path, categories) = (&config, &openai_client, &state_path, &categories);
+                    let (transcript_cache, deepgram, run_metrics) = (transcript_cache.as_ref(), deepgram.as_ref(), &run_metrics);
+                    let local_whisper = local_whisper.as_ref();
+                    let (state, decisions, estimated_cost, imported_any) = (&state, &decisions, &estimated_cost, &imported_any);
+                    let (lingq_client, postprocessor) = (&lingq_client, &postprocessor);
+                    let jobs = usize::from(jobs);
+                    let sources = futures::stream::iter(filtered_sources);
+                    sources.for_each_concurrent(jobs, |source| async move {
+                        if due && !state.borrow().is_due(&source.name, source.poll_interval) {
+                            println!("Skipping {}: not due yet", source.name);
+                            runlog::log(&source.name, "Skipped: not due yet");
+                            return;
+                        }
+                        println!("Syncing source: {}", source.name);
+                        runlog::log(&source.name, format!("Syncing {} (lqcli {})", source.name, env!("CARGO_PKG_VERSION")));
+
+                        let lingq_client = lingq_client.with_request_delay(
+                            source.request_delay.unwrap_or(config.lingq.request_delay),
+                        );
+                        // --count wins over the source's own max_items.
+                        let limit = count.unwrap_or_else(|| source.max_items(LATEST_ITEMS));
+                        let wanted = match order {
+                            source::ItemOrder::Newest => limit,
+                            source::ItemOrder::Oldest => usize::MAX,
+                        };
+                        let Some((mut items, declared_language)) = source_items(source, wanted).await else {
+                            return;
+                        };
+                        if !matches!(source.content_type, source::ContentType::LocalDirectory) {
+                            source::sort_items(&mut items, order);
+                        }
+                        {
+                            let mut run_metrics = run_metrics.borrow_mut();
+                            run_metrics.feeds_fetched += 1;
+                            run_metrics.items_considered += items.len();
+                        }
+                        let Some(language) = source.language.clone().or(declared_language) else {
+                            eprintln!(
+                                "Skipping {}: it doesn't declare a language, so set language for it",
+                                source.name
+                            );
+                            return;
+                        };
+
+                        let merged_titles = state.borrow().merged_titles(&source.name).to_vec();
+                        let lesson_titles = existing_titles(&lingq_client, source, &language, &merged_titles).await;
+
+                        // Run every skip check before touching any audio, so
+                        // nothing is downloaded for items that won't be imported.
+                        let mut new_items: Vec<source::SourceItem> = {
+                            let state = state.borrow();
+                            items
+                                .into_iter()
+                                .filter(|item| {
+                                    let reason = item_skip_reason(config, source, item, &lesson_titles, categories, &state);
+                                    let title = item.title().unwrap_or("<unknown>".to_string());
+                                    let decision = match &reason {
+                                        Some(reason) => format!("skip ({})", reason),
+                                        None => "import (new)".to_string(),
+                                    };
+                                    runlog::log(&source.name, format!("Considered {}: {}", title, decision));
+                                    if explain {
+                                        decisions.borrow_mut().push(ItemDecision { source: source.name.clone(), title, decision });
+                                        return reason.is_none();
+                                    }
+                                    match reason {
+                                        Some(source::SkipReason::TitleMatch(title)) => {
+                                            println!("Skipping existing lesson: {}", title);
+                                            false
+                                        }
+                                        Some(source::SkipReason::GuidMatch(_)) => {
+                                            println!("Skipping already imported item: {}", title);
+                                            false
+                                        }
+                                        Some(source::SkipReason::Downloaded(path)) => {
+                                            println!("Skipping already downloaded item: {}", path.display());
+                                            false
+                                        }
+                                        Some(source::SkipReason::NotInCategory | source::SkipReason::TitleFiltered) => false,
+                                        Some(reason) => {
+                                            report::warn(
+                                                "skipped-item",
+                                                Some(&source.name),
+                                                format!("skipping item in {} ({})", source.name, reason),
+                                            );
+                                            false
+                                        }
+                                        None => true,
+                                    }
+                                })
+                                .collect()
+                        };
+                        // Local directories list every file rather than just the
+                        // latest, and so does a feed with --order oldest, so only
+                        // take the first few new ones each time.
+                        if new_items.len() > limit {
+                            let left = new_items.len() - limit;
+                            runlog::log(&source.name, format!("Leaving {} new item(s) for later syncs", left));
+                            new_items.truncate(limit);
+                        }
+
+                        if explain {
+                            return;
+                        }
+
+                        if let Some(seconds) = preview {
+                            match new_items.first() {
+                                Some(item) => preview_item(config, openai_client, source, item, seconds).await,
+                                None => println!("Nothing new to preview for {}", source.name),
+                            }
+                            return;
+                        }
+
+                        if dry_run {
+                            for item in new_items {
+                                // skip_reason() guarantees a title, and audio
+                                // unless the source doesn't require it.
+                                let title = item.title().unwrap_or_default();
+                                match item.get_audio_link() {
+                                    Some(audio_link) => println!("Would import {}: {}", title, audio_link),
+                                    None => println!("Would import {} (text only)", title),
+                                }
+                            }
+                            return;
+                        }
+
+                        // Post-processed with the source's own prompt, if it has one.
+                        // Both clients keep a tally of just this source's usage,
+                        // so what it cost isn't mixed up with the sources syncing
+                        // alongside it.
+                        let postprocessor = postprocessor.with_prompt(source.postprocessing_prompt.as_deref()).scoped();
+                        let openai_client = openai_client.scoped();
+                        let context = SyncContext {
+                            config,
+                            lingq_client: &lingq_client,
+                            openai_client: &openai_client,
+                            postprocessor: &postprocessor,
+                            transcript_cache,
+                            deepgram,
+                            local_whisper,
+                            metrics: run_metrics,
+                            state_path,
+                        };
+                        {
+                            let mut state = state.borrow_mut();
+                            if let Some(cost) = state.estimate_cost(&source.name, new_items.len()) {
+                                estimated_cost.set(Some(estimated_cost.get().unwrap_or(0.0) + cost));
+                            }
+                            state.record_sync(&source.name, !new_items.is_empty());
+                            if let Err(e) = state.write(state_path) {
+                                eprintln!("Error writing state file {}: {}", state_path, e);
+                            }
+                        }
+                        let imported = sync_new_items(&context, source, &language, new_items, state).await;
+                        if imported > 0 {
+                            imported_any.set(true);
+                            if let Some(cost) = sync_cost(&openai_client, &postprocessor) {
+                                let mut state = state.borrow_mut();
+                                state.record_item_cost(&source.name, cost / imported as f64);
+                                if let Err(e) = state.write(state_path) {
+                                    eprintln!("Error writing state file {}: {}", state_path, e);
+                                }
+                            }
+                        }
+                    }).await;
+                }
+
+                if imported_any.get() {
+                    print_usage_summary(&openai_client, &postprocessor, estimated_cost.get());
+                }
+
+                if update_redirected_urls {
+                    for (old, new) in source::moved_feeds() {
+                        match config::replace_source_url(&cli.config_file, &old, &new) {
+                            Ok(true) => println!("Changed {} to {} in {}", old, new, cli.config_file),
+                            Ok(false) => eprintln!("Couldn't find {} in {} to change it", old, cli.config_file),
+                            Err(e) => eprintln!("Error changing {} in {}: {}", old, cli.config_file, e),
+                        }
+                    }
+                }
+
+                let items_failed = run_metrics.borrow().items_failed;
+                let raw_imports = run_metrics.borrow().raw_imports.clone();
+                if !raw_imports.is_empty() {
+                    println!("Imported with raw transcripts, since post-processing failed:");
+                    for title in raw_imports {
+                        println!("  {}", title);
+                    }
+                }
+
+                if let Some(format) = metrics_format {
+                    let mut run_metrics = run_metrics.into_inner();
+                    let transcription = openai_client.usage();
+                    let postprocessing = postprocessor.openai_client().map(|client| client.usage()).unwrap_or_default();
+                    run_metrics.whisper_seconds = transcription.audio_seconds;
+                    // Translations go through the transcription client.
+                    run_metrics.prompt_tokens = transcription.prompt_tokens + postprocessing.prompt_tokens;
+                    run_metrics.completion_tokens = transcription.completion_tokens + postprocessing.completion_tokens;
+                    run_metrics.total_seconds = started.elapsed().as_secs_f64();
+                    match format {
+                        OutputFormat::Table => println!("{}", run_metrics.to_table()),
+                        OutputFormat::Json => println!("{}", run_metrics.to_json()),
+                    }
+                }
+
+                if explain {
+                    let mut table = Table::new(decisions.into_inner());
+                    table.with(Style::modern())
+                        .modify(Rows::first(), Color::BOLD);
+                    println!("{}", table);
+                }
 
-                    // let resp = openai::postprocess(
-                    //     "hallo das hier ist ein test",
-                    //     config.openai.postprocessing_prompt.as_str(),
-                    //     &config.openai
-                    // ).await.unwrap();
-                    // println!("{}", resp);
+                // Each failure was reported as it happened, and the run went
+                // on with the other items, but scripts need to know.
+                if cli.strict && items_failed > 0 {
+                    fatal(
+                        "item-failed",
+                        None,
+                        format!(
+                            "Error (--strict): {} item(s) couldn't be imported; see `lqcli sources retry`",
+                            items_failed
+                        ),
+                    );
                 }
             }
         },
@@ -0,0 +1,62 @@
+//! Counts and timings collected over one run, for `sources sync --metrics`.
+
+use serde::Serialize;
+use tabled::{settings::Style, Table, Tabled};
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct RunMetrics {
+    /// Sources whose items were fetched successfully.
+    pub feeds_fetched: usize,
+    /// Items looked at, whether or not they were imported.
+    pub items_considered: usize,
+    pub items_imported: usize,
+    /// Items which couldn't be downloaded, transcribed or imported, and are
+    /// recorded for `sources retry`.
+    pub items_failed: usize,
+    /// Titles of lessons imported with their raw transcripts, because
+    /// post-processing failed (see on_postprocess_failure).
+    pub raw_imports: Vec<String>,
+    pub bytes_downloaded: u64,
+    /// Seconds of audio sent to Whisper.
+    pub whisper_seconds: f64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// Wall-clock seconds spent in each phase. The phases overlap, so these
+    /// can add up to more than total_seconds.
+    pub download_seconds: f64,
+    pub transcribe_seconds: f64,
+    pub import_seconds: f64,
+    pub total_seconds: f64,
+}
+
+#[derive(Tabled)]
+struct MetricRow {
+    metric: &'static str,
+    value: String,
+}
+
+impl RunMetrics {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    pub fn to_table(&self) -> String {
+        let seconds = |seconds: f64| format!("{:.1}s", seconds);
+        let rows = vec![
+            MetricRow { metric: "Feeds fetched", value: self.feeds_fetched.to_string() },
+            MetricRow { metric: "Items considered", value: self.items_considered.to_string() },
+            MetricRow { metric: "Items imported", value: self.items_imported.to_string() },
+            MetricRow { metric: "Items failed", value: self.items_failed.to_string() },
+            MetricRow { metric: "Imported raw", value: self.raw_imports.len().to_string() },
+            MetricRow { metric: "Bytes downloaded", value: self.bytes_downloaded.to_string() },
+            MetricRow { metric: "Whisper audio", value: seconds(self.whisper_seconds) },
+            MetricRow { metric: "Prompt tokens", value: self.prompt_tokens.to_string() },
+            MetricRow { metric: "Completion tokens", value: self.completion_tokens.to_string() },
+            MetricRow { metric: "Downloading", value: seconds(self.download_seconds) },
+            MetricRow { metric: "Transcribing", value: seconds(self.transcribe_seconds) },
+            MetricRow { metric: "Importing", value: seconds(self.import_seconds) },
+            MetricRow { metric: "Total", value: seconds(self.total_seconds) },
+        ];
+        Table::new(rows).with(Style::modern()).to_string()
+    }
+}
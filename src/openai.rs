@@ -1,38 +1,316 @@
 /// Use OpenAI to postprocess a transcript.
 
+use crate::audio;
 use crate::config;
 
 use async_openai::{
     types::AudioInput,
+    types::AudioResponseFormat,
     types::CreateChatCompletionRequestArgs,
     types::CreateChatCompletionRequest,
     types::CreateTranscriptionRequestArgs,
     types::CreateTranscriptionRequest,
+    types::CreateTranscriptionResponseVerboseJson,
     types::ChatCompletionRequestSystemMessageArgs,
     types::ChatCompletionRequestUserMessageArgs,
+    types::CompletionUsage,
+    error::OpenAIError,
     Client,
     config::OpenAIConfig as LibOpenAIConfig
 };
+use std::fmt::Display;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// A little under the biggest audio file (25 MB) the transcription API
+/// accepts. Anything bigger is split up and transcribed a piece at a time.
+const MAX_AUDIO_BYTES: usize = 24_000_000;
+
+/// Known chat model prices, in US dollars per million (prompt, completion)
+/// tokens. Only used for rough cost estimates.
+const CHAT_MODEL_PRICES: &[(&str, f64, f64)] = &[
+    ("gpt-4o-mini", 0.15, 0.60),
+    ("gpt-4o", 2.50, 10.00),
+];
+
+/// Known transcription model prices, in US dollars per minute of audio.
+const TRANSCRIPTION_MODEL_PRICES: &[(&str, f64)] = &[
+    ("whisper-1", 0.006),
+];
+
+/// Prompt for translating a transcript; {language} is replaced with the
+/// language to translate into.
+const TRANSLATION_PROMPT: &str = "Translate the following transcript into \
+{language}. Keep the paragraph breaks and any lines starting with \"## \" \
+(translating the text after them). Reply with only the translation.";
+
+/// How long to leave a rate-limited API key alone before trying it again.
+const RATE_LIMIT_BACKOFF: Duration = Duration::from_secs(60);
 
 pub struct OpenAI {
     config: config::OpenaiConfig,
-    client: Client<LibOpenAIConfig>,
+
+    /// One client per configured API key.
+    clients: Vec<Client<LibOpenAIConfig>>,
+
+    /// Index of the client to use for the next request. Shared with scoped
+    /// copies (see scoped), as is rate_limited_at.
+    next_client: Arc<AtomicUsize>,
+
+    /// For each client, when it was last rate limited (if it's still backing
+    /// off).
+    rate_limited_at: Arc<Mutex<Vec<Option<Instant>>>>,
+
+    /// Everything this client has used so far, followed by the running
+    /// totals of any it was scoped from, which its usage is added to too.
+    usage: Vec<Arc<Mutex<Usage>>>,
+}
+
+/// A running total of what requests have used, as reported by the API.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Usage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub audio_seconds: f64,
+
+    /// What the audio cost to transcribe, which is tallied as it goes since
+    /// different sources can use different Whisper models.
+    pub transcription_cost: f64,
+
+    /// Whether any audio was transcribed with a model we don't know the
+    /// price of.
+    pub unpriced_audio: bool,
+}
+
+/// Cost in US dollars of a chat request, if we know the model's prices.
+fn chat_cost(model: &str, prompt_tokens: u64, completion_tokens: u64) -> Option<f64> {
+    let (_, prompt_price, completion_price) = CHAT_MODEL_PRICES
+        .iter()
+        .find(|(name, _, _)| *name == model)?;
+    Some((prompt_tokens as f64 * prompt_price + completion_tokens as f64 * completion_price) / 1_000_000.0)
+}
+
+/// Cost in US dollars of transcribing some audio, if we know the model's
+/// price.
+fn transcription_cost(model: &str, audio_seconds: f64) -> Option<f64> {
+    let (_, price) = TRANSCRIPTION_MODEL_PRICES
+        .iter()
+        .find(|(name, _)| *name == model)?;
+    Some(audio_seconds / 60.0 * price)
+}
+
+/// Whether an error means the API key is out of quota or being rate limited,
+/// in which case a different key might succeed.
+fn is_rate_limited(err: &OpenAIError) -> bool {
+    match err {
+        OpenAIError::ApiError(err) => {
+            err.code.as_deref() == Some("rate_limit_exceeded")
+                || err.r#type.as_deref() == Some("insufficient_quota")
+        }
+        _ => false,
+    }
+}
+
+/// The ISO-639-1 code Whisper wants for a language code like "de" or
+/// "pt-BR", or None if it doesn't look like one (e.g. "yue"), in which case
+/// Whisper is left to detect the language itself.
+pub(crate) fn whisper_language(language: &str) -> Option<String> {
+    let primary = language.split(['-', '_']).next()?.to_ascii_lowercase();
+    Some(primary).filter(|code| code.len() == 2 && code.chars().all(|c| c.is_ascii_lowercase()))
+}
+
+/// A piece of a transcript and when it starts in the audio.
+pub struct Segment {
+    /// Seconds from the start of the audio.
+    pub start: f32,
+    pub text: String,
+}
+
+/// Why a request didn't give us any text. Other transcription services
+/// report their failures this way too, so they can be told apart from an
+/// empty transcript.
+#[derive(Debug)]
+pub enum ApiError {
+    /// The request failed, after any retries.
+    Request(OpenAIError),
+    /// Deepgram's API failed.
+    Deepgram(reqwest::Error),
+    /// whisper.cpp failed, or couldn't be run.
+    Local(std::io::Error),
+    /// The reply had no text in it, e.g. because the audio is silent.
+    Empty,
+}
+
+impl ApiError {
+    /// A short, stable name for the kind of error, for machine-readable
+    /// output.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ApiError::Request(_) => "openai",
+            ApiError::Deepgram(_) => "deepgram",
+            ApiError::Local(_) => "local-whisper",
+            ApiError::Empty => "empty-reply",
+        }
+    }
+}
+
+impl From<OpenAIError> for ApiError {
+    fn from(err: OpenAIError) -> Self {
+        ApiError::Request(err)
+    }
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ApiError::Request(err) => write!(f, "{}", err),
+            ApiError::Deepgram(err) => write!(f, "Deepgram: {}", err),
+            ApiError::Local(err) => write!(f, "whisper.cpp: {}", err),
+            ApiError::Empty => write!(f, "the reply was empty"),
+        }
+    }
+}
+
+/// The output of a post-processing request, along with what it cost.
+pub struct Postprocessed {
+    pub text: Result<String, ApiError>,
+    pub usage: Option<CompletionUsage>,
+}
+
+impl Postprocessed {
+    /// Estimated cost of the request in US dollars, if we know the model's
+    /// prices.
+    pub fn cost(&self, model: &str) -> Option<f64> {
+        let usage = self.usage.as_ref()?;
+        chat_cost(model, usage.prompt_tokens.into(), usage.completion_tokens.into())
+    }
 }
 
 impl OpenAI {
     pub fn new(config: config::OpenaiConfig) -> Self {
-        let api_key = config.api_key.clone();
-        let client_config = LibOpenAIConfig::new().with_api_key(api_key);
-        let client = Client::with_config(client_config);
-        Self { config, client }
+        let api_keys = config.all_api_keys();
+        let clients: Vec<Client<LibOpenAIConfig>> = api_keys
+            .into_iter()
+            .map(|api_key| {
+                let mut client_config = LibOpenAIConfig::new().with_api_key(api_key);
+                if let Some(api_base) = &config.api_base {
+                    client_config = client_config.with_api_base(api_base);
+                }
+                let client = Client::with_config(client_config);
+                if config.api_keys.is_some() {
+                    // Don't let the library sit retrying a rate-limited key
+                    // when we could just move on to the next one.
+                    client.with_backoff(
+                        backoff::ExponentialBackoffBuilder::new()
+                            .with_max_elapsed_time(Some(Duration::ZERO))
+                            .build(),
+                    )
+                } else {
+                    client
+                }
+            })
+            .collect();
+        let rate_limited_at = Arc::new(Mutex::new(vec![None; clients.len()]));
+        Self {
+            config,
+            clients,
+            next_client: Arc::new(AtomicUsize::new(0)),
+            rate_limited_at,
+            usage: vec![Arc::default()],
+        }
+    }
+
+    /// A copy of this client (sharing its API keys and their rate limits)
+    /// which keeps its own tally of what it uses, e.g. to tell what one
+    /// source cost while others sync alongside it. What it uses still counts
+    /// towards this client's usage as well.
+    pub fn scoped(&self) -> Self {
+        Self {
+            config: self.config.clone(),
+            clients: self.clients.clone(),
+            next_client: Arc::clone(&self.next_client),
+            rate_limited_at: Arc::clone(&self.rate_limited_at),
+            usage: std::iter::once(Arc::default()).chain(self.usage.iter().cloned()).collect(),
+        }
     }
 
-    pub async fn postprocess(&self, transcript: &str) -> Option<String> {
+    /// What this client has used so far.
+    pub fn usage(&self) -> Usage {
+        *self.usage[0].lock().unwrap()
+    }
+
+    /// Add to this client's usage, and that of any it was scoped from.
+    fn record(&self, add: impl Fn(&mut Usage)) {
+        for usage in &self.usage {
+            add(&mut usage.lock().unwrap());
+        }
+    }
+
+    /// Cost in US dollars of everything this client has used so far, or None
+    /// if it used a model we don't know the price of.
+    pub fn cost(&self) -> Option<f64> {
+        let usage = self.usage();
+        let chat = if usage.prompt_tokens + usage.completion_tokens > 0 {
+            chat_cost(&self.config.postprocessing_model, usage.prompt_tokens, usage.completion_tokens)?
+        } else {
+            0.0
+        };
+        if usage.unpriced_audio {
+            return None;
+        }
+        Some(chat + usage.transcription_cost)
+    }
+
+    /// Pick the next client in round-robin order, preferring ones which
+    /// haven't recently been rate limited.
+    fn pick_client(&self) -> usize {
+        let rate_limited_at = self.rate_limited_at.lock().unwrap();
+        let start = self.next_client.fetch_add(1, Ordering::Relaxed);
+        (0..self.clients.len())
+            .map(|offset| (start + offset) % self.clients.len())
+            .find(|i| match rate_limited_at[*i] {
+                Some(at) => at.elapsed() >= RATE_LIMIT_BACKOFF,
+                None => true,
+            })
+            .unwrap_or(start % self.clients.len())
+    }
+
+    /// Run a request, rotating to the next API key whenever one is rate
+    /// limited, until every key has been tried once.
+    async fn with_client<T, F, Fut>(&self, request: F) -> Result<T, OpenAIError>
+    where
+        F: Fn(Client<LibOpenAIConfig>) -> Fut,
+        Fut: Future<Output = Result<T, OpenAIError>>,
+    {
+        let mut attempts = 0;
+        loop {
+            let i = self.pick_client();
+            match request(self.clients[i].clone()).await {
+                Err(err) if is_rate_limited(&err) && attempts + 1 < self.clients.len() => {
+                    self.rate_limited_at.lock().unwrap()[i] = Some(Instant::now());
+                    attempts += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Post-process a transcript with `prompt`, e.g. a source's own
+    /// postprocessing_prompt or else openai.postprocessing_prompt.
+    pub async fn postprocess(&self, transcript: &str, prompt: &str) -> Result<String, ApiError> {
+        self.postprocess_with_prompt(transcript, prompt).await.text
+    }
+
+    /// Post-process a transcript with `prompt`, keeping what the request
+    /// cost.
+    pub async fn postprocess_with_prompt(&self, transcript: &str, prompt: &str) -> Postprocessed {
         let model = self.config.postprocessing_model.clone();
         let request: CreateChatCompletionRequest = CreateChatCompletionRequestArgs::default()
             .messages([
                 ChatCompletionRequestSystemMessageArgs::default()
-                    .content(self.config.postprocessing_prompt.clone())
+                    .content(prompt)
                     .build()
                     .unwrap()
                     .into(),
@@ -45,18 +323,169 @@ impl OpenAI {
             .model(model)
             .build()
             .unwrap();
-        let response = self.client.chat().create(request).await.unwrap();
-        response.choices.first().unwrap().message.content.clone()
+        let response = self
+            .with_client(|client| {
+                let request = request.clone();
+                async move { client.chat().create(request).await }
+            })
+            .await;
+        let response = match response {
+            Ok(response) => response,
+            Err(e) => return Postprocessed { text: Err(e.into()), usage: None },
+        };
+        if let Some(usage) = &response.usage {
+            self.record(|total| {
+                total.prompt_tokens += u64::from(usage.prompt_tokens);
+                total.completion_tokens += u64::from(usage.completion_tokens);
+            });
+        }
+        let text = response.choices.first().and_then(|choice| choice.message.content.clone());
+        Postprocessed {
+            text: text.filter(|text| !text.trim().is_empty()).ok_or(ApiError::Empty),
+            usage: response.usage,
+        }
+    }
+
+    /// Translate a transcript into `language` (e.g. "English"), with the
+    /// post-processing model.
+    pub async fn translate(&self, transcript: &str, language: &str) -> Result<String, ApiError> {
+        let prompt = TRANSLATION_PROMPT.replace("{language}", language);
+        self.postprocess_with_prompt(transcript, &prompt).await.text
     }
 
-    pub async fn transcribe(&self, audio: Vec<u8>) -> Option<String> {
-        let model = self.config.whisper_model.clone();
-        let request: CreateTranscriptionRequest = CreateTranscriptionRequestArgs::default()
-            .file(AudioInput::from_vec_u8("in.mp3".to_string(), audio))
+    pub fn postprocessing_model(&self) -> &str {
+        &self.config.postprocessing_model
+    }
+
+    pub fn postprocessing_prompt(&self) -> &str {
+        &self.config.postprocessing_prompt
+    }
+
+    pub fn whisper_model(&self) -> &str {
+        &self.config.whisper_model
+    }
+
+    pub fn whisper_temperature(&self) -> Option<f32> {
+        self.config.whisper_temperature
+    }
+
+    /// A transcription request for some audio, with `model` and
+    /// `temperature` if given or the configured ones otherwise, hinting that
+    /// the audio is in `language` if given.
+    fn transcription_request(
+        &self,
+        audio: Vec<u8>,
+        model: &str,
+        temperature: Option<f32>,
+        language: Option<&str>,
+    ) -> CreateTranscriptionRequest {
+        let mut args = CreateTranscriptionRequestArgs::default();
+        // The verbose response is the only one which says how long the audio
+        // was, which is what transcription is billed by.
+        args.file(AudioInput::from_vec_u8("in.mp3".to_string(), audio))
             .model(model)
-            .build()
-            .unwrap();
-        let response = self.client.audio().transcribe(request).await.unwrap();
-        Some(response.text)
+            .response_format(AudioResponseFormat::VerboseJson);
+        if let Some(temperature) = temperature.or(self.config.whisper_temperature) {
+            args.temperature(temperature);
+        }
+        if let Some(language) = language.and_then(whisper_language) {
+            args.language(language);
+        }
+        args.build().unwrap()
+    }
+
+    /// Add some transcribed audio to the running total.
+    fn record_audio(&self, model: &str, seconds: f64) {
+        let cost = transcription_cost(model, seconds);
+        self.record(|total| {
+            total.audio_seconds += seconds;
+            match cost {
+                Some(cost) => total.transcription_cost += cost,
+                None => total.unpriced_audio = true,
+            }
+        });
+    }
+
+    /// Transcribe some audio, a piece at a time if it's too big to send in
+    /// one go (see MAX_AUDIO_BYTES), returning the response for each piece
+    /// along with where the piece starts in the audio (in seconds).
+    async fn transcribe_pieces(
+        &self,
+        audio: Vec<u8>,
+        model: &str,
+        temperature: Option<f32>,
+        language: Option<&str>,
+    ) -> Result<Vec<(f64, CreateTranscriptionResponseVerboseJson)>, OpenAIError> {
+        let splitting = audio.clone();
+        let pieces = match audio::blocking(move || audio::split(&splitting, MAX_AUDIO_BYTES)).await {
+            Ok(pieces) => pieces,
+            Err(e) => {
+                eprintln!("Error splitting {} bytes of audio for transcription, sending it whole: {}", audio.len(), e);
+                vec![(0.0, audio)]
+            }
+        };
+        let mut responses = vec![];
+        for (start, piece) in pieces {
+            let request = self.transcription_request(piece, model, temperature, language);
+            let response = self
+                .with_client(|client| {
+                    let request = request.clone();
+                    async move { client.audio().transcribe_verbose_json(request).await }
+                })
+                .await?;
+            self.record_audio(model, f64::from(response.duration));
+            responses.push((start, response));
+        }
+        Ok(responses)
+    }
+
+    /// Transcribe some audio, with `model` and `temperature` if given or the
+    /// configured whisper_model and whisper_temperature otherwise. Giving
+    /// the `language` the audio is in makes for a much better transcript of
+    /// anything that isn't English. It's an ApiError::Empty if the transcript
+    /// is empty, e.g. because the audio is silent.
+    ///
+    /// Audio too big for the API is split up (at pauses, where it can be),
+    /// and the transcripts of the pieces put back together a line apart.
+    pub async fn transcribe(
+        &self,
+        audio: Vec<u8>,
+        model: Option<&str>,
+        temperature: Option<f32>,
+        language: Option<&str>,
+    ) -> Result<String, ApiError> {
+        let model = model.unwrap_or(&self.config.whisper_model).to_string();
+        let responses = self.transcribe_pieces(audio, &model, temperature, language).await?;
+        let texts: Vec<&str> = responses
+            .iter()
+            .map(|(_, response)| response.text.trim())
+            .filter(|text| !text.is_empty())
+            .collect();
+        Some(texts.join("\n")).filter(|text| !text.is_empty()).ok_or(ApiError::Empty)
+    }
+
+    /// Like transcribe, but keep the timestamp of each segment of the
+    /// transcript.
+    pub async fn transcribe_segments(
+        &self,
+        audio: Vec<u8>,
+        model: Option<&str>,
+        temperature: Option<f32>,
+        language: Option<&str>,
+    ) -> Result<Vec<Segment>, ApiError> {
+        let model = model.unwrap_or(&self.config.whisper_model).to_string();
+        let mut segments = vec![];
+        for (start, response) in self.transcribe_pieces(audio, &model, temperature, language).await? {
+            // Each piece's timestamps start again from zero. A reply without
+            // segments still has its text, timed from the start of the piece.
+            match response.segments {
+                Some(pieces) => segments.extend(pieces.into_iter().map(|segment| Segment {
+                    start: start as f32 + segment.start,
+                    text: segment.text,
+                })),
+                None => segments.push(Segment { start: start as f32, text: response.text }),
+            }
+        }
+        Ok(segments)
     }
 }
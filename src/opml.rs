@@ -0,0 +1,35 @@
+//! OPML, the format podcast apps use to share lists of feeds.
+
+use crate::source::{ContentType, Source};
+use html_escape::encode_double_quoted_attribute as attr;
+
+/// Namespace for the attributes lqcli adds to outlines, which other apps
+/// ignore.
+const LQCLI_NAMESPACE: &str = "https://github.com/relrod/lqcli";
+
+/// An OPML document listing the feeds of `sources`, each titled by its
+/// source's name and with its language (if set) in an lqcli:language
+/// attribute. Sources which aren't feeds (local directories) are left out.
+pub fn export(sources: &[&Source]) -> String {
+    let mut opml = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<opml version=\"2.0\" xmlns:lqcli=\"{}\">\n  <head>\n    <title>lqcli sources</title>\n  </head>\n  <body>\n",
+        LQCLI_NAMESPACE
+    );
+    for source in sources {
+        if !matches!(source.content_type, ContentType::Syndication) {
+            continue;
+        }
+        let language = match &source.language {
+            Some(language) => format!(" lqcli:language=\"{}\"", attr(language)),
+            None => String::new(),
+        };
+        opml.push_str(&format!(
+            "    <outline type=\"rss\" text=\"{name}\" title=\"{name}\" xmlUrl=\"{url}\"{language}/>\n",
+            name = attr(&source.name),
+            url = attr(&source.url),
+            language = language,
+        ));
+    }
+    opml.push_str("  </body>\n</opml>\n");
+    opml
+}
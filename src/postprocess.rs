@@ -0,0 +1,422 @@
+//! Post-processing turns a raw transcript into something nicer to read.
+
+use crate::chapters::{self, Chapter};
+use crate::config;
+use crate::openai::{ApiError, OpenAI, Segment};
+use regex::Regex;
+use serde::Deserialize;
+use std::sync::{Arc, LazyLock};
+
+/// How many sentences the rule-based cleaner puts in each paragraph.
+const RULES_SENTENCES_PER_PARAGRAPH: usize = 5;
+
+/// Told to the model along with the postprocessing prompt when several
+/// episodes are post-processed together (see batch_postprocess).
+const BATCH_INSTRUCTIONS: &str = "\
+The transcript below is made up of several episodes of the same show. Each \
+one starts with a line like \"=== EPISODE 1: Title ===\". Post-process each \
+episode as above, using the others for context (e.g. to name speakers \
+consistently). Keep every one of those lines exactly as it is, so the \
+episodes can be split apart again, and NEVER merge, reorder or leave out \
+episodes.";
+
+/// Roughly how far apart timestamp anchors are (see preserve_timestamps), in
+/// seconds of audio.
+const ANCHOR_INTERVAL_SECONDS: f32 = 60.0;
+
+// Only ever used within lqcli, so there are no outside callers who might
+// need the futures to be Send.
+#[allow(async_fn_in_trait)]
+pub trait Postprocessor {
+    /// Post-process a transcript, or say why that failed.
+    async fn postprocess(&self, transcript: &str) -> Result<String, ApiError>;
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PostprocessVia {
+    /// Send the transcript to the OpenAI chat API (openai.postprocessing_model).
+    Openai,
+    /// Send the transcript to a local LLM through an OpenAI-compatible API
+    /// (see the [local_llm] section).
+    Local,
+    /// Clean up whitespace, punctuation and capitalization with simple
+    /// rules. Free and offline, but it can't fix misheard words.
+    Rules,
+}
+
+/// What to do with an item whose transcript couldn't be post-processed.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PostprocessFailure {
+    /// Leave the item out of this sync; it's tried again next time.
+    Skip,
+    /// Import the raw transcript instead, with a warning.
+    ImportRaw,
+    /// Stop the run with an error.
+    Error,
+}
+
+#[derive(Clone)]
+enum Backend {
+    // Shared, so that per-source copies (see with_prompt) add up their
+    // usage in one place, unless they're scoped to keep their own.
+    Openai(Arc<OpenAI>),
+    Rules(RuleBased),
+}
+
+/// Whichever postprocessor has been configured.
+pub struct AnyPostprocessor {
+    backend: Backend,
+
+    /// Transcripts shorter than this many characters are left alone.
+    min_chars: usize,
+
+    /// The prompt to post-process with in place of
+    /// openai.postprocessing_prompt, e.g. a source's own.
+    prompt: Option<String>,
+}
+
+impl AnyPostprocessor {
+    /// Build the postprocessor selected by `postprocess_via` in the config.
+    pub fn from_config(config: &config::LqcliConfig) -> Result<Self, String> {
+        let backend = match config.postprocess_via {
+            PostprocessVia::Openai => Backend::Openai(Arc::new(OpenAI::new(config.openai.clone()))),
+            PostprocessVia::Local => {
+                let local = config.local_llm.as_ref().ok_or(
+                    "postprocess_via is \"local\" but there is no [local_llm] section".to_string(),
+                )?;
+                let openai_config = config::OpenaiConfig {
+                    api_key: local.api_key.clone().unwrap_or_default(),
+                    api_keys: None,
+                    api_base: Some(local.api_base.clone()),
+                    postprocessing_model: local.model.clone(),
+                    ..config.openai.clone()
+                };
+                Backend::Openai(Arc::new(OpenAI::new(openai_config)))
+            }
+            PostprocessVia::Rules => Backend::Rules(RuleBased),
+        };
+        Ok(Self { backend, min_chars: config.postprocess_min_chars, prompt: None })
+    }
+
+    /// This postprocessor, but using `prompt` (e.g. a source's
+    /// postprocessing_prompt) rather than openai.postprocessing_prompt, if
+    /// one is given.
+    pub fn with_prompt(&self, prompt: Option<&str>) -> Self {
+        Self {
+            backend: self.backend.clone(),
+            min_chars: self.min_chars,
+            prompt: prompt.map(str::to_string).or_else(|| self.prompt.clone()),
+        }
+    }
+
+    /// This postprocessor, but tallying what it uses separately (see
+    /// OpenAI::scoped) while still counting towards this one's usage.
+    pub fn scoped(&self) -> Self {
+        let backend = match &self.backend {
+            Backend::Openai(openai) => Backend::Openai(Arc::new(openai.scoped())),
+            Backend::Rules(rules) => Backend::Rules(*rules),
+        };
+        Self { backend, min_chars: self.min_chars, prompt: self.prompt.clone() }
+    }
+
+    /// The prompt to give `openai` for post-processing.
+    fn prompt<'a>(&'a self, openai: &'a OpenAI) -> &'a str {
+        self.prompt.as_deref().unwrap_or(openai.postprocessing_prompt())
+    }
+
+    /// Post-process several episodes' transcripts, given as (title,
+    /// transcript) pairs, in one request so each can be cleaned up with the
+    /// others for context. Returns the texts in the same order, or None if
+    /// that failed or the reply couldn't be split back into episodes.
+    ///
+    /// Transcripts too short to post-process are left alone, as they would
+    /// be on their own; postprocessors which don't use a model handle each
+    /// transcript separately.
+    pub async fn postprocess_batch(&self, episodes: &[(String, String)]) -> Option<Vec<String>> {
+        let mut texts: Vec<Option<String>> = episodes
+            .iter()
+            .map(|(_, transcript)| Some(transcript.clone()).filter(|t| t.trim().chars().count() < self.min_chars))
+            .collect();
+        let batch: Vec<(String, String)> = episodes
+            .iter()
+            .zip(&texts)
+            .filter(|(_, text)| text.is_none())
+            .map(|(episode, _)| episode.clone())
+            .collect();
+        let postprocessed = match &self.backend {
+            Backend::Openai(openai) if !batch.is_empty() => {
+                let prompt = format!("{}\n\n{}", self.prompt(openai), BATCH_INSTRUCTIONS);
+                let reply = match openai.postprocess_with_prompt(&join_batch(&batch), &prompt).await.text {
+                    Ok(reply) => reply,
+                    Err(e) => {
+                        eprintln!("Error post-processing: {}", e);
+                        return None;
+                    }
+                };
+                split_batch(&reply, batch.len())?
+            }
+            _ => {
+                let mut postprocessed = vec![];
+                for (_, transcript) in &batch {
+                    match self.postprocess(transcript).await {
+                        Ok(text) => postprocessed.push(text),
+                        Err(e) => {
+                            eprintln!("Error post-processing: {}", e);
+                            return None;
+                        }
+                    }
+                }
+                postprocessed
+            }
+        };
+        let mut postprocessed = postprocessed.into_iter();
+        for text in texts.iter_mut().filter(|text| text.is_none()) {
+            *text = postprocessed.next();
+        }
+        texts.into_iter().collect()
+    }
+
+    /// The API client behind this postprocessor, if it uses one.
+    pub fn openai_client(&self) -> Option<&OpenAI> {
+        match &self.backend {
+            Backend::Openai(openai) => Some(openai),
+            Backend::Rules(_) => None,
+        }
+    }
+}
+
+impl Postprocessor for AnyPostprocessor {
+    async fn postprocess(&self, transcript: &str) -> Result<String, ApiError> {
+        // Not worth an API call (or the risk of the model getting creative)
+        // for a clip this short.
+        if transcript.trim().chars().count() < self.min_chars {
+            return Ok(transcript.to_string());
+        }
+        match &self.backend {
+            Backend::Openai(openai) => openai.postprocess(transcript, self.prompt(openai)).await,
+            Backend::Rules(rules) => rules.postprocess(transcript).await,
+        }
+    }
+}
+
+/// Start a new paragraph at each speaker label matched by `pattern` (e.g.
+/// "Host:"), so interviews are laid out by who is talking no matter what the
+/// postprocessor makes of them.
+pub fn split_speaker_turns(transcript: &str, pattern: &Regex) -> String {
+    let mut text = String::new();
+    let mut last = 0;
+    for label in pattern.find_iter(transcript) {
+        let before = transcript[last..label.start()].trim();
+        if !before.is_empty() {
+            text.push_str(before);
+            text.push_str("\n\n");
+        }
+        last = label.start();
+    }
+    text.push_str(transcript[last..].trim());
+    text
+}
+
+/// A rough count of the tokens in some text, for keeping batches within a
+/// budget without a tokenizer. Errs on the high side for most languages.
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(3)
+}
+
+/// Put several episodes' transcripts, given as (title, transcript) pairs,
+/// into one text for post-processing together, each starting with a marker
+/// line which split_batch can find again.
+pub fn join_batch(episodes: &[(String, String)]) -> String {
+    episodes
+        .iter()
+        .enumerate()
+        .map(|(i, (title, transcript))| {
+            let title = title.replace('\n', " ");
+            format!("=== EPISODE {}: {} ===\n\n{}", i + 1, title.trim(), transcript.trim())
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Split the post-processed text of a batch made by join_batch back into
+/// `count` episodes. Returns None unless every marker comes back exactly
+/// once and in order, since then there's no telling which text belongs to
+/// which episode.
+pub fn split_batch(text: &str, count: usize) -> Option<Vec<String>> {
+    static MARKER: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"(?m)^[ \t]*=== EPISODE (\d+)(?::[^\n]*)? ===[ \t]*$").unwrap());
+    let markers: Vec<_> = MARKER.captures_iter(text).collect();
+    if markers.len() != count || !text[..markers.first()?.get(0)?.start()].trim().is_empty() {
+        return None;
+    }
+    let mut episodes = vec![];
+    for (i, marker) in markers.iter().enumerate() {
+        if marker[1].parse::<usize>().ok()? != i + 1 {
+            return None;
+        }
+        let start = marker.get(0)?.end();
+        let end = markers.get(i + 1).map_or(text.len(), |next| next.get(0).unwrap().start());
+        let episode = text[start..end].trim();
+        if episode.is_empty() {
+            return None;
+        }
+        episodes.push(episode.to_string());
+    }
+    Some(episodes)
+}
+
+/// Group transcript segments into blocks of about ANCHOR_INTERVAL_SECONDS
+/// each, breaking only between segments, so each block can be
+/// post-processed on its own and keep an anchor to where it starts.
+///
+/// Returns (start in seconds, text) pairs in order.
+pub fn timestamp_blocks(segments: &[Segment]) -> Vec<(f32, String)> {
+    let mut blocks: Vec<(f32, String)> = vec![];
+    for segment in segments.iter().filter(|segment| !segment.text.trim().is_empty()) {
+        match blocks.last_mut() {
+            Some((start, text)) if segment.start - *start < ANCHOR_INTERVAL_SECONDS => {
+                text.push(' ');
+                text.push_str(segment.text.trim());
+            }
+            _ => blocks.push((segment.start, segment.text.trim().to_string())),
+        }
+    }
+    blocks
+}
+
+/// The anchor marking where a block of text starts in the audio, e.g.
+/// "<!-- 12:34 -->" (or "<!-- 1:02:03 -->" past the first hour).
+pub fn timestamp_anchor(start: f32) -> String {
+    let seconds = start.max(0.0) as u64;
+    let (hours, minutes, seconds) = (seconds / 3600, seconds % 3600 / 60, seconds % 60);
+    if hours > 0 {
+        format!("<!-- {}:{:02}:{:02} -->", hours, minutes, seconds)
+    } else {
+        format!("<!-- {:02}:{:02} -->", minutes, seconds)
+    }
+}
+
+/// The running text of a transcript's segments, or ApiError::Empty if
+/// there's none (e.g. the audio is silent).
+pub fn segments_text(segments: &[Segment]) -> Result<String, ApiError> {
+    let text = segments
+        .iter()
+        .map(|segment| segment.text.trim())
+        .filter(|text| !text.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+    Some(text).filter(|text| !text.is_empty()).ok_or(ApiError::Empty)
+}
+
+/// Post-process a transcript's segments chapter by chapter, with a heading
+/// for each chapter (see chapters::split_by_chapters). Speaker turns are
+/// split first if `speaker_regex` is given.
+pub async fn postprocess_chapters(
+    postprocessor: &impl Postprocessor,
+    speaker_regex: Option<&Regex>,
+    segments: &[Segment],
+    chapters: &[Chapter],
+) -> Result<String, ApiError> {
+    let mut text = String::new();
+    for (title, section) in chapters::split_by_chapters(segments, chapters) {
+        // Nothing to send the chat API, nor a heading to put over nothing.
+        if section.trim().is_empty() {
+            continue;
+        }
+        if let Some(title) = title {
+            text.push_str(&format!("## {}\n\n", title));
+        }
+        let section = match speaker_regex {
+            Some(regex) => split_speaker_turns(&section, regex),
+            None => section,
+        };
+        text.push_str(postprocessor.postprocess(&section).await?.trim());
+        text.push_str("\n\n");
+    }
+    Some(text.trim_end().to_string()).filter(|text| !text.is_empty()).ok_or(ApiError::Empty)
+}
+
+/// Post-process a transcript's segments a block at a time (see
+/// timestamp_blocks), each block starting with an anchor giving its time in
+/// the audio. Speaker turns are split first if `speaker_regex` is given.
+pub async fn postprocess_timestamped(
+    postprocessor: &impl Postprocessor,
+    speaker_regex: Option<&Regex>,
+    segments: &[Segment],
+) -> Result<String, ApiError> {
+    let mut text = String::new();
+    for (start, block) in timestamp_blocks(segments) {
+        let block = match speaker_regex {
+            Some(regex) => split_speaker_turns(&block, regex),
+            None => block,
+        };
+        let postprocessed = postprocessor.postprocess(&block).await?;
+        text.push_str(&timestamp_anchor(start));
+        text.push_str("\n\n");
+        text.push_str(postprocessed.trim());
+        text.push_str("\n\n");
+    }
+    Some(text.trim_end().to_string()).filter(|text| !text.is_empty()).ok_or(ApiError::Empty)
+}
+
+/// A postprocessor which doesn't need any API at all.
+///
+/// It collapses runs of whitespace, removes spaces before punctuation, makes
+/// sure sentences start with a capital letter and end with punctuation, and
+/// groups sentences into paragraphs. Paragraph breaks already in the
+/// transcript (e.g. from speaker_pattern) are kept.
+#[derive(Clone, Copy)]
+pub struct RuleBased;
+
+impl Postprocessor for RuleBased {
+    async fn postprocess(&self, transcript: &str) -> Result<String, ApiError> {
+        let paragraphs: Vec<String> = transcript
+            .split("\n\n")
+            .filter(|block| !block.trim().is_empty())
+            .flat_map(RuleBased::clean_block)
+            .collect();
+        Some(paragraphs.join("\n\n")).filter(|text| !text.is_empty()).ok_or(ApiError::Empty)
+    }
+}
+
+impl RuleBased {
+    /// Clean up one block of text, splitting it into paragraphs.
+    fn clean_block(block: &str) -> Vec<String> {
+        let words: Vec<&str> = block.split_whitespace().collect();
+        let mut text = String::new();
+        for word in words {
+            let is_punctuation = word.chars().all(|c| ",.!?;:".contains(c));
+            if !text.is_empty() && !is_punctuation {
+                text.push(' ');
+            }
+            text.push_str(word);
+        }
+
+        let mut sentences = vec![];
+        let mut sentence = String::new();
+        for c in text.chars() {
+            if sentence.is_empty() && c == ' ' {
+                continue;
+            }
+            if sentence.is_empty() {
+                sentence.extend(c.to_uppercase());
+            } else {
+                sentence.push(c);
+            }
+            if ".!?".contains(c) {
+                sentences.push(std::mem::take(&mut sentence));
+            }
+        }
+        if !sentence.is_empty() {
+            sentence.push('.');
+            sentences.push(sentence);
+        }
+
+        sentences
+            .chunks(RULES_SENTENCES_PER_PARAGRAPH)
+            .map(|paragraph| paragraph.join(" "))
+            .collect()
+    }
+}
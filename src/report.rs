@@ -0,0 +1,49 @@
+//! Reporting problems: warnings, which lqcli carries on after (unless run
+//! with --strict), and fatal errors, which it doesn't. Either kind which
+//! happens in a source also goes in that source's log (see runlog).
+
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether fatal errors are reported as JSON (--json-errors).
+static JSON_ERRORS: AtomicBool = AtomicBool::new(false);
+
+/// Whether warnings are fatal (--strict).
+static STRICT: AtomicBool = AtomicBool::new(false);
+
+pub fn set_json_errors(json_errors: bool) {
+    JSON_ERRORS.store(json_errors, Ordering::Relaxed);
+}
+
+pub fn set_strict(strict: bool) {
+    STRICT.store(strict, Ordering::Relaxed);
+}
+
+/// Report an error which lqcli can't carry on from, and exit. `kind` is a
+/// short, stable name for what went wrong (e.g. "config" or "lingq-auth"),
+/// and `source` the configured source it happened in, if any.
+pub fn fatal(kind: &str, source: Option<&str>, message: impl Display) -> ! {
+    if let Some(source) = source {
+        crate::runlog::log(source, format!("Error: {}", message));
+    }
+    if JSON_ERRORS.load(Ordering::Relaxed) {
+        let error = serde_json::json!({ "error": message.to_string(), "kind": kind, "source": source });
+        eprintln!("{}", error);
+    } else {
+        eprintln!("{}", message);
+    }
+    std::process::exit(1);
+}
+
+/// Report something which might be a problem but doesn't stop lqcli, or,
+/// with --strict, report it as an error and exit. `kind` and `source` are
+/// as for fatal().
+pub fn warn(kind: &str, source: Option<&str>, message: impl Display) {
+    if STRICT.load(Ordering::Relaxed) {
+        fatal(kind, source, format!("Error (--strict): {}", message));
+    }
+    if let Some(source) = source {
+        crate::runlog::log(source, format!("Warning: {}", message));
+    }
+    eprintln!("Warning: {}", message);
+}
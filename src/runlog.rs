@@ -0,0 +1,102 @@
+//! Per-source logs (see log_dir): a record on disk of what syncs did with
+//! each source, apart from what's printed, for working out afterwards what
+//! went wrong in a run nobody was watching.
+//!
+//! A source's log is only rotated when it's first written to in a run, so
+//! each run's lines stay together in one file.
+
+use crate::source;
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+struct Logs {
+    dir: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+
+    /// Sources whose logs have been written to (and so rotated, if need be)
+    /// in this run.
+    opened: HashSet<String>,
+
+    /// Sources whose logs couldn't be written, which we've said so about
+    /// and stopped trying.
+    failed: HashSet<String>,
+}
+
+/// Where logs are kept, if anywhere (log_dir).
+static LOGS: Mutex<Option<Logs>> = Mutex::new(None);
+
+/// Start keeping a log for each source in `dir`, which is created when the
+/// first line is written. Logs bigger than `max_bytes` are rotated, keeping
+/// `keep` old ones.
+pub fn set_dir(dir: impl Into<PathBuf>, max_bytes: u64, keep: usize) {
+    let logs = Logs { dir: dir.into(), max_bytes, keep, opened: HashSet::new(), failed: HashSet::new() };
+    *LOGS.lock().unwrap_or_else(|e| e.into_inner()) = Some(logs);
+}
+
+/// Where a source's log is kept in `dir`.
+pub fn path_for(dir: &Path, source: &str) -> PathBuf {
+    dir.join(source::safe_filename_within(source, "log"))
+}
+
+/// The `n`th rotated copy of the log at `path`, e.g. feed.log.2.
+fn rotated(path: &Path, n: usize) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+/// If the log at `path` has grown past `max_bytes`, move it to path.1 (and
+/// path.1 to path.2, and so on), so the next line starts a new one. Only
+/// the latest `keep` rotated logs are kept.
+pub fn rotate(path: &Path, max_bytes: u64, keep: usize) -> io::Result<()> {
+    match std::fs::metadata(path) {
+        Ok(metadata) if metadata.len() > max_bytes => {}
+        Ok(_) => return Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    }
+    if keep == 0 {
+        return std::fs::remove_file(path);
+    }
+    // Renaming over the oldest one we keep drops it.
+    for n in (1..keep).rev() {
+        match std::fs::rename(rotated(path, n), rotated(path, n + 1)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    std::fs::rename(path, rotated(path, 1))
+}
+
+/// Append `message` to the log at `path`, each of its lines with the time.
+fn append(path: &Path, message: &str) -> io::Result<()> {
+    let timestamp = chrono::Local::now().format("%Y-%m-%d %H:%M:%S");
+    let lines: String = message.lines().map(|line| format!("{} {}\n", timestamp, line)).collect();
+    let mut file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(lines.as_bytes())
+}
+
+/// Add to a source's log, if logs are being kept.
+///
+/// A log which can't be written is reported once and then left alone for
+/// the rest of the run, rather than stopping the sync.
+pub fn log(source: &str, message: impl Display) {
+    let mut logs = LOGS.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(logs) = logs.as_mut().filter(|logs| !logs.failed.contains(source)) else {
+        return;
+    };
+    let path = path_for(&logs.dir, source);
+    let mut result = Ok(());
+    if logs.opened.insert(source.to_string()) {
+        result = std::fs::create_dir_all(&logs.dir).and_then(|()| rotate(&path, logs.max_bytes, logs.keep));
+    }
+    if let Err(e) = result.and_then(|()| append(&path, &message.to_string())) {
+        eprintln!("Error writing the log for {} to {}: {}", source, path.display(), e);
+        logs.failed.insert(source.to_string());
+    }
+}
@@ -1,14 +1,41 @@
 use atom_syndication::{Feed as AtomFeed, Entry};
+use chrono::{DateTime, FixedOffset};
 use rss::{Channel, Item as RssItem};
-use serde::Deserialize;
+use serde::de::{IntoDeserializer, value};
+use serde::{Deserialize, Deserializer};
+use regex::Regex;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use std::collections::HashMap;
 use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
 use tabled::Tabled;
+use url::Url;
 
-use crate::fetch::{DownloadMethod, fetch};
+use crate::audio;
+use crate::fetch::{DownloadMethod, DownloadOptions, fetch};
+use crate::transcribe::TranscriptVia;
 
 const DEFAULT_CONTENT_TYPE: ContentType = ContentType::Syndication;
+const DEFAULT_DIRECTORY_ORDER: DirectoryOrder = DirectoryOrder::Name;
 const DEFAULT_DOWNLOAD_METHOD: DownloadMethod = DownloadMethod::YtDlp;
-const DEFAULT_TRANSCRIPT_VIA: &str = "openai";
+const DEFAULT_TRANSCRIPT_VIA: TranscriptVia = TranscriptVia::Openai;
+
+/// The most pages of a paged Atom feed to fetch, however many entries are
+/// wanted, in case a feed's paging links go round in circles.
+const MAX_FEED_PAGES: usize = 20;
+
+/// Feeds found this run to have permanently moved (with a 301 or 308
+/// redirect), as (configured URL, new URL).
+static MOVED_FEEDS: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+
+/// The <podcast:transcript> types subtitles::parse can read.
+const SUBTITLE_TYPES: &[&str] = &["text/vtt", "application/x-subrip", "application/srt", "text/srt"];
+
+/// Extensions of the files a local-directory source picks up.
+const AUDIO_EXTENSIONS: &[&str] = &["mp3", "m4a", "aac", "ogg", "opus", "flac", "wav"];
 
 #[derive(Deserialize)]
 #[serde(transparent)]
@@ -64,11 +91,32 @@ pub struct Source {
 
     /// The course ID to create a lesson in for each fetched item from this
     /// source.
+    ///
+    /// 0 means no course at all, leaving the lessons wherever LingQ puts
+    /// imports without one. Since there's then no course to look in, lqcli
+    /// can't tell which items were already imported, so this is best kept
+    /// for one-off imports rather than sources.
     pub course_id: u64,
 
+    /// Set this if the source deliberately imports into the same course as
+    /// another source, to silence the warning about it.
+    #[serde(default)]
+    #[tabled(skip)]
+    pub allow_shared_course: bool,
+
+    /// Time in between requests to the LingQ API (in seconds) for this
+    /// source. Defaults to lingq.request_delay.
+    #[tabled(skip)]
+    pub request_delay: Option<u64>,
+
     /// The two-letter language code. The LingQ API uses this because course IDs
     /// are unique per language.
-    pub language: String,
+    ///
+    /// If this is left out, the language the feed declares (RSS <language>
+    /// or Atom xml:lang) is used instead. Set it anyway if the feed gets its
+    /// own language wrong.
+    #[tabled(display_with = "display_language")]
+    pub language: Option<String>,
 
     /// Tags allow you to group sources. One place where this could be useful
     /// is to tag sources that are known to update daily vs multiple times a
@@ -81,14 +129,293 @@ pub struct Source {
     /// Transcripts are normally assumed to be created by the OpenAI Whisper
     /// model described in openai.whisper_model. But sometimes, some sources
     /// might need special handling. For example the Easy German videos have
-    /// transcripts available for members and lqcli knows how to download them.
+    /// transcripts available for members and lqcli knows how to download them
+    /// from each episode's page (put your session cookie in headers).
     /// In this case, you would set this to "easy-german" or
-    /// "super-easy-german". The default is "openai". You can also set to
-    /// "lingq". LingQ will use Whisper (which is cheaper for you, the user,
-    /// than using OpenAI), but it doesn't do any post-processing. This is
-    /// normally good enough for single-speaker content.
+    /// "super-easy-german"; episodes whose transcript can't be found are
+    /// transcribed with Whisper instead. The default is "openai". You can
+    /// also set to "lingq", to import just the audio and have LingQ
+    /// transcribe it. LingQ will use Whisper (which is cheaper for you, the
+    /// user, than using OpenAI), but it doesn't do any post-processing. This
+    /// is normally good enough for single-speaker content. Set it to "deepgram"
+    /// to transcribe with Deepgram instead (see the [deepgram] section),
+    /// which can label speakers; chapters (include_chapters) need Whisper,
+    /// so are ignored then. Set it to "local" to transcribe on this machine
+    /// with whisper.cpp (see the [local_whisper] section), which costs
+    /// nothing per hour of audio; chapters are ignored then too. Set it to
+    /// "url" to fetch each item's transcript from transcript_url_template
+    /// instead, for sites which publish them alongside the audio.
     #[serde(default = "default_transcript_via")]
-    pub transcript_via: String,
+    pub transcript_via: TranscriptVia,
+
+    /// Some real-world feeds are not quite valid XML (stray control
+    /// characters, unescaped ampersands, HTML-only entities like &nbsp;).
+    /// When this is set and the feed fails to parse, lqcli will clean those
+    /// up and try again, printing a warning when it does so. Defaults to
+    /// false.
+    #[serde(default)]
+    #[tabled(skip)]
+    pub lenient_parsing: bool,
+
+    /// If the content has chapters (as yt-dlp understands them, e.g. YouTube
+    /// chapters), put each chapter's title as a "## Title" heading at the
+    /// right point in the lesson text. Each chapter is post-processed
+    /// separately so the headings survive. Defaults to false.
+    #[serde(default)]
+    #[tabled(skip)]
+    pub include_chapters: bool,
+
+    /// Keep approximate timestamps in the lesson text, so it can still be
+    /// lined up with the audio: every minute or so of the transcript is
+    /// post-processed separately and starts with an anchor like
+    /// "<!-- 12:34 -->". Only works with Whisper, and include_chapters
+    /// takes precedence when the content has chapters. Defaults to false.
+    #[serde(default)]
+    #[tabled(skip)]
+    pub preserve_timestamps: bool,
+
+    /// Use the subtitles an item links to (a Podcasting 2.0
+    /// <podcast:transcript> in WebVTT or SubRip) instead of transcribing its
+    /// audio. Their timings are kept as anchors, as with
+    /// preserve_timestamps, and are more accurate than Whisper's. Items
+    /// without subtitles are transcribed as usual. Defaults to false.
+    #[serde(default)]
+    #[tabled(skip)]
+    pub use_subtitles: bool,
+
+    /// With transcript_via = "url", where to fetch each item's transcript
+    /// from, e.g. "{link}/transcript". Can contain the placeholders
+    /// {source} (the source's name), {title}, {date} (when the item was
+    /// published, as YYYY-MM-DD), {guid}, {link} (the item's web page) and
+    /// {audio} (its audio link); {source} and {title} are URL-encoded, the
+    /// rest are filled in as they are. Any HTML in the transcript is
+    /// stripped and the rest becomes the lesson text as it is. Items whose
+    /// transcript can't be fetched, or is empty, are transcribed as usual.
+    #[tabled(skip)]
+    pub transcript_url_template: Option<String>,
+
+    /// Extra HTTP headers to send when fetching the feed (and, for
+    /// easy-german sources, episode pages), for feeds which want something
+    /// like a Referer, an API key header or a particular Accept-Language.
+    /// Values are treated as sensitive and never printed.
+    #[tabled(skip)]
+    pub headers: Option<HashMap<String, String>>,
+
+    /// Extra fields to send to LingQ with each lesson imported from this
+    /// source, for import options lqcli doesn't have a setting for. Useful
+    /// ones include "status" ("private" or "shared") and "original_url".
+    /// Fields lqcli sets itself (title, text, collection, audio,
+    /// description, level, save) can't be overridden this way, and names
+    /// must be letters, digits and underscores; anything else is ignored
+    /// with a warning.
+    #[tabled(skip)]
+    pub lingq_fields: Option<HashMap<String, String>>,
+
+    /// The LingQ level to import lessons at, so they can be filtered by
+    /// difficulty: 1 and 2 are Beginner 1 and 2, 3 and 4 Intermediate 1
+    /// and 2, and 5 and 6 Advanced 1 and 2. Left to LingQ if not set; any
+    /// other number is ignored with a warning.
+    #[tabled(skip)]
+    pub level: Option<u8>,
+
+    /// A regular expression matching the speaker labels in this source's
+    /// transcripts, e.g. "(Host|Guest):". Each match starts a new paragraph
+    /// before post-processing, which is more consistent than leaving it to
+    /// the model. Most useful for interview-style content.
+    #[tabled(skip)]
+    pub speaker_pattern: Option<String>,
+
+    /// Use each item's description (RSS <description> or Atom <summary>,
+    /// e.g. show notes) as the lesson's description in LingQ, with any HTML
+    /// removed. Defaults to false.
+    #[serde(default)]
+    #[tabled(skip)]
+    pub include_description: bool,
+
+    /// Also keep the raw Whisper transcript, before post-processing, in the
+    /// lesson's description in LingQ (after the item's description, if
+    /// include_description is set), for checking what the postprocessor
+    /// changed. Defaults to false.
+    #[serde(default)]
+    #[tabled(skip)]
+    pub keep_raw_transcript: bool,
+
+    /// Whether to post-process this source's new items together, a batch at
+    /// a time (see batch_postprocess_max_tokens), rather than one by one, so
+    /// the model can use the other episodes for context, e.g. to name
+    /// speakers consistently. Items which are split into chapters or keep
+    /// timestamps are still post-processed on their own. Defaults to false.
+    #[serde(default)]
+    #[tabled(skip)]
+    pub batch_postprocess: bool,
+
+    /// Whether items need audio to be imported. When this is set to false,
+    /// items without any audio (e.g. text-only posts) are imported as
+    /// text-only lessons, using the item's content as the lesson text,
+    /// instead of being skipped. Defaults to true.
+    #[serde(default = "default_require_audio")]
+    #[tabled(skip)]
+    pub require_audio: bool,
+
+    /// Merge items published within this many hours of each other into a
+    /// single lesson, for shows which publish lots of very short clips. The
+    /// audio is joined together (with ffmpeg) and each item's transcript
+    /// gets a "## Title" heading. The lesson is named after the earliest
+    /// item, e.g. "Clip 1 (+3 more)". Items without a publication date or
+    /// audio are never merged. Not set by default.
+    #[tabled(skip)]
+    pub merge_window: Option<u64>,
+
+    /// The Whisper model to transcribe this source with, for sources which
+    /// need a different model than openai.whisper_model (e.g. one which
+    /// handles a dialect better). Defaults to openai.whisper_model.
+    #[tabled(skip)]
+    pub whisper_model: Option<String>,
+
+    /// The Whisper temperature for this source's items, e.g. for one with
+    /// noisy audio. Defaults to openai.whisper_temperature.
+    #[tabled(skip)]
+    pub whisper_temperature: Option<f32>,
+
+    /// How often (in hours) this source is worth checking, for `sources
+    /// sync --due`. Each sync in a row that finds nothing new doubles the
+    /// wait (up to 8 times the interval), so quiet feeds are checked less
+    /// and less until they publish again. Sources without an interval are
+    /// always due.
+    #[tabled(skip)]
+    pub poll_interval: Option<u64>,
+
+    /// Also translate each transcript into this language (e.g. "English")
+    /// with openai.postprocessing_model and put the translation in the
+    /// lesson's description, for a bilingual reference. The lesson text
+    /// stays in the source's language. This costs an extra request per
+    /// item, so it's off by default.
+    #[tabled(skip)]
+    pub generate_translation_to: Option<String>,
+
+    /// Only compare new items against the titles of this many of the
+    /// course's most recently created lessons when looking for ones which
+    /// already exist, for courses with thousands of lessons where new items
+    /// will only ever match recent ones. Defaults to checking every lesson.
+    #[tabled(skip)]
+    pub dedup_window: Option<usize>,
+
+    /// How to tell which items have been imported already: "title" (by
+    /// the titles of the course's lessons) or "guid" (by the GUIDs lqcli
+    /// has imported from the source before), for feeds which reuse or
+    /// reword titles. Run `sources reconcile` before switching an existing
+    /// source to "guid", so it knows about items imported by title.
+    /// Defaults to "title".
+    #[serde(default)]
+    #[tabled(skip)]
+    pub dedup: Dedup,
+
+    /// How many of the source's latest items a sync looks at, and so imports
+    /// at most, e.g. for daily shows which publish several episodes at once.
+    /// `sources sync --count` overrides it. Defaults to 5.
+    #[tabled(skip)]
+    pub max_items: Option<usize>,
+
+    /// For local-directory sources, the order to import the files in:
+    /// "name" (by filename, e.g. for numbered tracks) or "modified" (oldest
+    /// first). Defaults to "name".
+    #[serde(default = "default_directory_order")]
+    #[tabled(skip)]
+    pub directory_order: DirectoryOrder,
+
+    /// Only import items whose title matches this regular expression, e.g.
+    /// "\\[Full Episode\\]" for feeds which mix full episodes and clips.
+    /// An invalid pattern is an error in the configuration file.
+    #[serde(default, deserialize_with = "deserialize_regex")]
+    #[tabled(skip)]
+    pub title_include: Option<Regex>,
+
+    /// Never import items whose title matches this regular expression, even
+    /// if it matches title_include.
+    #[serde(default, deserialize_with = "deserialize_regex")]
+    #[tabled(skip)]
+    pub title_exclude: Option<Regex>,
+
+    /// For content with several audio tracks (e.g. dubbed videos), download
+    /// the one in this language (e.g. "de") instead of the default. Items
+    /// without a track in the language fail to download. Only used with
+    /// yt-dlp.
+    #[tabled(skip)]
+    pub yt_dlp_audio_lang: Option<String>,
+
+    /// Some feeds put analytics redirects (e.g. https://chtbl.com/track/...)
+    /// in front of their audio, which yt-dlp occasionally can't get through.
+    /// Set this to strip known tracking prefixes and follow redirects to the
+    /// real audio URL before downloading it. Defaults to false.
+    #[serde(default)]
+    #[tabled(skip)]
+    pub unwrap_redirects: bool,
+}
+
+impl Source {
+    /// The Whisper model to transcribe this source with: its own
+    /// whisper_model if it has one, otherwise `default`.
+    pub fn whisper_model<'a>(&'a self, default: &'a str) -> &'a str {
+        self.whisper_model.as_deref().unwrap_or(default)
+    }
+
+    /// The Whisper temperature for this source: its own whisper_temperature
+    /// if it has one, otherwise `default`.
+    pub fn whisper_temperature(&self, default: Option<f32>) -> Option<f32> {
+        self.whisper_temperature.or(default)
+    }
+
+    /// How many items to sync from this source: its own max_items if it has
+    /// one, otherwise `default`.
+    pub fn max_items(&self, default: usize) -> usize {
+        self.max_items.unwrap_or(default)
+    }
+
+    /// Whether title_include and title_exclude let an item with this title
+    /// through.
+    pub fn title_allowed(&self, title: &str) -> bool {
+        self.title_include.as_ref().is_none_or(|include| include.is_match(title))
+            && !self.title_exclude.as_ref().is_some_and(|exclude| exclude.is_match(title))
+    }
+
+    /// The compiled speaker_pattern, if there is one.
+    pub fn speaker_regex(&self) -> Result<Option<Regex>, regex::Error> {
+        self.speaker_pattern.as_deref().map(Regex::new).transpose()
+    }
+
+    /// The configured level, if it's one LingQ has.
+    pub fn level(&self) -> Option<u8> {
+        self.level.filter(|level| crate::lingq::LEVELS.contains(level))
+    }
+
+    /// The configured lingq_fields, leaving out any with invalid names.
+    pub fn lingq_fields(&self) -> HashMap<String, String> {
+        self.lingq_fields
+            .iter()
+            .flatten()
+            .filter(|(name, _)| crate::lingq::is_valid_extra_field(name))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect()
+    }
+
+    /// The configured feed headers, checked for validity. Errors only name
+    /// the offending header, never its value.
+    pub fn feed_headers(&self) -> Result<HeaderMap, SourceError> {
+        let mut headers = HeaderMap::new();
+        for (name, value) in self.headers.iter().flatten() {
+            let header_name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| SourceError::InvalidHeader(format!("\"{}\" is not a valid header name", name)))?;
+            let mut header_value = HeaderValue::from_str(value)
+                .map_err(|_| SourceError::InvalidHeader(format!("the value of \"{}\" is not a valid header value", name)))?;
+            header_value.set_sensitive(true);
+            headers.insert(header_name, header_value);
+        }
+        Ok(headers)
+    }
+}
+
+fn display_language(language: &Option<String>) -> String {
+    language.clone().unwrap_or("(from feed)".to_string())
 }
 
 #[derive(Deserialize)]
@@ -96,16 +423,84 @@ pub struct Source {
 pub enum ContentType {
     /// The content comes from some kind of syndication feed (RSS or Atom).
     Syndication,
+    /// The content is a directory of audio files on this machine, given as
+    /// the source's url (e.g. "~/Music/Hörbuch"). Each audio file is an
+    /// item, titled by its tags or else its filename, and found among the
+    /// imported lessons by its filename. These never declare a language, so
+    /// set one for the source.
+    LocalDirectory,
+    /// The content is a YouTube channel's or playlist's videos, listed with
+    /// yt-dlp and downloaded with it too, so leave download_method as
+    /// "yt-dlp". Give the channel's videos tab as the url (e.g.
+    /// "https://www.youtube.com/@name/videos"), not the channel itself.
+    /// These never declare a language either.
+    YoutubeChannel,
 }
 
 impl Display for ContentType {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             ContentType::Syndication => write!(f, "Syndication Feed"),
+            ContentType::LocalDirectory => write!(f, "Local Directory"),
+            ContentType::YoutubeChannel => write!(f, "YouTube Channel"),
         }
     }
 }
 
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DirectoryOrder {
+    /// By filename.
+    Name,
+    /// By when the file was last modified, oldest first.
+    Modified,
+}
+
+/// How a source's items are matched against what's been imported already.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Dedup {
+    /// By title, against the titles of the course's lessons.
+    #[default]
+    Title,
+    /// By GUID (see SourceItem::guid), against the GUIDs imported from the
+    /// source before.
+    Guid,
+}
+
+/// Which of a feed's new items to take first when there are more than
+/// wanted, and the order to import them in.
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ItemOrder {
+    /// The latest items, newest first.
+    #[default]
+    Newest,
+    /// The earliest items, oldest first, e.g. to backfill a series in the
+    /// order it came out.
+    Oldest,
+}
+
+impl FromStr for ItemOrder {
+    type Err = value::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::deserialize(s.into_deserializer())
+    }
+}
+
+/// Put feed items in `order` by when they were published. If any item has
+/// no date, the feed's own order is trusted to be newest first, as feeds
+/// list them.
+pub fn sort_items(items: &mut [SourceItem], order: ItemOrder) {
+    if items.iter().all(|item| item.published().is_some()) {
+        items.sort_by_key(|item| std::cmp::Reverse(item.published()));
+    }
+    if let ItemOrder::Oldest = order {
+        items.reverse();
+    }
+}
+
 #[derive(Debug)]
 pub enum SourceError {
     FetchError(reqwest::Error),
@@ -114,6 +509,9 @@ pub enum SourceError {
     // TODO.
     ParseError(String),
     AudioDownloadError(std::io::Error),
+    InvalidHeader(String),
+    DirectoryError(std::io::Error),
+    PlaylistError(std::io::Error),
 }
 
 impl From<reqwest::Error> for SourceError {
@@ -128,12 +526,30 @@ impl From<std::io::Error> for SourceError {
     }
 }
 
+impl SourceError {
+    /// A short, stable name for the kind of error, for machine-readable
+    /// output.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SourceError::FetchError(_) => "fetch",
+            SourceError::ParseError(_) => "parse",
+            SourceError::AudioDownloadError(_) => "audio-download",
+            SourceError::InvalidHeader(_) => "invalid-header",
+            SourceError::DirectoryError(_) => "directory",
+            SourceError::PlaylistError(_) => "playlist",
+        }
+    }
+}
+
 impl Display for SourceError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             SourceError::FetchError(err) => write!(f, "Fetch error: {}", err),
             SourceError::ParseError(msg) => write!(f, "Parse error: {}", msg),
             SourceError::AudioDownloadError(err) => write!(f, "Audio download error: {}", err),
+            SourceError::InvalidHeader(msg) => write!(f, "Invalid header: {}", msg),
+            SourceError::DirectoryError(err) => write!(f, "Directory error: {}", err),
+            SourceError::PlaylistError(err) => write!(f, "Playlist error: {}", err),
         }
     }
 }
@@ -142,12 +558,29 @@ fn default_content_type() -> ContentType {
     DEFAULT_CONTENT_TYPE
 }
 
+/// Compile a regular expression while reading the configuration, so a bad
+/// pattern is reported along with where it is in the file.
+fn deserialize_regex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Regex>, D::Error> {
+    let Some(pattern) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    Regex::new(&pattern).map(Some).map_err(serde::de::Error::custom)
+}
+
+fn default_directory_order() -> DirectoryOrder {
+    DEFAULT_DIRECTORY_ORDER
+}
+
 fn default_download_method() -> DownloadMethod {
     DEFAULT_DOWNLOAD_METHOD
 }
 
-fn default_transcript_via() -> String {
-    DEFAULT_TRANSCRIPT_VIA.to_string()
+fn default_require_audio() -> bool {
+    true
+}
+
+fn default_transcript_via() -> TranscriptVia {
+    DEFAULT_TRANSCRIPT_VIA
 }
 
 #[derive(Debug)]
@@ -156,6 +589,12 @@ pub struct StaticItem {
     pub title: String,
 }
 
+#[derive(Debug)]
+pub struct LocalItem {
+    pub path: PathBuf,
+    pub title: String,
+}
+
 #[derive(Debug)]
 /// What kind of individual item are we dealing with?
 pub enum SourceItem {
@@ -163,6 +602,123 @@ pub enum SourceItem {
     Atom(Entry),
     /// Just a static link to an audio file somewhere.
     Static(StaticItem),
+    /// An audio file in a local directory.
+    Local(LocalItem),
+}
+
+/// The audio files in a local-directory source, in its directory_order.
+/// Anything which isn't an audio file is left out.
+///
+/// Each file's title comes from ffprobe, so the directory is read on
+/// tokio's blocking threads.
+pub async fn local_items(source: &Source) -> Result<Vec<SourceItem>, SourceError> {
+    let dir = shellexpand::tilde(&source.url).to_string();
+    let order = source.directory_order;
+    tokio::task::spawn_blocking(move || local_files(&dir, order))
+        .await
+        .map_err(|e| SourceError::DirectoryError(std::io::Error::other(e)))?
+}
+
+fn local_files(dir: &str, order: DirectoryOrder) -> Result<Vec<SourceItem>, SourceError> {
+    let mut files = vec![];
+    for entry in std::fs::read_dir(dir).map_err(SourceError::DirectoryError)? {
+        let entry = entry.map_err(SourceError::DirectoryError)?;
+        let path = entry.path();
+        let is_audio = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| AUDIO_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()));
+        if !is_audio || !path.is_file() {
+            continue;
+        }
+        let modified = entry.metadata().and_then(|metadata| metadata.modified()).ok();
+        files.push((path, modified));
+    }
+    match order {
+        DirectoryOrder::Name => files.sort_by(|(a, _), (b, _)| a.cmp(b)),
+        DirectoryOrder::Modified => files.sort_by_key(|(_, modified)| *modified),
+    }
+    Ok(files.into_iter().map(|(path, _)| SourceItem::from_file(&path)).collect())
+}
+
+/// The part of each line of `yt-dlp --flat-playlist --dump-json` output we
+/// care about.
+#[derive(Deserialize)]
+struct FlatPlaylistEntry {
+    id: String,
+    url: Option<String>,
+    title: Option<String>,
+}
+
+/// Up to `count` of the latest videos of a youtube-channel source, newest
+/// first, listed by yt-dlp without downloading any of them.
+pub async fn youtube_items(source: &Source, count: usize) -> Result<Vec<SourceItem>, SourceError> {
+    let mut command = Command::new("yt-dlp");
+    command.arg("--flat-playlist").arg("--dump-json");
+    if count < usize::MAX {
+        command.arg("--playlist-end").arg(count.to_string());
+    }
+    let output = command.arg(&source.url).output().await.map_err(SourceError::PlaylistError)?;
+    if !output.status.success() {
+        return Err(SourceError::PlaylistError(std::io::Error::other(format!(
+            "yt-dlp failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ))));
+    }
+    flat_playlist_items(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Items for the videos in `yt-dlp --flat-playlist --dump-json` output, one
+/// JSON object per line, each linking to the video itself.
+pub fn flat_playlist_items(output: &str) -> Result<Vec<SourceItem>, SourceError> {
+    output
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let entry: FlatPlaylistEntry =
+                serde_json::from_str(line).map_err(|e| SourceError::ParseError(e.to_string()))?;
+            // Older versions of yt-dlp give just the video's id as its url.
+            let url = entry
+                .url
+                .filter(|url| url.starts_with("http"))
+                .unwrap_or_else(|| format!("https://www.youtube.com/watch?v={}", entry.id));
+            Ok(SourceItem::from_url_and_title(&url, &entry.title.unwrap_or(entry.id)))
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+/// Why an item from a feed won't be imported.
+pub enum SkipReason {
+    /// A lesson with this title already exists in the course.
+    TitleMatch(String),
+    /// An item with this GUID has been imported from the source already.
+    GuidMatch(String),
+    /// The item has no title, so we can't check whether it already exists.
+    NoTitle,
+    /// We couldn't find a link to any audio for the item.
+    NoAudio,
+    /// The item isn't in any of the requested categories.
+    NotInCategory,
+    /// The source's title_include or title_exclude filtered the item out.
+    TitleFiltered,
+    /// The item's audio is already in download_dir, at this path, and
+    /// existing_downloads is "skip".
+    Downloaded(PathBuf),
+}
+
+impl Display for SkipReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SkipReason::TitleMatch(title) => write!(f, "title match: {}", title),
+            SkipReason::GuidMatch(guid) => write!(f, "GUID match: {}", guid),
+            SkipReason::NoTitle => write!(f, "no title"),
+            SkipReason::NoAudio => write!(f, "no audio"),
+            SkipReason::NotInCategory => write!(f, "not in category"),
+            SkipReason::TitleFiltered => write!(f, "filtered by title"),
+            SkipReason::Downloaded(path) => write!(f, "already downloaded: {}", path.display()),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -176,16 +732,224 @@ impl Feed {
     /// We don't know if a link is RSS or Atom. So first we try to parse it as
     /// RSS. If that fails, we try to parse it as Atom.
     pub async fn from_source(source: &Source) -> Result<Self, SourceError> {
-        let content = reqwest::get(&source.url).await?.bytes().await?;
-        rss::Channel::read_from(&content[..])
+        let (content, moved_to) = Self::fetch_noting_moves(&source.url, source.feed_headers()?).await?;
+        if let Some(moved_to) = moved_to {
+            crate::report::warn(
+                "feed-moved",
+                Some(&source.name),
+                format!(
+                    "the feed for {} has permanently moved to {}; update its url (or sync with --update-redirected-urls)",
+                    source.name, moved_to
+                ),
+            );
+            MOVED_FEEDS.lock().unwrap().push((source.url.clone(), moved_to));
+        }
+        let feed = match Self::parse(&content) {
+            Ok(feed) => feed,
+            Err(e) if !source.lenient_parsing => Self::parse_item_by_item(&content, &source.name).ok_or(e)?,
+            Err(e) => {
+                let content = sanitize_feed(&content);
+                let feed = match Self::parse(&content) {
+                    Ok(feed) => feed,
+                    Err(_) => Self::parse_item_by_item(&content, &source.name).ok_or(e)?,
+                };
+                crate::report::warn(
+                    "sanitized-feed",
+                    Some(&source.name),
+                    format!("feed for {} is not valid XML, parsed it after sanitizing", source.name),
+                );
+                feed
+            }
+        };
+        Ok(feed.with_absolute_links(&source.url))
+    }
+
+    /// Fetch and parse a feed which isn't (necessarily) a configured source.
+    pub async fn from_url(url: &str) -> Result<Self, SourceError> {
+        let content = Self::fetch(url, HeaderMap::new()).await?;
+        let feed = match Self::parse(&content) {
+            Ok(feed) => feed,
+            Err(e) => Self::parse_item_by_item(&content, url).ok_or(e)?,
+        };
+        Ok(feed.with_absolute_links(url))
+    }
+
+    /// Parse a feed which doesn't parse as a whole because of some of its
+    /// items (e.g. an Atom entry with a date that isn't one), by parsing
+    /// each item on its own and leaving out the ones which fail, with a
+    /// warning naming `name` (the source, or the feed's URL).
+    ///
+    /// Returns None if the problem isn't with individual items: the feed
+    /// doesn't parse even without them, or none of them parse.
+    fn parse_item_by_item(content: &[u8], name: &str) -> Option<Self> {
+        for (open, close) in [("<item", "</item>"), ("<entry", "</entry>")] {
+            let Some(SplitFeed { skeleton, insert_at, items }) = split_out_elements(content, open, close) else {
+                continue;
+            };
+            let Ok(mut feed) = Self::parse(&skeleton) else {
+                continue;
+            };
+            let total = items.len();
+            for item in items {
+                let mut one = skeleton.clone();
+                one.splice(insert_at..insert_at, item.iter().copied());
+                match (&mut feed, Self::parse(&one)) {
+                    (Feed::Rss(channel), Ok(Feed::Rss(mut parsed))) if parsed.items.len() == 1 => {
+                        channel.items.push(parsed.items.remove(0));
+                    }
+                    (Feed::Atom(feed), Ok(Feed::Atom(mut parsed))) if parsed.entries.len() == 1 => {
+                        feed.entries.push(parsed.entries.remove(0));
+                    }
+                    _ => {}
+                }
+            }
+            let parsed = match &feed {
+                Feed::Rss(channel) => channel.items.len(),
+                Feed::Atom(feed) => feed.entries.len(),
+            };
+            if parsed == 0 {
+                return None;
+            }
+            crate::report::warn(
+                "skipped-item",
+                Some(name),
+                format!("skipped {} of {} items in the feed for {}, which couldn't be parsed", total - parsed, total, name),
+            );
+            return Some(feed);
+        }
+        None
+    }
+
+    /// Large Atom feeds only carry their latest entries, and link to older
+    /// ones using RFC 5005 paging (rel="next" or rel="prev-archive"). Follow
+    /// those links until the feed has at least `count` entries or there are
+    /// no more pages. RSS has no equivalent, so RSS feeds are left alone.
+    pub async fn fetch_older_pages(&mut self, feed_url: &str, headers: HeaderMap, count: usize) -> Result<(), SourceError> {
+        let Feed::Atom(feed) = self else {
+            return Ok(());
+        };
+        let mut page = feed.clone();
+        let mut page_url = feed_url.to_string();
+        let mut seen = vec![page_url.clone()];
+        while feed.entries.len() < count && seen.len() < MAX_FEED_PAGES {
+            let next = page
+                .links()
+                .iter()
+                .find(|link| link.rel() == "next" || link.rel() == "prev-archive")
+                .and_then(|link| Url::parse(&page_url).and_then(|url| url.join(link.href())).ok());
+            let Some(next) = next.map(String::from).filter(|next| !seen.contains(next)) else {
+                break;
+            };
+            let content = Self::fetch(&next, headers.clone()).await?;
+            let Feed::Atom(next_page) = Self::parse(&content)?.with_absolute_links(&next) else {
+                break;
+            };
+            feed.entries.extend(next_page.entries.iter().cloned());
+            seen.push(next.clone());
+            page = next_page;
+            page_url = next;
+        }
+        Ok(())
+    }
+
+    /// Some self-hosted feeds give audio links relative to the feed, which
+    /// nothing downstream can download. Resolve them against the feed's
+    /// declared base (Atom's xml:base) if it has one, or else the URL we
+    /// fetched it from. Links which are already absolute are left alone.
+    fn with_absolute_links(mut self, feed_url: &str) -> Self {
+        let declared_base = match &self {
+            Feed::Atom(feed) => feed.base(),
+            Feed::Rss(_) => None,
+        };
+        let base = declared_base
+            .and_then(|base| Url::parse(feed_url).and_then(|url| url.join(base)).ok())
+            .or_else(|| Url::parse(feed_url).ok());
+        let Some(base) = base else {
+            return self;
+        };
+        let resolve = |link: &str| {
+            base.join(link).map(String::from).unwrap_or_else(|_| link.to_string())
+        };
+        match &mut self {
+            Feed::Rss(channel) => {
+                for enclosure in channel.items.iter_mut().filter_map(|item| item.enclosure.as_mut()) {
+                    enclosure.url = resolve(&enclosure.url);
+                }
+                let transcripts = channel
+                    .items
+                    .iter_mut()
+                    .filter_map(|item| item.extensions.get_mut("podcast")?.get_mut("transcript"))
+                    .flatten();
+                for url in transcripts.filter_map(|transcript| transcript.attrs.get_mut("url")) {
+                    *url = resolve(url);
+                }
+            }
+            Feed::Atom(feed) => {
+                for link in feed.entries.iter_mut().flat_map(|entry| entry.links.iter_mut()) {
+                    link.href = resolve(&link.href);
+                }
+            }
+        }
+        self
+    }
+
+    async fn fetch(url: &str, headers: HeaderMap) -> Result<Vec<u8>, SourceError> {
+        Ok(Self::fetch_noting_moves(url, headers).await?.0)
+    }
+
+    /// Fetch a feed, also returning where it has permanently moved to if it
+    /// was reached through permanent redirects (301 or 308). Only redirects
+    /// which are all permanent from the start count, since the URL after a
+    /// temporary one isn't where the feed lives.
+    async fn fetch_noting_moves(url: &str, headers: HeaderMap) -> Result<(Vec<u8>, Option<String>), SourceError> {
+        // (whether every redirect so far was permanent, where they lead)
+        let moves: Arc<Mutex<(bool, Option<String>)>> = Arc::new(Mutex::new((true, None)));
+        let policy_moves = moves.clone();
+        let policy = reqwest::redirect::Policy::custom(move |attempt| {
+            let mut moves = policy_moves.lock().unwrap();
+            let permanent = matches!(
+                attempt.status(),
+                reqwest::StatusCode::MOVED_PERMANENTLY | reqwest::StatusCode::PERMANENT_REDIRECT
+            );
+            moves.0 &= permanent;
+            if moves.0 {
+                moves.1 = Some(attempt.url().to_string());
+            }
+            // The same limit as reqwest's default policy.
+            if attempt.previous().len() > 10 {
+                attempt.error("too many redirects")
+            } else {
+                attempt.follow()
+            }
+        });
+        let client = reqwest::Client::builder().redirect(policy).build()?;
+        let response = client.get(url).headers(headers).send().await?;
+        let content = response.bytes().await?.to_vec();
+        let moved_to = moves.lock().unwrap().1.take();
+        Ok((content, moved_to))
+    }
+
+    fn parse(content: &[u8]) -> Result<Self, SourceError> {
+        rss::Channel::read_from(content)
             .map(Feed::Rss)
             .or_else(|_| {
-                atom_syndication::Feed::read_from(&content[..])
+                atom_syndication::Feed::read_from(content)
                     .map(Feed::Atom)
             })
             .map_err(|_| SourceError::ParseError("Could not parse as RSS or Atom feed".to_string()))
     }
 
+    /// The language the feed says it's in, as a LingQ language code (e.g.
+    /// "de" for "de-DE").
+    pub fn language(&self) -> Option<String> {
+        let language = match self {
+            Feed::Rss(channel) => channel.language.clone(),
+            Feed::Atom(feed) => feed.lang.clone(),
+        }?;
+        let code = language.trim().split(['-', '_']).next()?.to_lowercase();
+        Some(code).filter(|code| !code.is_empty())
+    }
+
     pub fn items(&self, count: usize) -> Vec<SourceItem> {
         match self {
             Feed::Rss(channel) => channel
@@ -204,11 +968,205 @@ impl Feed {
     }
 }
 
+/// A feed with its items cut out, see split_out_elements.
+struct SplitFeed<'a> {
+    /// The feed without its items.
+    skeleton: Vec<u8>,
+    /// Where in the skeleton the first item was.
+    insert_at: usize,
+    /// The items, in order.
+    items: Vec<&'a [u8]>,
+}
+
+/// Cut every `open ... close` element (e.g. "<item" and "</item>") out of a
+/// feed. None if there are no such elements.
+fn split_out_elements<'a>(content: &'a [u8], open: &str, close: &str) -> Option<SplitFeed<'a>> {
+    let find = |haystack: &[u8], needle: &[u8]| haystack.windows(needle.len()).position(|window| window == needle);
+    let mut skeleton = Vec::with_capacity(content.len());
+    let mut elements = vec![];
+    let mut insert_at = None;
+    let mut rest = content;
+    while let Some(start) = find(rest, open.as_bytes()) {
+        // Only the element itself, not e.g. <itemRef> or <entryCount>.
+        let after = rest.get(start + open.len()).copied();
+        if !matches!(after, Some(b'>' | b'/' | b' ' | b'\t' | b'\n' | b'\r')) {
+            skeleton.extend_from_slice(&rest[..start + open.len()]);
+            rest = &rest[start + open.len()..];
+            continue;
+        }
+        let end = find(&rest[start..], close.as_bytes())? + start + close.len();
+        skeleton.extend_from_slice(&rest[..start]);
+        insert_at.get_or_insert(skeleton.len());
+        elements.push(&rest[start..end]);
+        rest = &rest[end..];
+    }
+    skeleton.extend_from_slice(rest);
+    Some(SplitFeed { skeleton, insert_at: insert_at?, items: elements })
+}
+
+/// Feeds found to have permanently moved so far this run, as (configured
+/// URL, new URL).
+pub fn moved_feeds() -> Vec<(String, String)> {
+    MOVED_FEEDS.lock().unwrap().clone()
+}
+
+/// Named entities which are valid in HTML but not XML, and often show up in
+/// feeds anyway.
+const HTML_ENTITIES: &[(&str, &str)] = &[
+    ("nbsp", "#160"),
+    ("copy", "#169"),
+    ("reg", "#174"),
+    ("ndash", "#8211"),
+    ("mdash", "#8212"),
+    ("lsquo", "#8216"),
+    ("rsquo", "#8217"),
+    ("ldquo", "#8220"),
+    ("rdquo", "#8221"),
+    ("hellip", "#8230"),
+];
+
+/// Clean up the most common ways feeds fail to be valid XML.
+///
+/// Control characters which XML forbids are dropped, HTML-only named
+/// entities are turned into numeric references, and any other `&` which
+/// doesn't start an entity is escaped.
+fn sanitize_feed(content: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(content.len());
+    let mut i = 0;
+    while i < content.len() {
+        let byte = content[i];
+        i += 1;
+        match byte {
+            b'\t' | b'\n' | b'\r' => out.push(byte),
+            0x00..=0x1f => {}
+            b'&' => {
+                let rest = &content[i..];
+                let name_len = rest
+                    .iter()
+                    .take(32)
+                    .position(|b| *b == b';')
+                    .filter(|len| *len > 0 && rest[..*len].iter().all(|b| b.is_ascii_alphanumeric() || *b == b'#'));
+                match name_len.map(|len| std::str::from_utf8(&rest[..len]).unwrap_or_default()) {
+                    Some(name) if name.starts_with('#') || ["amp", "lt", "gt", "quot", "apos"].contains(&name) => {
+                        out.push(b'&');
+                    }
+                    Some(name) => match HTML_ENTITIES.iter().find(|(html, _)| *html == name) {
+                        Some((_, numeric)) => {
+                            out.push(b'&');
+                            out.extend_from_slice(numeric.as_bytes());
+                            i += name.len();
+                        }
+                        None => out.extend_from_slice(b"&amp;"),
+                    },
+                    None => out.extend_from_slice(b"&amp;"),
+                }
+            }
+            _ => out.push(byte),
+        }
+    }
+    out
+}
+
+/// Turn a title into something safe to use as (part of) a filename, by
+/// replacing path separators and other characters filesystems dislike.
+pub fn safe_filename(title: &str) -> String {
+    title
+        .trim()
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// Longest filename (in bytes, including the extension) safe_filename_within
+/// will produce. Most filesystems allow 255.
+const MAX_FILENAME_BYTES: usize = 200;
+
+/// Like safe_filename, but also keeps the result (stem plus extension) under
+/// MAX_FILENAME_BYTES.
+pub fn safe_filename_within(stem: &str, ext: &str) -> String {
+    let mut stem = safe_filename(stem);
+    let max_stem = MAX_FILENAME_BYTES.saturating_sub(ext.len() + 1);
+    if stem.len() > max_stem {
+        let mut end = max_stem;
+        while !stem.is_char_boundary(end) {
+            end -= 1;
+        }
+        stem.truncate(end);
+    }
+    format!("{}.{}", stem.trim_end(), ext)
+}
+
+/// The longest lesson description LingQ accepts, in characters.
+const MAX_DESCRIPTION_CHARS: usize = 2000;
+
+/// Turn HTML into plain text, keeping line breaks where the HTML had them.
+pub(crate) fn html_to_text(html: &str) -> String {
+    let mut text = String::new();
+    let mut tag = None::<String>;
+    for c in html.chars() {
+        match (&mut tag, c) {
+            (None, '<') => tag = Some(String::new()),
+            (None, c) => text.push(c),
+            (Some(name), '>') => {
+                let name = name.trim_start_matches('/').split_whitespace().next().unwrap_or_default().to_lowercase();
+                if ["br", "br/", "p", "div", "li"].contains(&name.as_str()) {
+                    text.push('\n');
+                }
+                tag = None;
+            }
+            (Some(name), c) => name.push(c),
+        }
+    }
+    let text = html_escape::decode_html_entities(&text);
+    let lines: Vec<String> = text
+        .lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|line| !line.is_empty())
+        .collect();
+    lines.join("\n")
+}
+
+/// Fetch a transcript published at `url`, as plain text.
+pub async fn fetch_transcript(url: &str) -> Result<String, SourceError> {
+    let body = reqwest::get(url).await?.error_for_status()?.text().await?;
+    Ok(html_to_text(&body))
+}
+
+/// Turn an HTML description into plain text, cut down to
+/// MAX_DESCRIPTION_CHARS.
+fn plain_description(html: &str) -> String {
+    let text = html_to_text(html);
+    if text.chars().count() <= MAX_DESCRIPTION_CHARS {
+        return text;
+    }
+    // Leave room for the ellipsis.
+    let (end, _) = text.char_indices().nth(MAX_DESCRIPTION_CHARS - 1).unwrap();
+    format!("{}…", text[..end].trim_end())
+}
+
+/// Decode HTML entities in a title, e.g. "Tom &amp; Jerry" to "Tom & Jerry".
+pub fn decode_title(title: &str) -> String {
+    html_escape::decode_html_entities(title).into_owned()
+}
+
 impl SourceItem {
     pub fn from_url_and_title(url: &str, title: &str) -> Self {
         SourceItem::Static(StaticItem { url: url.into(), title: title.into() })
     }
 
+    /// An item for a local audio file, titled by its tags if it has a
+    /// title there, or else its filename.
+    pub fn from_file(path: &Path) -> Self {
+        let title = audio::title(path).unwrap_or_else(|| {
+            path.file_stem().unwrap_or_default().to_string_lossy().into_owned()
+        });
+        SourceItem::Local(LocalItem { path: path.to_path_buf(), title })
+    }
+
     pub fn get_audio_link(&self) -> Option<String> {
         match self {
             SourceItem::Rss(item) => {
@@ -218,18 +1176,177 @@ impl SourceItem {
                 entry.links().first().map(|link| link.href().to_string())
             }
             SourceItem::Static(item) => Some(item.url.clone()),
+            SourceItem::Local(item) => Some(item.path.to_string_lossy().into_owned()),
         }
     }
 
+    /// The item's description (e.g. show notes) as plain text, if it has one.
+    pub fn description(&self) -> Option<String> {
+        let html = match self {
+            SourceItem::Rss(item) => item.description.clone(),
+            SourceItem::Atom(entry) => entry.summary().map(|summary| summary.value.clone()),
+            SourceItem::Static(_) | SourceItem::Local(_) => None,
+        }?;
+        Some(plain_description(&html)).filter(|description| !description.is_empty())
+    }
+
+    /// The item's full content as plain text, for items imported without
+    /// audio. Falls back to the description if there's no separate content.
+    pub fn text(&self) -> Option<String> {
+        let html = match self {
+            SourceItem::Rss(item) => item.content.clone().or(item.description.clone()),
+            SourceItem::Atom(entry) => entry
+                .content()
+                .and_then(|content| content.value.clone())
+                .or(entry.summary().map(|summary| summary.value.clone())),
+            SourceItem::Static(_) | SourceItem::Local(_) => None,
+        }?;
+        Some(html_to_text(&html)).filter(|text| !text.is_empty())
+    }
+
+    /// The item's title, with any HTML entities (`&amp;`, `&#8217;`, ...)
+    /// decoded, since feeds often double-escape them.
     pub fn title(&self) -> Option<String> {
-        match self {
+        let title = match self {
             SourceItem::Rss(item) => item.title.clone(),
             SourceItem::Atom(entry) => Some(entry.title().to_string()),
             SourceItem::Static(item) => Some(item.title.clone()),
+            SourceItem::Local(item) => Some(item.title.clone()),
+        };
+        title.map(|title| decode_title(&title))
+    }
+
+    /// The item's web page, if its feed gives one.
+    pub fn link(&self) -> Option<String> {
+        match self {
+            SourceItem::Rss(item) => item.link().map(str::to_string),
+            SourceItem::Atom(entry) => {
+                entry.links().iter().find(|link| link.rel() == "alternate").map(|link| link.href().to_string())
+            }
+            SourceItem::Static(_) | SourceItem::Local(_) => None,
+        }
+    }
+
+    /// Where to fetch the item's transcript from, filling in the
+    /// placeholders of a transcript_url_template.
+    pub fn transcript_url(&self, template: &str, source_name: &str) -> String {
+        // Spaces as %20 rather than +, which only means a space in a query.
+        let encode = |value: &str| url::form_urlencoded::byte_serialize(value.as_bytes()).collect::<String>().replace('+', "%20");
+        let date = self.published().map(|date| date.format("%Y-%m-%d").to_string()).unwrap_or_default();
+        template
+            .replace("{source}", &encode(source_name))
+            .replace("{title}", &encode(&self.title().unwrap_or_default()))
+            .replace("{date}", &date)
+            .replace("{guid}", &self.guid().unwrap_or_default())
+            .replace("{link}", &self.link().unwrap_or_default())
+            .replace("{audio}", &self.get_audio_link().unwrap_or_default())
+    }
+
+    /// A link to subtitles for the item, if its feed gives one in a format
+    /// subtitles::parse reads (see use_subtitles).
+    pub fn subtitles_link(&self) -> Option<String> {
+        let SourceItem::Rss(item) = self else {
+            return None;
+        };
+        item.extensions
+            .get("podcast")?
+            .get("transcript")?
+            .iter()
+            .find(|transcript| {
+                transcript.attrs.get("type").is_some_and(|kind| SUBTITLE_TYPES.contains(&kind.as_str()))
+            })
+            .and_then(|transcript| transcript.attrs.get("url").cloned())
+    }
+
+    /// A stable identifier for the item: the RSS `<guid>` or Atom `<id>`,
+    /// falling back to the audio link. Local files go by their filename.
+    pub fn guid(&self) -> Option<String> {
+        match self {
+            SourceItem::Rss(item) => item
+                .guid()
+                .map(|guid| guid.value().to_string())
+                .or_else(|| self.get_audio_link()),
+            SourceItem::Atom(entry) => Some(entry.id().to_string()),
+            SourceItem::Static(item) => Some(item.url.clone()),
+            SourceItem::Local(item) => item.path.file_name().map(|name| name.to_string_lossy().into_owned()),
+        }
+    }
+
+    /// What the item's title is compared with to find its lesson. That's
+    /// the title, except for local files, which are known by their filename
+    /// since a folder's tags often give every file the same title.
+    fn dedup_title(&self) -> Option<String> {
+        match self {
+            SourceItem::Local(item) => item.path.file_stem().map(|stem| stem.to_string_lossy().into_owned()),
+            _ => self.title(),
         }
     }
 
-    pub async fn download_audio(&self, method: DownloadMethod) -> Result<Vec<u8>, SourceError> {
-        fetch(self, method)
+    /// When the item was published, if the feed says (and we can parse it).
+    pub fn published(&self) -> Option<DateTime<FixedOffset>> {
+        match self {
+            SourceItem::Rss(item) => item
+                .pub_date()
+                .and_then(|date| DateTime::parse_from_rfc2822(date.trim()).ok()),
+            SourceItem::Atom(entry) => Some(*entry.published().unwrap_or(entry.updated())),
+            SourceItem::Static(_) | SourceItem::Local(_) => None,
+        }
+    }
+
+    /// Categories the feed assigns to this item (RSS `<category>` or Atom
+    /// `<category term="...">`).
+    pub fn categories(&self) -> Vec<String> {
+        match self {
+            SourceItem::Rss(item) => item.categories().iter().map(|c| c.name().to_string()).collect(),
+            SourceItem::Atom(entry) => entry.categories().iter().map(|c| c.term().to_string()).collect(),
+            SourceItem::Static(_) | SourceItem::Local(_) => vec![],
+        }
+    }
+
+    /// Whether the item has any of the given categories (case-insensitively).
+    /// An empty list of categories matches everything.
+    pub fn has_any_category(&self, categories: &[String]) -> bool {
+        if categories.is_empty() {
+            return true;
+        }
+        self.categories()
+            .iter()
+            .any(|category| categories.iter().any(|c| c.eq_ignore_ascii_case(category)))
+    }
+
+    /// Decide whether this item should be skipped, without downloading
+    /// anything.
+    ///
+    /// Every check that can cause an item to be skipped belongs here, so that
+    /// the (expensive) audio download only ever happens for items which will
+    /// actually be imported.
+    pub fn skip_reason(&self, lesson_titles: &[String], categories: &[String], require_audio: bool) -> Option<SkipReason> {
+        if !self.has_any_category(categories) {
+            return Some(SkipReason::NotInCategory);
+        }
+        let title = match self.dedup_title() {
+            Some(title) => title,
+            None => return Some(SkipReason::NoTitle),
+        };
+        // Lessons imported before titles were decoded may still have the raw
+        // entities in them, so decode both sides.
+        if lesson_titles.iter().any(|lesson_title| decode_title(lesson_title) == title) {
+            return Some(SkipReason::TitleMatch(title));
+        }
+        if self.get_audio_link().is_none() && (require_audio || self.text().is_none()) {
+            return Some(SkipReason::NoAudio);
+        }
+        None
+    }
+
+    /// Get the item's audio. Local files are read straight from disk (as
+    /// mp3), off the async runtime since that may mean converting them;
+    /// anything else is downloaded with `method`.
+    pub async fn download_audio(&self, method: DownloadMethod, options: DownloadOptions<'_>) -> Result<Vec<u8>, SourceError> {
+        if let SourceItem::Local(item) = self {
+            let path = item.path.clone();
+            return audio::blocking(move || audio::read_as_mp3(&path)).await.map_err(SourceError::from);
+        }
+        fetch(self, method, options).await
     }
 }
@@ -0,0 +1,368 @@
+//! Local state which lqcli keeps between runs, stored as JSON next to the
+//! configuration file.
+
+use crate::source::SourceItem;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How many items the rolling average of item processing times covers
+/// (roughly; older items fade out rather than drop off).
+const TIMING_WINDOW: u64 = 10;
+
+/// How many times a source's poll_interval can double while it has nothing
+/// new.
+const MAX_POLL_BACKOFF: u32 = 3;
+
+/// Where state is kept when the configuration was read from a URL.
+const REMOTE_STATE_PATH: &str = "~/.lqcli-remote.state.json";
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct State {
+    /// Per-source state, keyed by source name.
+    #[serde(default)]
+    pub sources: HashMap<String, SourceState>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct SourceState {
+    /// Rolling average of how long one item takes to download, transcribe
+    /// and import (in seconds).
+    pub average_item_seconds: Option<f64>,
+
+    /// How many items went into average_item_seconds (capped at
+    /// TIMING_WINDOW).
+    #[serde(default)]
+    pub timed_items: u64,
+
+    /// Rolling average of what one item costs in API usage (in US dollars).
+    pub average_item_cost: Option<f64>,
+
+    /// How many runs went into average_item_cost (capped at TIMING_WINDOW).
+    #[serde(default)]
+    pub costed_runs: u64,
+
+    /// Items which failed the last time they were tried, so they can be
+    /// retried individually.
+    #[serde(default)]
+    pub failed_items: Vec<FailedItem>,
+
+    /// Titles of items which were imported as part of a merged lesson (see
+    /// merge_window), and so don't have a lesson of their own to be found by.
+    #[serde(default)]
+    pub merged_titles: Vec<String>,
+
+    /// When the source was last synced, in seconds since the Unix epoch.
+    pub last_synced: Option<u64>,
+
+    /// How many syncs in a row have found nothing new.
+    #[serde(default)]
+    pub quiet_syncs: u32,
+
+    /// Items transcribed by `sources prefetch` but not imported yet, by
+    /// audio link, with the transcript cache key of their transcripts.
+    #[serde(default)]
+    pub prefetched: HashMap<String, String>,
+
+    /// GUIDs of the items imported from the source (see dedup).
+    #[serde(default)]
+    pub imported_guids: HashSet<String>,
+}
+
+/// Enough about an item which failed to process it again on its own.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct FailedItem {
+    pub title: String,
+    pub guid: Option<String>,
+    /// The item's audio (or video) link.
+    pub url: String,
+    /// What went wrong, for display.
+    pub error: String,
+    /// The language the item was being imported as, in case the source
+    /// takes its language from the feed.
+    #[serde(default)]
+    pub language: Option<String>,
+}
+
+/// Something the state file has out of step with LingQ or the
+/// configuration, found by `sources reconcile`.
+#[derive(Debug, PartialEq)]
+pub enum Discrepancy {
+    /// State kept for a source which isn't in the configuration any more.
+    UnknownSource,
+    /// An item recorded as failed which has a lesson after all, e.g. one
+    /// imported by hand.
+    FailedButImported { title: String, url: String },
+    /// A prefetched transcript of an item which already has a lesson, and
+    /// so will never be used.
+    PrefetchedButImported { title: String, url: String },
+    /// An item with a lesson whose GUID isn't among the imported ones, e.g.
+    /// one imported before they were kept.
+    GuidNotRecorded { title: String, guid: String },
+}
+
+impl fmt::Display for Discrepancy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Discrepancy::UnknownSource => write!(f, "not in the configuration any more"),
+            Discrepancy::FailedButImported { title, .. } => {
+                write!(f, "{} is recorded as failed, but is in LingQ", title)
+            }
+            Discrepancy::PrefetchedButImported { title, .. } => {
+                write!(f, "{} has a prefetched transcript, but is in LingQ already", title)
+            }
+            Discrepancy::GuidNotRecorded { title, .. } => {
+                write!(f, "{} is in LingQ, but isn't recorded as imported", title)
+            }
+        }
+    }
+}
+
+impl State {
+    /// Where the state for a given configuration file lives, e.g.
+    /// ~/.lqcli.toml keeps its state in ~/.lqcli.state.json.
+    ///
+    /// Configurations read from a URL keep their state in
+    /// ~/.lqcli-remote.state.json.
+    pub fn path_for(config_path: &str) -> String {
+        if crate::config::is_remote(config_path).unwrap_or(false) {
+            return shellexpand::tilde(REMOTE_STATE_PATH).to_string();
+        }
+        let normalized_path = shellexpand::tilde(config_path).to_string();
+        Path::new(&normalized_path)
+            .with_extension("state.json")
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// Read the state file, or start with empty state if there isn't one yet.
+    pub fn read(path: &str) -> Result<Self, std::io::Error> {
+        match std::fs::read_to_string(path) {
+            Ok(json) => serde_json::from_str(&json)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), std::io::Error> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        std::fs::write(path, json)
+    }
+
+    /// Fold the time it took to process one item into the source's rolling
+    /// average.
+    pub fn record_item_time(&mut self, source: &str, elapsed: Duration) {
+        let source_state = self.sources.entry(source.to_string()).or_default();
+        let seconds = elapsed.as_secs_f64();
+        source_state.timed_items = (source_state.timed_items + 1).min(TIMING_WINDOW);
+        source_state.average_item_seconds = Some(match source_state.average_item_seconds {
+            Some(average) => average + (seconds - average) / source_state.timed_items as f64,
+            None => seconds,
+        });
+    }
+
+    /// Fold the per-item cost of a run into the source's rolling average.
+    pub fn record_item_cost(&mut self, source: &str, cost: f64) {
+        let source_state = self.sources.entry(source.to_string()).or_default();
+        source_state.costed_runs = (source_state.costed_runs + 1).min(TIMING_WINDOW);
+        source_state.average_item_cost = Some(match source_state.average_item_cost {
+            Some(average) => average + (cost - average) / source_state.costed_runs as f64,
+            None => cost,
+        });
+    }
+
+    /// Estimate what it will cost to process `items` more items from a
+    /// source, if we have any history for it.
+    pub fn estimate_cost(&self, source: &str, items: usize) -> Option<f64> {
+        let average = self.sources.get(source)?.average_item_cost?;
+        Some(average * items as f64)
+    }
+
+    /// Remember the titles of items imported together as one lesson.
+    pub fn record_merged(&mut self, source: &str, titles: impl IntoIterator<Item = String>) {
+        let source_state = self.sources.entry(source.to_string()).or_default();
+        source_state.merged_titles.extend(titles);
+    }
+
+    /// Remember the GUIDs of items which were just imported.
+    pub fn record_imported(&mut self, source: &str, guids: impl IntoIterator<Item = String>) {
+        let source_state = self.sources.entry(source.to_string()).or_default();
+        source_state.imported_guids.extend(guids);
+    }
+
+    /// Whether an item with this GUID has been imported from the source.
+    pub fn is_imported(&self, source: &str, guid: &str) -> bool {
+        self.sources.get(source).is_some_and(|source_state| source_state.imported_guids.contains(guid))
+    }
+
+    /// Titles of a source's items which were imported into merged lessons.
+    pub fn merged_titles(&self, source: &str) -> &[String] {
+        self.sources.get(source).map(|source_state| source_state.merged_titles.as_slice()).unwrap_or_default()
+    }
+
+    /// Remember that an item failed, replacing any earlier failure of the
+    /// same item.
+    pub fn record_failure(&mut self, source: &str, failure: FailedItem) {
+        let source_state = self.sources.entry(source.to_string()).or_default();
+        source_state.failed_items.retain(|item| item.url != failure.url);
+        source_state.failed_items.push(failure);
+    }
+
+    /// Forget about an item's failure, once it has succeeded.
+    pub fn clear_failure(&mut self, source: &str, url: &str) {
+        if let Some(source_state) = self.sources.get_mut(source) {
+            source_state.failed_items.retain(|item| item.url != url);
+        }
+    }
+
+    /// Remember that an item's transcript is waiting in the transcript cache
+    /// under `key`.
+    pub fn record_prefetched(&mut self, source: &str, url: &str, key: &str) {
+        let source_state = self.sources.entry(source.to_string()).or_default();
+        source_state.prefetched.insert(url.to_string(), key.to_string());
+    }
+
+    /// Forget about an item's prefetched transcript, once it's imported.
+    pub fn clear_prefetched(&mut self, source: &str, url: &str) {
+        if let Some(source_state) = self.sources.get_mut(source) {
+            source_state.prefetched.remove(url);
+        }
+    }
+
+    /// The transcript cache key of an item's prefetched transcript, if it
+    /// has one.
+    pub fn prefetched_key(&self, source: &str, url: &str) -> Option<&str> {
+        self.sources.get(source)?.prefetched.get(url).map(String::as_str)
+    }
+
+    /// Find failed items by their title or GUID, along with the names of
+    /// their sources, sorted by source. Different sources can have failed
+    /// items with the same title.
+    pub fn find_failures(&self, title_or_guid: &str) -> Vec<(&str, &FailedItem)> {
+        let mut found: Vec<(&str, &FailedItem)> = self
+            .sources
+            .iter()
+            .filter_map(|(name, source_state)| {
+                source_state
+                    .failed_items
+                    .iter()
+                    .find(|item| item.title == title_or_guid || item.guid.as_deref() == Some(title_or_guid))
+                    .map(|item| (name.as_str(), item))
+            })
+            .collect();
+        found.sort_by_key(|(name, _)| *name);
+        found
+    }
+
+    /// Move a source's state to a new name, after the source is renamed.
+    pub fn rename_source(&mut self, old: &str, new: &str) {
+        if let Some(source_state) = self.sources.remove(old) {
+            self.sources.insert(new.to_string(), source_state);
+        }
+    }
+
+    /// Sources with state which aren't among `names`, sorted by name.
+    pub fn unknown_sources(&self, names: &[&str]) -> Vec<String> {
+        let mut unknown: Vec<String> =
+            self.sources.keys().filter(|name| !names.contains(&name.as_str())).cloned().collect();
+        unknown.sort();
+        unknown
+    }
+
+    /// Compare what's recorded about a source and its current `items` with
+    /// the titles of its lessons in LingQ.
+    ///
+    /// Merged lessons (see merge_window) aren't checked, since only the
+    /// titles of the items in them are kept, not the lessons' own titles.
+    pub fn reconcile(&self, source: &str, lesson_titles: &[String], items: &[SourceItem]) -> Vec<Discrepancy> {
+        let source_state = self.sources.get(source);
+        // As when skipping items, lessons may have been imported before
+        // titles were decoded.
+        let in_lingq = |title: &str| {
+            let title = crate::source::decode_title(title);
+            lesson_titles.iter().any(|lesson_title| crate::source::decode_title(lesson_title) == title)
+        };
+        let mut discrepancies: Vec<Discrepancy> = source_state
+            .iter()
+            .flat_map(|source_state| &source_state.failed_items)
+            .filter(|item| in_lingq(&item.title))
+            .map(|item| Discrepancy::FailedButImported { title: item.title.clone(), url: item.url.clone() })
+            .collect();
+        for item in items {
+            let Some(title) = item.title().filter(|title| in_lingq(title)) else {
+                continue;
+            };
+            if let Some(url) = item.get_audio_link().filter(|url| self.prefetched_key(source, url).is_some()) {
+                discrepancies.push(Discrepancy::PrefetchedButImported { title: title.clone(), url });
+            }
+            if let Some(guid) = item.guid().filter(|guid| !self.is_imported(source, guid)) {
+                discrepancies.push(Discrepancy::GuidNotRecorded { title, guid });
+            }
+        }
+        discrepancies
+    }
+
+    /// Bring the state in line with LingQ and the configuration again.
+    pub fn repair(&mut self, source: &str, discrepancy: &Discrepancy) {
+        match discrepancy {
+            Discrepancy::UnknownSource => {
+                self.sources.remove(source);
+            }
+            Discrepancy::FailedButImported { url, .. } => self.clear_failure(source, url),
+            Discrepancy::PrefetchedButImported { url, .. } => self.clear_prefetched(source, url),
+            Discrepancy::GuidNotRecorded { guid, .. } => self.record_imported(source, [guid.clone()]),
+        }
+    }
+
+    /// Remember that a source was just synced, and whether it had anything
+    /// new.
+    pub fn record_sync(&mut self, source: &str, found_new: bool) {
+        let source_state = self.sources.entry(source.to_string()).or_default();
+        source_state.last_synced = Some(now());
+        source_state.quiet_syncs = if found_new { 0 } else { source_state.quiet_syncs + 1 };
+    }
+
+    /// Whether a source with the given poll_interval (in hours) is due to be
+    /// synced, backing off while it keeps having nothing new.
+    pub fn is_due(&self, source: &str, poll_interval: Option<u64>) -> bool {
+        let Some(hours) = poll_interval else {
+            return true;
+        };
+        let Some(source_state) = self.sources.get(source) else {
+            return true;
+        };
+        let Some(last_synced) = source_state.last_synced else {
+            return true;
+        };
+        let backoff = 2u64.pow(source_state.quiet_syncs.min(MAX_POLL_BACKOFF));
+        now().saturating_sub(last_synced) >= hours * 3600 * backoff
+    }
+
+    /// Estimate how long it will take to process `items` more items from a
+    /// source, if we have any history for it.
+    pub fn estimate(&self, source: &str, items: usize) -> Option<Duration> {
+        let average = self.sources.get(source)?.average_item_seconds?;
+        Some(Duration::from_secs_f64(average * items as f64))
+    }
+}
+
+/// Seconds since the Unix epoch.
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+/// Format an estimate for humans, e.g. "~4m".
+pub fn format_eta(eta: Duration) -> String {
+    let seconds = eta.as_secs();
+    if seconds < 60 {
+        format!("~{}s", seconds)
+    } else if seconds < 3600 {
+        format!("~{}m", (seconds + 30) / 60)
+    } else {
+        format!("~{}h {}m", seconds / 3600, (seconds % 3600) / 60)
+    }
+}
@@ -0,0 +1,112 @@
+//! Reading subtitles (WebVTT or SubRip) into timed pieces of text, for
+//! content which comes with its own captions.
+
+use crate::openai::Segment;
+use regex::Regex;
+use std::sync::LazyLock;
+
+/// Markup inside a cue: styling like <i> and <c.yellow>, voice spans like
+/// <v Anna>, and the per-word timings of karaoke-style captions
+/// (<00:00:01.250>).
+static CUE_TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<[^>]*>").unwrap());
+
+/// How close (in seconds) a cue has to start to the end of the one before
+/// for lines they share to count as the same line carried over, as rolling
+/// captions do, rather than something really said twice.
+const CARRY_OVER_SECONDS: f32 = 1.0;
+
+/// One caption: the lines shown from `start` to `end` (in seconds).
+struct Cue {
+    start: f32,
+    end: f32,
+    lines: Vec<String>,
+}
+
+/// Parse a timestamp like "01:02:03.456", "02:03.456" or SubRip's
+/// "01:02:03,456" into seconds.
+fn parse_timestamp(timestamp: &str) -> Option<f32> {
+    let mut seconds = 0.0;
+    for part in timestamp.trim().replace(',', ".").split(':') {
+        seconds = seconds * 60.0 + part.parse::<f32>().ok()?;
+    }
+    Some(seconds)
+}
+
+/// A cue's text without its markup, with entities decoded and whitespace
+/// tidied.
+fn clean_line(line: &str) -> String {
+    let line = CUE_TAG.replace_all(line, "");
+    html_escape::decode_html_entities(&line).split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Every cue in some subtitles, in order of when they start. WebVTT and
+/// SubRip both come as blocks separated by blank lines, with a
+/// "start --> end" line before the text; anything else (the WEBVTT header,
+/// NOTE and STYLE blocks, SubRip's cue numbers) is skipped.
+fn cues(content: &str) -> Vec<Cue> {
+    let content = content.trim_start_matches('\u{feff}').replace("\r\n", "\n");
+    let mut cues = vec![];
+    for block in content.split("\n\n") {
+        let mut lines = block.lines().skip_while(|line| !line.contains("-->"));
+        let Some(timing) = lines.next() else {
+            continue;
+        };
+        let Some((start, end)) = timing.split_once("-->") else {
+            continue;
+        };
+        // WebVTT can have cue settings (e.g. "align:start") after the end.
+        let end = end.split_whitespace().next().unwrap_or_default();
+        let (Some(start), Some(end)) = (parse_timestamp(start), parse_timestamp(end)) else {
+            continue;
+        };
+        let lines: Vec<String> = lines.map(clean_line).filter(|line| !line.is_empty()).collect();
+        if !lines.is_empty() {
+            cues.push(Cue { start, end, lines });
+        }
+    }
+    cues.sort_by(|a, b| a.start.total_cmp(&b.start));
+    cues
+}
+
+/// Read subtitles from a URL or a local file.
+pub async fn read(location: &str) -> Result<String, String> {
+    if !location.starts_with("http://") && !location.starts_with("https://") {
+        let path = shellexpand::tilde(location).to_string();
+        return std::fs::read_to_string(&path).map_err(|e| e.to_string());
+    }
+    let response = reqwest::get(location).await.and_then(|response| response.error_for_status());
+    response.map_err(|e| e.to_string())?.text().await.map_err(|e| e.to_string())
+}
+
+/// Parse subtitles into segments of text with when they start, ready for
+/// postprocess::timestamp_blocks.
+///
+/// Captions often repeat themselves: rolling captions (as YouTube makes)
+/// carry each line over into the next cue, and some files have the same cue
+/// twice or cues which overlap. Lines carried over from the cue before are
+/// dropped, so each thing said appears once.
+///
+/// Returns an error if there aren't any cues, e.g. because it isn't a
+/// subtitle file at all.
+pub fn parse(content: &str) -> Result<Vec<Segment>, String> {
+    let mut segments = vec![];
+    let mut previous: Option<Cue> = None;
+    for cue in cues(content) {
+        let mut lines = cue.lines.as_slice();
+        if let Some(previous) = previous.as_ref().filter(|previous| cue.start <= previous.end + CARRY_OVER_SECONDS) {
+            let carried = (1..=lines.len().min(previous.lines.len()))
+                .rev()
+                .find(|&count| previous.lines[previous.lines.len() - count..] == lines[..count])
+                .unwrap_or(0);
+            lines = &lines[carried..];
+        }
+        if !lines.is_empty() {
+            segments.push(Segment { start: cue.start, text: lines.join(" ") });
+        }
+        previous = Some(cue);
+    }
+    if segments.is_empty() {
+        return Err("no subtitles found (is it a WebVTT or SubRip file?)".to_string());
+    }
+    Ok(segments)
+}
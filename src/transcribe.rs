@@ -0,0 +1,111 @@
+//! Transcription services, which turn audio into a raw transcript.
+
+use crate::deepgram::Deepgram;
+use crate::local_whisper::LocalWhisper;
+use crate::openai::{ApiError, OpenAI, Segment};
+use serde::Deserialize;
+use std::fmt::Display;
+
+/// How a source's items get their transcripts (its transcript_via).
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TranscriptVia {
+    /// OpenAI's Whisper, then post-processed.
+    Openai,
+    /// LingQ transcribes the uploaded audio itself, without any
+    /// post-processing.
+    Lingq,
+    /// Deepgram (see the [deepgram] section).
+    Deepgram,
+    /// whisper.cpp, on this machine (see the [local_whisper] section).
+    Local,
+    /// The transcripts Easy German publishes for its members.
+    EasyGerman,
+    /// The transcripts Easy German publishes for Super Easy German.
+    SuperEasyGerman,
+    /// The transcript at the source's transcript_url_template.
+    Url,
+}
+
+impl Display for TranscriptVia {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TranscriptVia::Openai => write!(f, "openai"),
+            TranscriptVia::Lingq => write!(f, "lingq"),
+            TranscriptVia::Deepgram => write!(f, "deepgram"),
+            TranscriptVia::Local => write!(f, "local"),
+            TranscriptVia::EasyGerman => write!(f, "easy-german"),
+            TranscriptVia::SuperEasyGerman => write!(f, "super-easy-german"),
+            TranscriptVia::Url => write!(f, "url"),
+        }
+    }
+}
+
+// Only ever used within lqcli, so there are no outside callers who might
+// need the futures to be Send.
+#[allow(async_fn_in_trait)]
+pub trait Transcriber {
+    /// Transcribe audio in the given language. It's an ApiError::Empty if
+    /// there's no transcript, e.g. because the audio is silent.
+    async fn transcribe(&self, audio: Vec<u8>, language: &str) -> Result<String, ApiError>;
+
+    /// Which service and model this is, e.g. for telling cached transcripts
+    /// apart.
+    fn name(&self) -> String;
+}
+
+/// OpenAI's Whisper API, with a particular model (and temperature, if not
+/// the configured one).
+pub struct Whisper<'a> {
+    pub client: &'a OpenAI,
+    pub model: &'a str,
+    pub temperature: Option<f32>,
+}
+
+impl Whisper<'_> {
+    /// Like transcribe, but keep the timestamp of each segment.
+    pub async fn transcribe_segments(&self, audio: Vec<u8>, language: &str) -> Result<Vec<Segment>, ApiError> {
+        let language = Some(language).filter(|language| !language.is_empty());
+        self.client.transcribe_segments(audio, Some(self.model), self.temperature, language).await
+    }
+}
+
+impl Transcriber for Whisper<'_> {
+    async fn transcribe(&self, audio: Vec<u8>, language: &str) -> Result<String, ApiError> {
+        let language = Some(language).filter(|language| !language.is_empty());
+        self.client.transcribe(audio, Some(self.model), self.temperature, language).await
+    }
+
+    fn name(&self) -> String {
+        // A different temperature gives a different transcript.
+        match self.temperature {
+            Some(temperature) => format!("{}@{}", self.model, temperature),
+            None => self.model.to_string(),
+        }
+    }
+}
+
+/// Whichever transcription service a source uses (see its transcript_via).
+pub enum AnyTranscriber<'a> {
+    Whisper(Whisper<'a>),
+    Deepgram(&'a Deepgram),
+    Local(&'a LocalWhisper),
+}
+
+impl Transcriber for AnyTranscriber<'_> {
+    async fn transcribe(&self, audio: Vec<u8>, language: &str) -> Result<String, ApiError> {
+        match self {
+            AnyTranscriber::Whisper(whisper) => whisper.transcribe(audio, language).await,
+            AnyTranscriber::Deepgram(deepgram) => deepgram.transcribe(audio, language).await,
+            AnyTranscriber::Local(local) => local.transcribe(audio, language).await,
+        }
+    }
+
+    fn name(&self) -> String {
+        match self {
+            AnyTranscriber::Whisper(whisper) => whisper.name(),
+            AnyTranscriber::Deepgram(deepgram) => deepgram.name(),
+            AnyTranscriber::Local(local) => local.name(),
+        }
+    }
+}
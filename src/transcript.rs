@@ -0,0 +1,50 @@
+//! Transcripts published on an episode's own web page, as Easy German
+//! publishes them for its members (transcript_via = "easy-german" or
+//! "super-easy-german").
+
+use crate::source::{self, SourceItem};
+use regex::Regex;
+use reqwest::header::HeaderMap;
+use std::sync::LazyLock;
+
+/// The opening tag of the element holding a page's transcript: the first
+/// one whose id or class mentions "transcript".
+static TRANSCRIPT_ELEMENT: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?i)<([a-z][a-z0-9]*)\b[^>]*\b(?:id|class)\s*=\s*["'][^"']*transcript[^"']*["'][^>]*>"#).unwrap()
+});
+
+/// Fetch the transcript from an item's episode page, sending `headers`
+/// (e.g. a members' session cookie) with the request.
+pub async fn fetch(item: &SourceItem, headers: HeaderMap) -> Result<String, String> {
+    let Some(link) = item.link() else {
+        return Err("the item doesn't link to its episode page".to_string());
+    };
+    let response = reqwest::Client::new()
+        .get(&link)
+        .headers(headers)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| e.to_string())?;
+    let page = response.text().await.map_err(|e| e.to_string())?;
+    extract(&page).ok_or_else(|| format!("there's no transcript on {} (is it for members only?)", link))
+}
+
+/// The text of the transcript on an episode page, if it has one.
+pub fn extract(page: &str) -> Option<String> {
+    let opening = TRANSCRIPT_ELEMENT.captures(page)?;
+    let name = opening.get(1)?.as_str();
+    let start = opening.get(0)?.end();
+    // Find the tag closing the element, past any of the same kind inside it.
+    let tags = Regex::new(&format!(r"(?i)<(/?){}\b[^>]*>", regex::escape(name))).ok()?;
+    let mut depth = 1;
+    let mut end = page.len();
+    for tag in tags.captures_iter(&page[start..]) {
+        depth += if tag[1].is_empty() { 1 } else { -1 };
+        if depth == 0 {
+            end = start + tag.get(0)?.start();
+            break;
+        }
+    }
+    Some(source::html_to_text(&page[start..end])).filter(|text| !text.is_empty())
+}
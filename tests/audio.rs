@@ -0,0 +1,37 @@
+//! Splitting audio too big to transcribe in one go.
+
+use lqcli::audio;
+
+#[test]
+fn cuts_are_even_or_at_a_nearby_pause() {
+    assert_eq!(audio::cut_points(300.0, 3, &[]), vec![100.0, 200.0]);
+    // The pause at 97s is close enough to move the first cut to; the one at
+    // 150s is too far from either cut, and 208s is nearer the second than
+    // 190s is.
+    let silences = [40.0, 97.0, 150.0, 190.0, 208.0];
+    assert_eq!(audio::cut_points(300.0, 3, &silences), vec![97.0, 208.0]);
+    // A pause right before the end doesn't leave a scrap for the last piece.
+    assert_eq!(audio::cut_points(300.0, 3, &[295.0]), vec![100.0, 200.0]);
+    assert!(audio::cut_points(300.0, 1, &silences).is_empty());
+}
+
+#[test]
+fn wav_samples_are_scaled() {
+    let mut wav = b"RIFF\0\0\0\0WAVE".to_vec();
+    // A format chunk to skip, then samples which (as when ffmpeg writes to a
+    // pipe) don't match the data chunk's size.
+    wav.extend(b"fmt \x02\0\0\0\x01\0");
+    wav.extend(b"data\xff\xff\xff\xff");
+    for sample in [0i16, 16384, -32768] {
+        wav.extend(sample.to_le_bytes());
+    }
+    assert_eq!(audio::wav_samples(&wav).unwrap(), vec![0.0, 0.5, -1.0]);
+    assert!(audio::wav_samples(b"RIFF\0\0\0\0WAVE").is_err());
+    assert!(audio::wav_samples(b"ID3 not a WAV").is_err());
+}
+
+#[test]
+fn small_audio_is_not_split() {
+    let pieces = audio::split(b"not really audio", 100).unwrap();
+    assert_eq!(pieces, vec![(0.0, b"not really audio".to_vec())]);
+}
@@ -0,0 +1,84 @@
+//! The transcript cache, used from many tasks at once.
+
+use lqcli::cache::TranscriptCache;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+#[test]
+fn keys_depend_on_audio_and_model() {
+    let key = TranscriptCache::key(b"audio", "whisper-1");
+    assert_eq!(key.len(), 64);
+    assert_eq!(key, TranscriptCache::key(b"audio", "whisper-1"));
+    assert_ne!(key, TranscriptCache::key(b"audio", "whisper-2"));
+    assert_ne!(key, TranscriptCache::key(b"other audio", "whisper-1"));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_transcriptions_of_the_same_audio_happen_once() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = Arc::new(TranscriptCache::new(dir.path()));
+    let calls = Arc::new(AtomicUsize::new(0));
+    let key = TranscriptCache::key(b"audio", "whisper-1");
+
+    let tasks: Vec<_> = (0..16)
+        .map(|_| {
+            let (cache, calls, key) = (cache.clone(), calls.clone(), key.clone());
+            tokio::spawn(async move {
+                cache
+                    .get_or_transcribe(&key, || async {
+                        calls.fetch_add(1, Ordering::SeqCst);
+                        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                        Ok::<_, ()>("Hallo Welt".to_string())
+                    })
+                    .await
+            })
+        })
+        .collect();
+    for task in tasks {
+        assert_eq!(task.await.unwrap().as_deref(), Ok("Hallo Welt"));
+    }
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_writes_never_leave_partial_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = Arc::new(TranscriptCache::new(dir.path()));
+    let key = TranscriptCache::key(b"audio", "whisper-1");
+    let transcripts: Vec<String> = (0..8).map(|i| i.to_string().repeat(100_000)).collect();
+
+    let writers: Vec<_> = transcripts
+        .iter()
+        .cloned()
+        .map(|transcript| {
+            let (cache, key) = (cache.clone(), key.clone());
+            tokio::task::spawn_blocking(move || cache.put(&key, &transcript).unwrap())
+        })
+        .collect();
+    let readers: Vec<_> = (0..8)
+        .map(|_| {
+            let (cache, key) = (cache.clone(), key.clone());
+            tokio::task::spawn_blocking(move || cache.get(&key))
+        })
+        .collect();
+    for writer in writers {
+        writer.await.unwrap();
+    }
+    for reader in readers {
+        if let Some(transcript) = reader.await.unwrap() {
+            assert!(transcripts.contains(&transcript));
+        }
+    }
+    assert!(transcripts.contains(&cache.get(&key).unwrap()));
+    // Only the entry itself is left, no temporary files.
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+}
+
+#[tokio::test]
+async fn empty_transcripts_are_not_cached() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = TranscriptCache::new(dir.path());
+    let key = TranscriptCache::key(b"silence", "whisper-1");
+    assert_eq!(cache.get_or_transcribe(&key, || async { Err(()) }).await, Err(()));
+    assert_eq!(cache.get(&key), None);
+}
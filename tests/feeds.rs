@@ -0,0 +1,416 @@
+//! Feed parsing, against the fixture feeds in tests/fixtures, served over a
+//! throwaway local HTTP server.
+
+use lqcli::source::{self, Feed, ItemOrder, LocalItem, SkipReason, Source, SourceItem};
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+
+const RSS: &str = include_str!("fixtures/rss.xml");
+const ATOM: &str = include_str!("fixtures/atom.xml");
+const MALFORMED: &str = include_str!("fixtures/malformed.xml");
+const BAD_ENTRY: &str = include_str!("fixtures/bad_entry.xml");
+const SKIPPABLE: &str = include_str!("fixtures/skippable.xml");
+const UNREACHABLE: &str = include_str!("fixtures/unreachable.xml");
+const NEW_ITEMS: &str = include_str!("fixtures/new_items.xml");
+
+/// Serve `body` to every request on a local port, returning the URL of the
+/// feed.
+fn serve(body: &'static str) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/feeds/feed.xml", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut request = [0; 4096];
+            let _ = stream.read(&mut request);
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/xml\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            let _ = stream.write_all(response.as_bytes());
+        }
+    });
+    url
+}
+
+/// Like serve, but permanently redirect requests for /feeds/old.xml to
+/// /feeds/new.xml, returning the old URL.
+fn serve_moved(body: &'static str) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/feeds/old.xml", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut request = [0; 4096];
+            let read = stream.read(&mut request).unwrap_or(0);
+            let response = if String::from_utf8_lossy(&request[..read]).starts_with("GET /feeds/old.xml") {
+                "HTTP/1.1 301 Moved Permanently\r\nLocation: /feeds/new.xml\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+            } else {
+                format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/xml\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+            };
+            let _ = stream.write_all(response.as_bytes());
+        }
+    });
+    url
+}
+
+/// Serve `feed` at /feeds/feed.xml, a LingQ API under /api whose course 1
+/// already has lessons called "Folge 1" and "Folge 2", and a few bytes of "audio" at any
+/// other path. Returns the URL of the feed and the paths of every audio
+/// request as they come in.
+fn serve_with_audio(feed: &'static str) -> (String, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/feeds/feed.xml", listener.local_addr().unwrap());
+    let requested = Arc::new(Mutex::new(vec![]));
+    let audio_requests = requested.clone();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut request = [0; 4096];
+            let read = stream.read(&mut request).unwrap_or(0);
+            let request = String::from_utf8_lossy(&request[..read]);
+            let path = request.split(' ').nth(1).unwrap_or_default().to_string();
+            let body = if path == "/feeds/feed.xml" {
+                feed
+            } else if path == "/api/v2/contexts/" {
+                "[]"
+            } else if path.starts_with("/api/v2/de/collections/1/") {
+                r#"{"results": [
+                    {"id": 1, "title": "Folge 1", "url": "https://www.lingq.com/lesson/1"},
+                    {"id": 2, "title": "Folge 2", "url": "https://www.lingq.com/lesson/2"}
+                ], "next": null}"#
+            } else {
+                audio_requests.lock().unwrap().push(path);
+                "audio"
+            };
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            let _ = stream.write_all(response.as_bytes());
+        }
+    });
+    (url, requested)
+}
+
+/// A source for the given feed, with everything else left at its default.
+fn source(url: &str, extra: &str) -> Source {
+    toml::from_str(&format!(
+        "name = \"test\"\nurl = \"{}\"\ncourse_id = 1\ntags = []\n{}",
+        url, extra
+    ))
+    .unwrap()
+}
+
+async fn items(body: &'static str) -> (String, Vec<SourceItem>) {
+    let url = serve(body);
+    let feed = Feed::from_source(&source(&url, "")).await.unwrap();
+    (url, feed.items(10))
+}
+
+#[tokio::test]
+async fn rss_items_in_feed_order() {
+    let (_, items) = items(RSS).await;
+    let titles: Vec<Option<String>> = items.iter().map(|item| item.title()).collect();
+    assert_eq!(
+        titles,
+        vec![
+            Some("Folge 3: Tom & Jerry".to_string()),
+            Some("Folge 2".to_string()),
+            Some("Nur Text".to_string()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn rss_items_limited_to_count() {
+    let url = serve(RSS);
+    let feed = Feed::from_source(&source(&url, "")).await.unwrap();
+    assert_eq!(feed.items(2).len(), 2);
+}
+
+#[tokio::test]
+async fn rss_audio_links() {
+    let (url, items) = items(RSS).await;
+    assert_eq!(
+        items[0].get_audio_link().as_deref(),
+        Some("https://cdn.example.com/folge-3.mp3")
+    );
+    // Relative enclosures are resolved against the feed's URL.
+    let base = url.trim_end_matches("feed.xml");
+    assert_eq!(
+        items[1].get_audio_link(),
+        Some(format!("{}audio/folge-2.mp3", base))
+    );
+    assert_eq!(items[2].get_audio_link(), None);
+    // Only subtitle formats lqcli can read, resolved like enclosures.
+    assert_eq!(items[0].subtitles_link(), Some(format!("{}subs/folge-3.vtt", base)));
+    assert_eq!(items[1].subtitles_link(), None);
+}
+
+#[tokio::test]
+async fn rss_metadata() {
+    let url = serve(RSS);
+    let feed = Feed::from_source(&source(&url, "")).await.unwrap();
+    assert_eq!(feed.language().as_deref(), Some("de"));
+    let items = feed.items(10);
+    assert_eq!(items[0].guid().as_deref(), Some("folge-3"));
+    assert_eq!(items[0].categories(), vec!["Interviews".to_string()]);
+    assert_eq!(items[0].description().as_deref(), Some("Shownotes für Folge 3"));
+    assert_eq!(items[2].text().as_deref(), Some("Diesmal gibt es keine Folge."));
+}
+
+#[test]
+fn titles_are_decoded() {
+    assert_eq!(source::decode_title("Tom &amp; Jerry"), "Tom & Jerry");
+    // Numeric character references, in decimal and in hex.
+    assert_eq!(source::decode_title("Rock &#8217;n&#8217; Roll"), "Rock \u{2019}n\u{2019} Roll");
+    assert_eq!(source::decode_title("Rock &#x2019;n&#X2019; Roll"), "Rock \u{2019}n\u{2019} Roll");
+    assert_eq!(source::decode_title("Folge 2"), "Folge 2");
+}
+
+#[tokio::test]
+async fn rss_skip_reasons() {
+    let (_, items) = items(RSS).await;
+    let existing = vec!["Folge 3: Tom &amp; Jerry".to_string()];
+    assert!(matches!(
+        items[0].skip_reason(&existing, &[], true),
+        Some(SkipReason::TitleMatch(_))
+    ));
+    assert!(items[1].skip_reason(&existing, &[], true).is_none());
+    assert!(matches!(
+        items[2].skip_reason(&existing, &[], true),
+        Some(SkipReason::NoAudio)
+    ));
+    assert!(items[2].skip_reason(&existing, &[], false).is_none());
+    assert!(matches!(
+        items[1].skip_reason(&[], &["Interviews".to_string()], true),
+        Some(SkipReason::NotInCategory)
+    ));
+}
+
+#[test]
+fn local_files_are_matched_by_filename() {
+    let local = |path: &str| SourceItem::Local(LocalItem { path: path.into(), title: "Lecture".to_string() });
+    let (first, second) = (local("course/lecture-01.mp3"), local("course/lecture-02.mp3"));
+    // Files tagged with the same title aren't taken for one another.
+    let existing = vec!["Lecture".to_string()];
+    assert!(first.skip_reason(&existing, &[], true).is_none());
+    assert!(second.skip_reason(&existing, &[], true).is_none());
+    let existing = vec!["lecture-01".to_string()];
+    assert!(matches!(
+        first.skip_reason(&existing, &[], true),
+        Some(SkipReason::TitleMatch(title)) if title == "lecture-01"
+    ));
+    assert!(second.skip_reason(&existing, &[], true).is_none());
+    assert_eq!(first.guid().as_deref(), Some("lecture-01.mp3"));
+}
+
+/// `lqcli <args> sources sync`, at home in `dir`, with a configuration
+/// whose LingQ API and only source, with `settings` added to it, are served
+/// from `feed_url` (see serve_with_audio). `globals` go at the top of the
+/// configuration.
+fn sync_command(dir: &Path, feed_url: &str, globals: &str, settings: &str, args: &[&str]) -> Command {
+    let config_path = dir.join("lqcli.toml");
+    let config = format!(
+        "{}\n[lingq]\napi_key = \"lingq\"\nrequest_delay = 0\napi_base = \"{}\"\n\n\
+         [openai]\napi_key = \"openai\"\n\n\
+         [[sources]]\nname = \"test\"\nurl = \"{}\"\ncourse_id = 1\ntags = []\n{}\n",
+        globals,
+        feed_url.replace("/feeds/feed.xml", "/api"),
+        feed_url,
+        settings
+    );
+    std::fs::write(&config_path, config).unwrap();
+    let mut command = Command::new(env!("CARGO_BIN_EXE_lqcli"));
+    command.arg("--config-file").arg(&config_path).args(args).args(["sources", "sync"]).env("HOME", dir);
+    command
+}
+
+/// Run `lqcli <args> sources sync` (see sync_command).
+fn sync(feed_url: &str, settings: &str, args: &[&str]) -> std::process::Output {
+    let dir = tempfile::tempdir().unwrap();
+    sync_command(dir.path(), feed_url, "", settings, args).output().unwrap()
+}
+
+#[test]
+fn existing_items_are_never_downloaded() {
+    let (url, requested) = serve_with_audio(SKIPPABLE);
+    let output = sync(&url, "title_exclude = \"^Trailer\"", &[]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}{}", stdout, String::from_utf8_lossy(&output.stderr));
+    assert!(stdout.contains("Skipping existing lesson: Folge 1"), "{}", stdout);
+    assert!(stdout.contains("Skipping existing lesson: Folge 2"), "{}", stdout);
+    assert!(requested.lock().unwrap().is_empty());
+}
+
+#[test]
+fn strict_syncs_fail_when_an_item_does() {
+    // Folge 3's audio is somewhere nothing is listening.
+    let (url, _) = serve_with_audio(UNREACHABLE);
+    let output = sync(&url, "download_method = \"direct\"", &[]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Error downloading Folge 3"));
+
+    let output = sync(&url, "download_method = \"direct\"", &["--strict"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("1 item(s) couldn't be imported"));
+}
+
+#[test]
+fn items_download_while_earlier_ones_are_transcribed() {
+    let (url, _) = serve_with_audio(NEW_ITEMS);
+    let dir = tempfile::tempdir().unwrap();
+    let (bin, downloads, log) = (dir.path().join("bin"), dir.path().join("downloads"), dir.path().join("whisper.log"));
+    std::fs::create_dir(&bin).unwrap();
+    // ffmpeg passes the audio on untouched, and whisper.cpp waits (for up
+    // to 5s) for both items' audio to be downloaded before it finishes.
+    let scripts = [
+        ("ffmpeg", "#!/bin/sh\nfor arg; do output=$arg; done\ncp \"$5\" \"$output\"\n".to_string()),
+        (
+            "whisper-cli",
+            format!(
+                "#!/bin/sh\nfor i in $(seq 50); do\n  if [ $(ls {0} | wc -l) -ge 2 ]; then echo overlapped >> {1}; echo Hallo; exit; fi\n  sleep 0.1\ndone\necho waited >> {1}\necho Hallo\n",
+                downloads.display(),
+                log.display()
+            ),
+        ),
+    ];
+    for (name, script) in scripts {
+        std::fs::write(bin.join(name), script).unwrap();
+        std::fs::set_permissions(bin.join(name), std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    let globals = format!("download_dir = \"{}\"\n", downloads.display());
+    let settings = "download_method = \"direct\"\ntranscript_via = \"local\"\n\n[local_whisper]\nmodel = \"ggml-base.bin\"\n";
+    let path = format!("{}:{}", bin.display(), std::env::var("PATH").unwrap_or_default());
+    let output = sync_command(dir.path(), &url, &globals, settings, &[]).env("PATH", path).output().unwrap();
+    let whisper_log = std::fs::read_to_string(&log).unwrap_or_default();
+    assert_eq!(
+        whisper_log.lines().next(),
+        Some("overlapped"),
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[tokio::test]
+async fn atom_items() {
+    let url = serve(ATOM);
+    let feed = Feed::from_source(&source(&url, "")).await.unwrap();
+    assert_eq!(feed.language().as_deref(), Some("fr"));
+    let items = feed.items(10);
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[0].title().as_deref(), Some("Épisode 2"));
+    assert_eq!(items[0].guid().as_deref(), Some("urn:example:episode-2"));
+    // Relative links are resolved against the feed's xml:base.
+    assert_eq!(
+        items[0].get_audio_link().as_deref(),
+        Some("https://example.org/podcast/episodes/2.mp3")
+    );
+    assert_eq!(
+        items[1].get_audio_link().as_deref(),
+        Some("https://cdn.example.org/1.mp3")
+    );
+    assert_eq!(items[0].description().as_deref(), Some("Le deuxième épisode"));
+}
+
+#[tokio::test]
+async fn items_sorted_oldest_first() {
+    let titles = |items: &[SourceItem]| items.iter().map(|item| item.title().unwrap()).collect::<Vec<_>>();
+    // Atom entries always have a date to go by.
+    let (_, mut episodes) = items(ATOM).await;
+    source::sort_items(&mut episodes, ItemOrder::Oldest);
+    assert_eq!(titles(&episodes), vec!["Épisode 1", "Épisode 2"]);
+    source::sort_items(&mut episodes, ItemOrder::Newest);
+    assert_eq!(titles(&episodes), vec!["Épisode 2", "Épisode 1"]);
+    // Without dates, the feed is taken to list its newest items first.
+    let (_, mut folgen) = items(RSS).await;
+    source::sort_items(&mut folgen, ItemOrder::Oldest);
+    assert_eq!(titles(&folgen), vec!["Nur Text", "Folge 2", "Folge 3: Tom & Jerry"]);
+}
+
+#[tokio::test]
+async fn malformed_feed_needs_lenient_parsing() {
+    let url = serve(MALFORMED);
+    assert!(Feed::from_source(&source(&url, "")).await.is_err());
+
+    let feed = Feed::from_source(&source(&url, "lenient_parsing = true")).await.unwrap();
+    let items = feed.items(10);
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0].title().as_deref(), Some("Fragen\u{a0}und Antworten"));
+    assert_eq!(
+        items[0].get_audio_link().as_deref(),
+        Some("https://example.net/qa.mp3?a=1&b=2")
+    );
+}
+
+#[tokio::test]
+async fn unparseable_feed_is_an_error() {
+    let url = serve("this is not a feed");
+    assert!(Feed::from_source(&source(&url, "lenient_parsing = true")).await.is_err());
+}
+
+#[tokio::test]
+async fn permanently_moved_feeds_are_noted() {
+    let old = serve_moved(RSS);
+    let feed = Feed::from_source(&source(&old, "")).await.unwrap();
+    assert_eq!(feed.items(10).len(), 3);
+    let new = old.replace("old.xml", "new.xml");
+    assert!(source::moved_feeds().contains(&(old, new)));
+}
+
+#[tokio::test]
+async fn bad_items_are_skipped_and_the_rest_kept() {
+    let (_, items) = items(BAD_ENTRY).await;
+    let titles: Vec<Option<String>> = items.iter().map(|item| item.title()).collect();
+    assert_eq!(titles, vec![Some("Folge 3".to_string()), Some("Folge 1".to_string())]);
+    assert_eq!(items[1].get_audio_link().as_deref(), Some("https://cdn.example.com/1.mp3"));
+}
+
+#[test]
+fn youtube_channels_list_each_video_by_its_own_url() {
+    let output = concat!(
+        r#"{"id": "abc123", "url": "https://www.youtube.com/watch?v=abc123", "title": "Folge 2"}"#,
+        "\n",
+        r#"{"id": "def456", "url": "def456", "title": "Folge 1"}"#,
+        "\n",
+    );
+    let items = source::flat_playlist_items(output).unwrap();
+    let found: Vec<(Option<String>, Option<String>)> =
+        items.iter().map(|item| (item.title(), item.get_audio_link())).collect();
+    assert_eq!(
+        found,
+        vec![
+            (Some("Folge 2".to_string()), Some("https://www.youtube.com/watch?v=abc123".to_string())),
+            (Some("Folge 1".to_string()), Some("https://www.youtube.com/watch?v=def456".to_string())),
+        ]
+    );
+    assert!(source::flat_playlist_items("not json").is_err());
+
+    let channel = source("https://www.youtube.com/@name/videos", "content_type = \"youtube-channel\"\n");
+    assert_eq!(channel.content_type.to_string(), "YouTube Channel");
+}
+
+#[tokio::test]
+async fn transcripts_are_fetched_from_their_own_urls() {
+    let item = SourceItem::from_url_and_title("https://example.com/audio/7.mp3", "Folge 7: Tee & Kekse");
+    assert_eq!(
+        item.transcript_url("https://example.com/{source}/{title}.html?audio={audio}", "Mein Podcast"),
+        "https://example.com/Mein%20Podcast/Folge%207%3A%20Tee%20%26%20Kekse.html?audio=https://example.com/audio/7.mp3"
+    );
+
+    let url = serve("<html><body><p>Hallo &amp; willkommen!</p><p>Heute gibt es Tee.</p></body></html>");
+    let transcript = source::fetch_transcript(&url).await.unwrap();
+    assert_eq!(transcript, "Hallo & willkommen!\nHeute gibt es Tee.");
+}
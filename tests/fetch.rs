@@ -0,0 +1,90 @@
+//! Link handling before downloads, and keeping downloads.
+
+use lqcli::fetch::{self, DownloadMethod, DownloadOptions, strip_tracking_prefixes};
+use lqcli::source::{LocalItem, SourceItem};
+use std::io::{Read, Write};
+use std::net::TcpListener;
+
+#[test]
+fn tracking_prefixes_are_stripped() {
+    assert_eq!(
+        strip_tracking_prefixes("https://chtbl.com/track/ABC123/traffic.example.com/folge-1.mp3"),
+        "https://traffic.example.com/folge-1.mp3"
+    );
+    // Stacked prefixes, one of which keeps the wrapped URL's scheme.
+    assert_eq!(
+        strip_tracking_prefixes("https://pdst.fm/e/op3.dev/e/http://cdn.example.com/a.mp3?x=1"),
+        "http://cdn.example.com/a.mp3?x=1"
+    );
+    assert_eq!(
+        strip_tracking_prefixes("https://dts.podtrac.com/redirect.mp3/cdn.example.com/b.mp3"),
+        "https://cdn.example.com/b.mp3"
+    );
+}
+
+#[test]
+fn other_links_are_left_alone() {
+    let url = "https://cdn.example.com/track/ABC123/folge-1.mp3";
+    assert_eq!(strip_tracking_prefixes(url), url);
+}
+
+#[test]
+fn existing_downloads_are_found_by_template() {
+    let dir = tempfile::tempdir().unwrap();
+    let download_dir = dir.path().to_string_lossy().into_owned();
+    let item = SourceItem::Local(LocalItem { path: "elsewhere/1.mp3".into(), title: "Folge 1: A/B".to_string() });
+    let template = "{source} - {title}.{ext}";
+    assert_eq!(fetch::existing_download(&download_dir, template, "Podcast", &item), None);
+
+    let saved = fetch::save_download(&download_dir, template, "Podcast", &item, b"audio").unwrap();
+    assert_eq!(saved, dir.path().join("Podcast - Folge 1_ A_B.mp3"));
+    assert_eq!(fetch::existing_download(&download_dir, template, "Podcast", &item), Some(saved));
+    assert_eq!(fetch::existing_download(&download_dir, template, "Other", &item), None);
+}
+
+#[tokio::test]
+async fn direct_downloads_are_plain_http_requests() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/folge-1.mp3", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut request = [0; 4096];
+            let _ = stream.read(&mut request);
+            let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Type: audio/mpeg\r\nContent-Length: 5\r\nConnection: close\r\n\r\naudio");
+        }
+    });
+    let method: DownloadMethod = "direct".parse().unwrap();
+    let item = SourceItem::from_url_and_title(&url, "Folge 1");
+    let audio = fetch::fetch(&item, method, DownloadOptions::default()).await.unwrap();
+    assert_eq!(audio, b"audio");
+}
+
+#[tokio::test]
+async fn direct_downloads_keep_to_the_rate_limit() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/folge-1.mp3", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut request = [0; 4096];
+            let _ = stream.read(&mut request);
+            let body = vec![0u8; 32 * 1024];
+            let head = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: audio/mpeg\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            );
+            let _ = stream.write_all(head.as_bytes());
+            let _ = stream.write_all(&body);
+        }
+    });
+    assert_eq!(fetch::parse_rate("16K"), Some(16384.0));
+    let method: DownloadMethod = "direct".parse().unwrap();
+    let item = SourceItem::from_url_and_title(&url, "Folge 1");
+    let options = DownloadOptions { max_rate: Some("16K"), ..Default::default() };
+    let started = std::time::Instant::now();
+    let audio = fetch::fetch(&item, method, options).await.unwrap();
+    assert_eq!(audio.len(), 32 * 1024);
+    // 32K at 16K a second takes two seconds, give or take a timer tick.
+    assert!(started.elapsed().as_secs_f64() >= 1.9, "took {:?}", started.elapsed());
+}
@@ -0,0 +1,113 @@
+//! Talking to the LingQ API, here a throwaway local server standing in for
+//! it.
+
+use lqcli::config::LingqConfig;
+use lqcli::lingq::{sort_by_creation, LingqClient, LingqLesson};
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const FIRST_PAGE: &str = include_str!("fixtures/lingq_lessons_1.json");
+const SECOND_PAGE: &str = include_str!("fixtures/lingq_lessons_2.json");
+
+/// Answer each request on a local port with `reply(api_base, path)`, or a
+/// 404 if that's None. Returns the API base and the paths requested so far.
+fn serve(reply: impl Fn(&str, &str) -> Option<String> + Send + 'static) -> (String, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let api_base = format!("http://{}/api", listener.local_addr().unwrap());
+    let requested = Arc::new(Mutex::new(vec![]));
+    let (base, paths) = (api_base.clone(), requested.clone());
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut request = [0; 4096];
+            let read = stream.read(&mut request).unwrap_or(0);
+            let request = String::from_utf8_lossy(&request[..read]);
+            let path = request.split(' ').nth(1).unwrap_or_default().to_string();
+            let response = match reply(&base, &path) {
+                Some(body) => format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                ),
+                None => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+            };
+            paths.lock().unwrap().push(path);
+            let _ = stream.write_all(response.as_bytes());
+        }
+    });
+    (api_base, requested)
+}
+
+fn client(api_base: &str) -> LingqClient {
+    let config: LingqConfig =
+        toml::from_str(&format!("api_key = \"lingq\"\nrequest_delay = 0\napi_base = \"{}\"\n", api_base)).unwrap();
+    LingqClient::new(&config)
+}
+
+#[tokio::test]
+async fn course_lessons_come_a_page_at_a_time() {
+    let (api_base, requested) = serve(|api_base, path| match path {
+        "/api/v2/de/collections/123/" => Some(FIRST_PAGE.replace("https://www.lingq.com/api", api_base)),
+        "/api/v2/de/collections/123/?page=2" => Some(SECOND_PAGE.replace("https://www.lingq.com/api", api_base)),
+        _ => None,
+    });
+    let titles = client(&api_base).get_lesson_titles("de", 123).await.unwrap();
+    assert_eq!(titles, ["Folge 1: Ankommen", "Folge 2: Einkaufen", "Folge 3: Tee &amp; Kekse"]);
+    assert_eq!(*requested.lock().unwrap(), ["/api/v2/de/collections/123/", "/api/v2/de/collections/123/?page=2"]);
+}
+
+#[tokio::test]
+async fn pages_linking_to_themselves_end_the_lessons() {
+    let (api_base, requested) = serve(|api_base, path| match path {
+        "/api/v2/de/collections/123/" => Some(FIRST_PAGE.replace("https://www.lingq.com/api", api_base)),
+        // The last page claims to be followed by itself.
+        "/api/v2/de/collections/123/?page=2" => Some(SECOND_PAGE.replace(
+            "\"next\": null",
+            &format!("\"next\": \"{}/v2/de/collections/123/?page=2\"", api_base),
+        )),
+        _ => None,
+    });
+    let lessons = client(&api_base).get_lessons("de", 123).await.unwrap();
+    assert_eq!(lessons.iter().map(|lesson| lesson.id).collect::<Vec<_>>(), [Some(901), Some(902), Some(903)]);
+    assert_eq!(requested.lock().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn requests_are_spaced_by_request_delay() {
+    let (api_base, requested) =
+        serve(|_, path| (path == "/api/v2/de/collections/123/").then(|| SECOND_PAGE.to_string()));
+    let client = client(&api_base).with_request_delay(1);
+    let started = Instant::now();
+    client.get_lesson_titles("de", 123).await.unwrap();
+    // The first request of a run goes out straight away, and the next waits
+    // its turn, even from a copy of the client with a source's own delay.
+    assert!(started.elapsed() < Duration::from_secs(1));
+    client.with_request_delay(1).get_lesson_titles("de", 123).await.unwrap();
+    assert!(started.elapsed() >= Duration::from_secs(1));
+    assert_eq!(requested.lock().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn lessons_without_a_course_need_no_request() {
+    let (api_base, requested) = serve(|_, _| None);
+    assert!(client(&api_base).get_lessons("de", lqcli::lingq::NO_COURSE).await.unwrap().is_empty());
+    assert!(requested.lock().unwrap().is_empty());
+}
+
+#[test]
+fn lessons_sort_by_when_they_were_created() {
+    let mut lessons: Vec<LingqLesson> = serde_json::from_str(
+        r#"[
+            {"id": 7, "title": "Neu", "url": "", "pubDate": "2024-03-02T09:00:00+01:00"},
+            {"id": 9, "title": "Umsortiert", "url": "", "pubDate": "2024-01-15"},
+            {"id": 3, "title": "Ohne Datum", "url": ""},
+            {"id": 8, "title": "Am selben Tag", "url": "", "creationDate": "2024-03-02T09:00:00+01:00"}
+        ]"#,
+    )
+    .unwrap();
+    sort_by_creation(&mut lessons);
+    let titles: Vec<&str> = lessons.iter().map(|lesson| lesson.title.as_str()).collect();
+    assert_eq!(titles, ["Ohne Datum", "Umsortiert", "Neu", "Am selben Tag"]);
+}
@@ -0,0 +1,70 @@
+//! Transcribing with whisper.cpp, here a script standing in for it.
+//! Built with `whisper-rs` there's no program to stand in for.
+#![cfg(not(feature = "whisper-rs"))]
+
+use lqcli::config::LocalWhisperConfig;
+use lqcli::local_whisper::LocalWhisper;
+use lqcli::transcribe::Transcriber;
+use std::os::unix::fs::PermissionsExt;
+
+/// A stand-in for whisper.cpp which prints its arguments, one per line,
+/// the way whisper.cpp prints a transcript's segments.
+fn fake_whisper(dir: &std::path::Path) -> String {
+    let path = dir.join("whisper-cli");
+    std::fs::write(&path, "#!/bin/sh\nfor arg in \"$@\"; do echo \" $arg\"; done\n").unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+#[tokio::test]
+async fn whisper_cpp_is_given_the_model_and_language() {
+    let dir = tempfile::tempdir().unwrap();
+    let config: LocalWhisperConfig = toml::from_str(&format!(
+        "model = \"models/ggml-large-v3.bin\"\ncommand = \"{}\"\nthreads = 4\n",
+        fake_whisper(dir.path())
+    ))
+    .unwrap();
+    let local = LocalWhisper::new(config);
+    assert_eq!(local.name(), "whisper.cpp/ggml-large-v3");
+
+    let transcript = local.transcribe_file(std::path::Path::new("folge-1.wav"), "de-AT").await.unwrap();
+    assert_eq!(
+        transcript,
+        "--model models/ggml-large-v3.bin --language de --no-timestamps --no-prints --file folge-1.wav --threads 4"
+    );
+    // Languages whisper.cpp doesn't have a code for are left to it to detect.
+    let transcript = local.transcribe_file(std::path::Path::new("folge-1.wav"), "yue").await.unwrap();
+    assert!(transcript.contains("--language auto"));
+}
+
+#[tokio::test]
+async fn transcripts_which_are_not_utf8_are_errors() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("whisper-cli");
+    // "Grüße" with the ü cut in half, as a segment boundary can leave it.
+    std::fs::write(&path, "#!/bin/sh\nprintf ' Gr\\303\\n\\274\\303\\237e\\n'\n").unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let config: LocalWhisperConfig =
+        toml::from_str(&format!("model = \"ggml-base.bin\"\ncommand = \"{}\"\n", path.display())).unwrap();
+    let error = LocalWhisper::new(config).transcribe_file(std::path::Path::new("folge-1.wav"), "de").await.unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[tokio::test]
+async fn streamed_audio_is_piped_through_ffmpeg_into_whisper_cpp() {
+    let dir = tempfile::tempdir().unwrap();
+    // ffmpeg passes the audio on untouched, and whisper.cpp "transcribes"
+    // it by printing what it reads.
+    for (name, script) in [("ffmpeg", "#!/bin/sh\nexec cat\n"), ("whisper-cli", "#!/bin/sh\nexec cat\n")] {
+        let path = dir.path().join(name);
+        std::fs::write(&path, script).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    std::env::set_var("PATH", format!("{}:{}", dir.path().display(), std::env::var("PATH").unwrap_or_default()));
+    let config: LocalWhisperConfig = toml::from_str("model = \"ggml-base.bin\"\n").unwrap();
+    let chunks = ["Hallo\n", "und willkommen\n"].map(|chunk| Ok(chunk.as_bytes().to_vec()));
+    let (audio, transcript) =
+        LocalWhisper::new(config).transcribe_stream(futures::stream::iter(chunks), "de").await.unwrap();
+    assert_eq!(audio, b"Hallo\nund willkommen\n");
+    assert_eq!(transcript, "Hallo und willkommen");
+}
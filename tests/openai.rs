@@ -0,0 +1,96 @@
+//! Talking to an OpenAI-compatible API, here a local stand-in for it.
+
+use lqcli::config::OpenaiConfig;
+use lqcli::openai::{ApiError, OpenAI};
+use lqcli::transcribe::{Transcriber, Whisper};
+use std::io::{Read, Write};
+use std::net::TcpListener;
+
+/// Serve `body` as the JSON reply to every request, returning the API base
+/// to point a client at.
+fn serve(body: &'static str) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let api_base = format!("http://{}/v1", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            // Read the whole request, so the client isn't cut off mid-upload.
+            let mut request = vec![];
+            let mut buffer = [0; 65536];
+            while let Ok(read) = stream.read(&mut buffer) {
+                request.extend_from_slice(&buffer[..read]);
+                let text = String::from_utf8_lossy(&request);
+                let Some(end) = text.find("\r\n\r\n") else { continue };
+                let length = text[..end]
+                    .lines()
+                    .find_map(|line| line.to_ascii_lowercase().strip_prefix("content-length:").map(|n| n.trim().to_string()))
+                    .and_then(|n| n.parse::<usize>().ok())
+                    .unwrap_or(0);
+                if read == 0 || request.len() >= end + 4 + length {
+                    break;
+                }
+            }
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            let _ = stream.write_all(response.as_bytes());
+        }
+    });
+    api_base
+}
+
+fn client(api_base: &str) -> OpenAI {
+    let config: OpenaiConfig = toml::from_str(&format!("api_key = \"test\"\napi_base = \"{}\"\n", api_base)).unwrap();
+    OpenAI::new(config)
+}
+
+const CHAT_REPLY: &str = r#"{
+    "id": "chatcmpl-1", "object": "chat.completion", "created": 0, "model": "gpt-4o-mini",
+    "choices": [{"index": 0, "message": {"role": "assistant", "content": "Hallo!"}, "finish_reason": "stop"}],
+    "usage": {"prompt_tokens": 100, "completion_tokens": 20, "total_tokens": 120}
+}"#;
+
+#[tokio::test]
+async fn scoped_clients_keep_their_own_tally() {
+    let openai = client(&serve(CHAT_REPLY));
+    let (first, second) = (openai.scoped(), openai.scoped());
+    assert_eq!(first.postprocess("hallo", "Clean this up.").await.unwrap(), "Hallo!");
+    assert_eq!(first.postprocess("hallo", "Clean this up.").await.unwrap(), "Hallo!");
+    assert_eq!(second.postprocess("hallo", "Clean this up.").await.unwrap(), "Hallo!");
+
+    assert_eq!(first.usage().prompt_tokens, 200);
+    assert_eq!(second.usage().prompt_tokens, 100);
+    // Everything still counts towards the client they were scoped from.
+    assert_eq!(openai.usage().prompt_tokens, 300);
+    assert_eq!(openai.usage().completion_tokens, 60);
+    assert!(second.cost().unwrap() < first.cost().unwrap());
+}
+
+/// A transcription with nothing but whitespace in it, as Whisper gives for
+/// silent audio.
+const SILENT_TRANSCRIPTION_REPLY: &str = r#"{"language": "german", "duration": 12.5, "text": "  \n ", "segments": []}"#;
+
+#[tokio::test]
+async fn whitespace_transcripts_are_empty() {
+    let openai = client(&serve(SILENT_TRANSCRIPTION_REPLY));
+    let transcript = openai.transcribe(b"audio".to_vec(), None, None, Some("de")).await;
+    assert!(matches!(transcript, Err(ApiError::Empty)), "{:?}", transcript.map_err(|e| e.to_string()));
+    // Which is no transcript at all to a sync, so the item is skipped.
+    let whisper = Whisper { client: &openai, model: "whisper-1", temperature: None };
+    assert!(matches!(whisper.transcribe(b"audio".to_vec(), "de").await, Err(ApiError::Empty)));
+    // The audio was still paid for.
+    assert!(openai.cost().unwrap() > 0.0);
+}
+
+/// A transcription which, for whatever reason, came back without segments.
+const UNSEGMENTED_TRANSCRIPTION_REPLY: &str = r#"{"language": "german", "duration": 12.5, "text": "Hallo und willkommen."}"#;
+
+#[tokio::test]
+async fn transcripts_without_segments_keep_their_text() {
+    let openai = client(&serve(UNSEGMENTED_TRANSCRIPTION_REPLY));
+    let segments = openai.transcribe_segments(b"audio".to_vec(), None, None, Some("de")).await.unwrap();
+    assert_eq!(segments.len(), 1);
+    assert_eq!((segments[0].start, segments[0].text.as_str()), (0.0, "Hallo und willkommen."));
+}
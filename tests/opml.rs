@@ -0,0 +1,47 @@
+//! Exporting sources as OPML.
+
+use lqcli::config::LqcliConfig;
+
+const CONFIG: &str = r#"
+[lingq]
+api_key = "lingq"
+
+[openai]
+api_key = "openai"
+
+[[sources]]
+name = "Nachrichten & mehr"
+url = "https://example.com/feed.xml?a=1&b=2"
+course_id = 1
+language = "de"
+tags = []
+
+[[sources]]
+name = "podcast"
+url = "https://example.com/podcast.xml"
+course_id = 2
+tags = []
+
+[[sources]]
+name = "audiobook"
+url = "~/Hörbuch"
+content_type = "local-directory"
+course_id = 3
+language = "de"
+tags = []
+"#;
+
+#[test]
+fn feeds_are_exported_as_outlines() {
+    let config: LqcliConfig = toml::from_str(CONFIG).unwrap();
+    let opml = lqcli::opml::export(&config.filtered_sources(&[], None));
+    assert!(opml.contains(
+        r#"<outline type="rss" text="Nachrichten &amp; mehr" title="Nachrichten &amp; mehr" xmlUrl="https://example.com/feed.xml?a=1&amp;b=2" lqcli:language="de"/>"#
+    ));
+    assert!(opml.contains(
+        r#"<outline type="rss" text="podcast" title="podcast" xmlUrl="https://example.com/podcast.xml"/>"#
+    ));
+    // Local directories aren't feeds.
+    assert!(!opml.contains("audiobook"));
+    assert_eq!(opml.matches("<outline").count(), 2);
+}
@@ -0,0 +1,78 @@
+//! Post-processing helpers which don't need an API.
+
+use lqcli::chapters::Chapter;
+use lqcli::openai::{ApiError, Segment};
+use lqcli::postprocess::{
+    join_batch, postprocess_chapters, postprocess_timestamped, segments_text, split_batch, timestamp_anchor,
+    timestamp_blocks, RuleBased,
+};
+
+fn segment(start: f32, text: &str) -> Segment {
+    Segment { start, text: text.to_string() }
+}
+
+#[test]
+fn segments_are_grouped_into_timestamped_blocks() {
+    let segments = vec![
+        segment(0.0, " Hallo und willkommen."),
+        segment(30.0, " Heute geht es um Käse."),
+        segment(61.5, " Erst aber die Nachrichten."),
+        segment(90.0, "   "),
+    ];
+    assert_eq!(
+        timestamp_blocks(&segments),
+        vec![
+            (0.0, "Hallo und willkommen. Heute geht es um Käse.".to_string()),
+            (61.5, "Erst aber die Nachrichten.".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn anchors() {
+    assert_eq!(timestamp_anchor(0.0), "<!-- 00:00 -->");
+    assert_eq!(timestamp_anchor(754.9), "<!-- 12:34 -->");
+    assert_eq!(timestamp_anchor(3723.0), "<!-- 1:02:03 -->");
+}
+
+#[test]
+fn batches_split_back_into_episodes() {
+    let episodes = vec![
+        ("Folge 1".to_string(), "hallo erstmal".to_string()),
+        ("Folge 2".to_string(), "und weiter geht's".to_string()),
+    ];
+    let batch = join_batch(&episodes);
+    assert_eq!(
+        split_batch(&batch, 2),
+        Some(vec!["hallo erstmal".to_string(), "und weiter geht's".to_string()])
+    );
+
+    let reply = batch.replace("hallo erstmal", "Hallo erstmal.\n\nAnna: Ja.");
+    assert_eq!(split_batch(&reply, 2).unwrap()[0], "Hallo erstmal.\n\nAnna: Ja.");
+
+    // A reply which lost or reordered an episode can't be split reliably.
+    assert_eq!(split_batch(&batch, 3), None);
+    assert_eq!(split_batch(&batch.replace("EPISODE 2", "EPISODE 3"), 2), None);
+    assert_eq!(split_batch(&format!("Sure!\n\n{}", batch), 2), None);
+}
+
+#[tokio::test]
+async fn silent_segments_make_no_transcript() {
+    let chapters = vec![Chapter { start_time: 0.0, title: "Intro".to_string() }];
+    for segments in [vec![], vec![segment(0.0, "  "), segment(4.0, "\n")]] {
+        assert!(matches!(segments_text(&segments), Err(ApiError::Empty)));
+        assert!(matches!(postprocess_timestamped(&RuleBased, None, &segments).await, Err(ApiError::Empty)));
+        assert!(matches!(postprocess_chapters(&RuleBased, None, &segments, &chapters).await, Err(ApiError::Empty)));
+    }
+    // Chapters without any text under them just go.
+    let chapters = vec![
+        Chapter { start_time: 0.0, title: "Intro".to_string() },
+        Chapter { start_time: 10.0, title: "Käse".to_string() },
+    ];
+    let segments = vec![segment(0.0, " "), segment(12.0, "heute geht es um käse")];
+    assert_eq!(segments_text(&segments).unwrap(), "heute geht es um käse");
+    assert_eq!(
+        postprocess_chapters(&RuleBased, None, &segments, &chapters).await.unwrap(),
+        "## Käse\n\nHeute geht es um käse."
+    );
+}
@@ -0,0 +1,37 @@
+//! Per-source logs, and rotating them.
+
+use lqcli::runlog;
+
+#[test]
+fn logs_are_rotated_once_per_run_keeping_the_latest() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = runlog::path_for(dir.path(), "Mein/Podcast");
+    assert_eq!(path, dir.path().join("Mein_Podcast.log"));
+    let rotated = |n: usize| dir.path().join(format!("Mein_Podcast.log.{}", n));
+
+    std::fs::write(&path, "first run\n").unwrap();
+    runlog::rotate(&path, 100, 2).unwrap();
+    assert!(path.exists(), "logs within the limit are left alone");
+
+    for run in ["second run", "third run", "fourth run"] {
+        std::fs::write(&path, format!("{}\n", run).repeat(20)).unwrap();
+        runlog::rotate(&path, 100, 2).unwrap();
+        assert!(!path.exists());
+    }
+    assert!(std::fs::read_to_string(rotated(1)).unwrap().starts_with("fourth run"));
+    assert!(std::fs::read_to_string(rotated(2)).unwrap().starts_with("third run"));
+    assert!(!rotated(3).exists());
+
+    // Lines go to the source's log, rotating it first if it's too big.
+    runlog::set_dir(dir.path(), 10, 2);
+    std::fs::write(&path, "too big already\n").unwrap();
+    runlog::log("Mein/Podcast", "Fetched 3 item(s)\nand another line");
+    runlog::log("Mein/Podcast", "Imported 1 of 1 new lesson(s)");
+    let log = std::fs::read_to_string(&path).unwrap();
+    let lines: Vec<&str> = log.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].ends_with(" Fetched 3 item(s)"));
+    assert!(lines[1].ends_with(" and another line"));
+    assert!(lines[2].ends_with(" Imported 1 of 1 new lesson(s)"));
+    assert_eq!(std::fs::read_to_string(rotated(1)).unwrap(), "too big already\n");
+}
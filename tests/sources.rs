@@ -0,0 +1,274 @@
+//! Per-source settings, resolved against the rest of the configuration.
+
+use lqcli::config::{LqcliConfig, NamePattern};
+
+const CONFIG: &str = r#"
+[lingq]
+api_key = "lingq"
+
+[openai]
+api_key = "openai"
+
+[[sources]]
+name = "news"
+url = "https://example.com/news.xml"
+course_id = 1
+tags = []
+
+[[sources]]
+name = "dialect"
+url = "https://example.com/dialect.xml"
+course_id = 2
+tags = []
+whisper_model = "whisper-dialect"
+"#;
+
+#[test]
+fn whisper_model_override_is_per_source() {
+    let config: LqcliConfig = toml::from_str(CONFIG).unwrap();
+    let default = config.openai.whisper_model.as_str();
+    assert_eq!(default, "whisper-1");
+    assert_eq!(config.sources[0].whisper_model(default), "whisper-1");
+    assert_eq!(config.sources[1].whisper_model(default), "whisper-dialect");
+}
+
+#[test]
+fn whisper_temperature_falls_back_to_global() {
+    let config: LqcliConfig = toml::from_str(&format!("{}whisper_temperature = 0.4\n", CONFIG)).unwrap();
+    assert_eq!(config.openai.whisper_temperature, None);
+    assert_eq!(config.sources[0].whisper_temperature(Some(0.0)), Some(0.0));
+    assert_eq!(config.sources[1].whisper_temperature(Some(0.0)), Some(0.4));
+    assert_eq!(config.sources[0].whisper_temperature(None), None);
+}
+
+#[test]
+fn title_filters() {
+    let config: LqcliConfig = toml::from_str(&format!(
+        "{}title_include = \"^\\\\[Full\"\ntitle_exclude = \"(?i)trailer\"\n",
+        CONFIG
+    ))
+    .unwrap();
+    let source = &config.sources[1];
+    assert!(source.title_allowed("[Full Episode] Folge 1"));
+    assert!(!source.title_allowed("[Clip] Folge 1"));
+    assert!(!source.title_allowed("[Full Episode] Trailer"));
+    assert!(config.sources[0].title_allowed("[Clip] Folge 1"));
+
+    let invalid = format!("{}title_include = \"[Full\"\n", CONFIG);
+    assert!(toml::from_str::<LqcliConfig>(&invalid).is_err());
+}
+
+#[test]
+fn lingq_fields_leave_out_invalid_names() {
+    let config: LqcliConfig = toml::from_str(&format!(
+        "{}lingq_fields = {{ status = \"private\", title = \"Overridden\", \"bad name\" = \"x\" }}\n",
+        CONFIG
+    ))
+    .unwrap();
+    let fields = config.sources[1].lingq_fields();
+    assert_eq!(fields.len(), 1);
+    assert_eq!(fields["status"], "private");
+    assert_eq!(config.validate().iter().filter(|warning| warning.contains("lingq_fields")).count(), 2);
+    assert!(config.sources[0].lingq_fields().is_empty());
+}
+
+#[test]
+fn sources_filtered_by_name() {
+    let config: LqcliConfig = toml::from_str(CONFIG).unwrap();
+    let names = |name: Option<&NamePattern>| -> Vec<String> {
+        config.filtered_sources(&[], name).iter().map(|source| source.name.clone()).collect()
+    };
+    assert_eq!(names(None), vec!["news", "dialect"]);
+    assert_eq!(names(Some(&NamePattern::Substring("DIAL".to_string()))), vec!["dialect"]);
+    let regex = regex::Regex::new("^n.*s$").unwrap();
+    assert_eq!(names(Some(&NamePattern::Regex(regex))), vec!["news"]);
+    // Tags have to match as well.
+    let tags = vec!["daily".to_string()];
+    assert!(config.filtered_sources(&tags, Some(&NamePattern::Substring("news".to_string()))).is_empty());
+}
+
+#[test]
+fn moved_source_urls_are_replaced_in_place() {
+    let file = tempfile::NamedTempFile::new().unwrap();
+    std::fs::write(file.path(), CONFIG).unwrap();
+    let path = file.path().to_str().unwrap();
+    let changed = lqcli::config::replace_source_url(path, "https://example.com/news.xml", "https://news.example.com/feed.xml");
+    assert!(changed.unwrap());
+    let toml = std::fs::read_to_string(path).unwrap();
+    assert!(toml.contains("url = \"https://news.example.com/feed.xml\""));
+    assert!(toml.contains("url = \"https://example.com/dialect.xml\""));
+    assert!(!lqcli::config::replace_source_url(path, "https://example.com/gone.xml", "https://example.com/new.xml").unwrap());
+    assert!(lqcli::config::replace_source_url("https://example.com/lqcli.toml", "a", "b").is_err());
+}
+
+#[test]
+fn moved_source_urls_are_only_replaced_on_url_lines() {
+    let file = tempfile::NamedTempFile::new().unwrap();
+    let toml = CONFIG.replace(
+        "name = \"news\"\n",
+        "name = \"news\"\n# Was \"https://example.com/dialect.xml\" until 2024\n",
+    );
+    std::fs::write(file.path(), toml).unwrap();
+    let path = file.path().to_str().unwrap();
+    let changed = lqcli::config::replace_source_url(path, "https://example.com/dialect.xml", "https://example.com/mundart.xml");
+    assert!(changed.unwrap());
+    let toml = std::fs::read_to_string(path).unwrap();
+    assert!(toml.contains("# Was \"https://example.com/dialect.xml\" until 2024"));
+    assert!(toml.contains("url = \"https://example.com/mundart.xml\""));
+
+    // Two sources with the same url are too unusual to guess at.
+    std::fs::write(path, format!("{}\n[[sources]]\nname = \"again\"\nurl = \"https://example.com/news.xml\"\n", toml)).unwrap();
+    assert!(lqcli::config::replace_source_url(path, "https://example.com/news.xml", "https://example.com/b.xml").is_err());
+}
+
+#[test]
+fn levels_outside_lingqs_range_are_ignored() {
+    let config: LqcliConfig = toml::from_str(&format!("{}level = 7\n", CONFIG)).unwrap();
+    assert_eq!(config.sources[1].level(), None);
+    assert!(config.validate().iter().any(|warning| warning.contains("level 7")));
+    let config: LqcliConfig = toml::from_str(&format!("{}level = 3\n", CONFIG)).unwrap();
+    assert_eq!(config.sources[1].level(), Some(3));
+}
+
+#[test]
+fn languages_must_be_lingq_codes() {
+    assert!(lqcli::lingq::check_language("es").is_ok());
+    assert!(lqcli::lingq::check_language("de-DE").is_err());
+    let config: LqcliConfig = toml::from_str(&format!("{}language = \"German\"\n", CONFIG)).unwrap();
+    assert!(config.validate().iter().any(|warning| warning.contains("\"German\"")));
+}
+
+#[test]
+fn sources_can_sync_more_items() {
+    let config: LqcliConfig = toml::from_str(&format!("{}max_items = 20\n", CONFIG)).unwrap();
+    assert_eq!(config.sources[0].max_items(5), 5);
+    assert_eq!(config.sources[1].max_items(5), 20);
+    let config: LqcliConfig = toml::from_str(&format!("{}max_items = 0\n", CONFIG)).unwrap();
+    assert!(config.validate().iter().any(|warning| warning.contains("max_items = 0")));
+}
+
+#[test]
+fn imports_still_processing_are_pending() {
+    let created = |json: &str| serde_json::from_str::<lqcli::lingq::CreatedLesson>(json).unwrap();
+    assert!(created(r#"{"id": 1, "status": "Processing"}"#).is_pending());
+    assert!(!created(r#"{"id": 1, "status": "done"}"#).is_pending());
+    assert!(!created(r#"{"id": 1, "url": "https://www.lingq.com/lesson/1"}"#).is_pending());
+}
+
+#[test]
+fn sources_are_renamed_in_place() {
+    let file = tempfile::NamedTempFile::new().unwrap();
+    std::fs::write(file.path(), CONFIG).unwrap();
+    let path = file.path().to_str().unwrap();
+    lqcli::config::rename_source(path, "news", "Nachrichten \"heute\"").unwrap();
+    let config: LqcliConfig = toml::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+    assert_eq!(config.sources[0].name, "Nachrichten \"heute\"");
+    assert_eq!(config.sources[1].name, "dialect");
+    assert!(lqcli::config::rename_source(path, "news", "again").is_err());
+}
+
+#[test]
+fn starter_config_is_written_once() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("lqcli.toml");
+    let path = path.to_str().unwrap();
+    lqcli::config::write_starter_config(path, false).unwrap();
+    let config: LqcliConfig = toml::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+    assert!(config.sources.is_empty());
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        assert_eq!(std::fs::metadata(path).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+
+    std::fs::write(path, "changed").unwrap();
+    assert!(lqcli::config::write_starter_config(path, false).is_err());
+    assert_eq!(std::fs::read_to_string(path).unwrap(), "changed");
+    lqcli::config::write_starter_config(path, true).unwrap();
+    assert_eq!(std::fs::read_to_string(path).unwrap(), lqcli::config::starter_config());
+}
+
+#[test]
+fn sources_are_added_at_the_end() {
+    let file = tempfile::NamedTempFile::new().unwrap();
+    std::fs::write(file.path(), format!("# My sources\n{}", CONFIG)).unwrap();
+    let path = file.path().to_str().unwrap();
+    let mut new_source = lqcli::config::NewSource {
+        name: "radio".to_string(),
+        url: "https://example.com/radio.xml".to_string(),
+        course_id: 3,
+        language: Some("de".to_string()),
+        tags: vec!["daily".to_string()],
+        content_type: "syndication".to_string(),
+    };
+    lqcli::config::add_source(path, &new_source).unwrap();
+    let toml = std::fs::read_to_string(path).unwrap();
+    assert!(toml.starts_with("# My sources\n"));
+    let config: LqcliConfig = toml::from_str(&toml).unwrap();
+    assert_eq!(config.sources.len(), 3);
+    assert_eq!(config.sources[2].name, "radio");
+    assert_eq!(config.sources[2].course_id, 3);
+
+    // Names have to be unique, and the source has to make sense.
+    assert!(lqcli::config::add_source(path, &new_source).is_err());
+    new_source.name = "podcast".to_string();
+    new_source.content_type = "carrier-pigeon".to_string();
+    assert!(lqcli::config::add_source(path, &new_source).is_err());
+    assert_eq!(std::fs::read_to_string(path).unwrap(), toml);
+}
+
+#[test]
+fn transcript_via_must_be_a_known_service() {
+    use lqcli::transcribe::TranscriptVia;
+    let config: LqcliConfig = toml::from_str(&format!("{}transcript_via = \"lingq\"\n", CONFIG)).unwrap();
+    assert_eq!(config.sources[0].transcript_via, TranscriptVia::Openai);
+    assert_eq!(config.sources[1].transcript_via, TranscriptVia::Lingq);
+    let error = toml::from_str::<LqcliConfig>(&format!("{}transcript_via = \"lingo\"\n", CONFIG)).err().unwrap();
+    assert!(error.to_string().contains("unknown variant `lingo`"));
+}
+
+#[test]
+fn local_transcription_needs_a_model() {
+    let local = format!("{}transcript_via = \"local\"\n", CONFIG);
+    let config: LqcliConfig = toml::from_str(&local).unwrap();
+    assert!(config.validate().iter().any(|warning| warning.contains("[local_whisper]")));
+    let config: LqcliConfig = toml::from_str(&format!("{}\n[local_whisper]\nmodel = \"ggml-base.bin\"\n", local)).unwrap();
+    assert!(!config.validate().iter().any(|warning| warning.contains("[local_whisper]")));
+    assert_eq!(config.local_whisper.unwrap().command, "whisper-cli");
+}
+
+#[tokio::test]
+async fn bad_sources_are_named_when_reading_the_configuration() {
+    let file = tempfile::NamedTempFile::new().unwrap();
+    std::fs::write(file.path(), format!("{}transcript_via = \"lingqq\"\n", CONFIG)).unwrap();
+    let error = LqcliConfig::read(file.path().to_str().unwrap(), None).await.err().unwrap();
+    assert!(error.to_string().starts_with("source dialect: unknown variant `lingqq`"), "{}", error);
+}
+
+#[test]
+fn small_courses_come_whole() {
+    use lqcli::lingq::LessonPage;
+    // A small course comes whole, as the course itself, rather than a page
+    // at a time (see tests/lingq.rs).
+    let course: LessonPage = serde_json::from_str(
+        r#"{"pk": 123, "title": "Podcast", "url": "https://www.lingq.com/course/123", "lessons": [{"title": "Folge 1", "url": "https://www.lingq.com/lesson/1"}]}"#,
+    )
+    .unwrap();
+    assert_eq!(course.lessons.len(), 1);
+    assert_eq!(course.next, None);
+}
+
+#[test]
+fn imported_lessons_are_found_despite_html_encoded_titles() {
+    use lqcli::lingq::{CreatedLesson, LessonPage};
+    let page: LessonPage = serde_json::from_str(include_str!("fixtures/lingq_lessons_2.json")).unwrap();
+    let listed = &page.lessons[0];
+    // By ID, whatever the title.
+    let created = CreatedLesson { id: Some(903), ..Default::default() };
+    assert!(listed.is(&created, "Folge 3: Tee & Kekse"));
+    assert!(!listed.is(&CreatedLesson { id: Some(904), ..Default::default() }, "Folge 3: Tee & Kekse"));
+    // Without an ID, by the decoded title.
+    let created = CreatedLesson::default();
+    assert!(listed.is(&created, "Folge 3: Tee & Kekse"));
+    assert!(!listed.is(&created, "Folge 3: Tee"));
+}
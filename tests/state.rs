@@ -0,0 +1,113 @@
+//! State kept between runs: scheduling syncs with poll_interval and items
+//! waiting to be imported.
+
+use lqcli::source::SourceItem;
+use lqcli::state::{Discrepancy, FailedItem, State};
+
+#[test]
+fn recently_synced_sources_are_not_due() {
+    let mut state = State::default();
+    state.record_sync("news", true);
+    assert!(!state.is_due("news", Some(1)));
+    assert!(state.is_due("news", None));
+    assert!(state.is_due("other", Some(1)));
+}
+
+#[test]
+fn quiet_sources_back_off() {
+    let mut state = State::default();
+    state.record_sync("news", false);
+    state.record_sync("news", false);
+    assert_eq!(state.sources["news"].quiet_syncs, 2);
+    state.record_sync("news", true);
+    assert_eq!(state.sources["news"].quiet_syncs, 0);
+}
+
+#[test]
+fn prefetched_items_are_forgotten_once_imported() {
+    let mut state = State::default();
+    state.record_prefetched("news", "https://example.com/1.mp3", "abc");
+    assert_eq!(state.prefetched_key("news", "https://example.com/1.mp3"), Some("abc"));
+    assert_eq!(state.prefetched_key("news", "https://example.com/2.mp3"), None);
+    state.clear_prefetched("news", "https://example.com/1.mp3");
+    assert_eq!(state.prefetched_key("news", "https://example.com/1.mp3"), None);
+}
+
+#[test]
+fn renamed_sources_keep_their_state() {
+    let mut state = State::default();
+    state.record_sync("news", false);
+    state.rename_source("news", "nachrichten");
+    assert!(!state.sources.contains_key("news"));
+    assert_eq!(state.sources["nachrichten"].quiet_syncs, 1);
+}
+
+#[test]
+fn reconciling_finds_items_which_are_in_lingq_after_all() {
+    let mut state = State::default();
+    let failure = |title: &str, url: &str| FailedItem {
+        title: title.into(),
+        guid: None,
+        url: url.into(),
+        error: "timed out".into(),
+        language: None,
+    };
+    state.record_failure("news", failure("Tom & Jerry", "https://example.com/1.mp3"));
+    state.record_failure("news", failure("Still missing", "https://example.com/2.mp3"));
+    state.record_prefetched("news", "https://example.com/3.mp3", "abc");
+    state.record_prefetched("news", "https://example.com/4.mp3", "def");
+    state.record_sync("old", true);
+
+    let lesson_titles = vec!["Tom &amp; Jerry".to_string(), "Episode 3".to_string()];
+    let items = [
+        SourceItem::from_url_and_title("https://example.com/3.mp3", "Episode 3"),
+        SourceItem::from_url_and_title("https://example.com/4.mp3", "Episode 4"),
+    ];
+    let discrepancies = state.reconcile("news", &lesson_titles, &items);
+    assert_eq!(
+        discrepancies,
+        vec![
+            Discrepancy::FailedButImported { title: "Tom & Jerry".into(), url: "https://example.com/1.mp3".into() },
+            Discrepancy::PrefetchedButImported { title: "Episode 3".into(), url: "https://example.com/3.mp3".into() },
+            Discrepancy::GuidNotRecorded { title: "Episode 3".into(), guid: "https://example.com/3.mp3".into() },
+        ]
+    );
+    assert_eq!(state.unknown_sources(&["news"]), vec!["old".to_string()]);
+
+    for discrepancy in &discrepancies {
+        state.repair("news", discrepancy);
+    }
+    state.repair("old", &Discrepancy::UnknownSource);
+    assert!(state.reconcile("news", &lesson_titles, &items).is_empty());
+    assert!(state.is_imported("news", "https://example.com/3.mp3"));
+    assert_eq!(state.sources["news"].failed_items.len(), 1);
+    assert_eq!(state.prefetched_key("news", "https://example.com/4.mp3"), Some("def"));
+    assert!(state.unknown_sources(&["news"]).is_empty());
+}
+
+#[test]
+fn imported_guids_are_kept_per_source() {
+    let mut state = State::default();
+    state.record_imported("news", ["tag:example.com,2024:1".to_string()]);
+    assert!(state.is_imported("news", "tag:example.com,2024:1"));
+    assert!(!state.is_imported("news", "tag:example.com,2024:2"));
+    assert!(!state.is_imported("other", "tag:example.com,2024:1"));
+}
+
+#[test]
+fn failures_with_the_same_title_are_found_in_every_source() {
+    let mut state = State::default();
+    let failure = |url: &str| FailedItem {
+        title: "Folge 1".into(),
+        guid: None,
+        url: url.into(),
+        error: "timed out".into(),
+        language: None,
+    };
+    for source in ["zeitfragen", "anfänger", "nachrichten"] {
+        state.record_failure(source, failure(&format!("https://{}.example.com/1.mp3", source)));
+    }
+    let found: Vec<&str> = state.find_failures("Folge 1").into_iter().map(|(source, _)| source).collect();
+    assert_eq!(found, ["anfänger", "nachrichten", "zeitfragen"]);
+    assert!(state.find_failures("Folge 2").is_empty());
+}
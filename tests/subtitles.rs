@@ -0,0 +1,46 @@
+//! Reading WebVTT and SubRip subtitles.
+
+use lqcli::subtitles::parse;
+
+fn texts(content: &str) -> Vec<(f32, String)> {
+    parse(content).unwrap().into_iter().map(|segment| (segment.start, segment.text)).collect()
+}
+
+#[test]
+fn rolling_captions_are_not_repeated() {
+    let vtt = "WEBVTT
+Kind: captions
+Language: de
+
+NOTE made by a captioning service
+
+00:00:00.000 --> 00:00:02.500 align:start position:0%
+Hallo <c.yellow>und</c> willkommen
+
+00:00:02.500 --> 00:00:02.510
+Hallo und willkommen
+
+00:00:02.510 --> 00:00:05.000
+Hallo und willkommen
+<00:00:03.000><c>zu</c><00:00:03.500><c> Folge 3.</c>
+
+1:05.000 --> 1:07.000
+<v Anna>Tom &amp; Jerry sind da.
+";
+    assert_eq!(
+        texts(vtt),
+        vec![
+            (0.0, "Hallo und willkommen".to_string()),
+            (2.51, "zu Folge 3.".to_string()),
+            (65.0, "Tom & Jerry sind da.".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn subrip() {
+    let srt = "1\r\n00:00:01,000 --> 00:00:02,000\r\n<i>Guten Morgen.</i>\r\n\r\n2\r\n00:00:10,000 --> 00:00:12,000\r\nGuten Morgen.\r\n";
+    // Far enough apart that it was really said twice.
+    assert_eq!(texts(srt), vec![(1.0, "Guten Morgen.".to_string()), (10.0, "Guten Morgen.".to_string())]);
+    assert!(parse("this is not a subtitle file").is_err());
+}
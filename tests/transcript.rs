@@ -0,0 +1,21 @@
+//! Finding the transcripts published on episode pages.
+
+use lqcli::transcript;
+
+#[test]
+fn the_transcript_is_the_element_which_says_so() {
+    let page = r#"<html><body>
+        <nav><div>Episoden</div></nav>
+        <div class="episode-transcript members-only">
+          <p><b>Manuel:</b> Hallo &amp; herzlich willkommen!</p>
+          <div class="aside"><p>Cari: Hallo!</p></div>
+        </div>
+        <footer><div>Impressum</div></footer>
+    </body></html>"#;
+    assert_eq!(
+        transcript::extract(page).as_deref(),
+        Some("Manuel: Hallo & herzlich willkommen!\nCari: Hallo!")
+    );
+    assert_eq!(transcript::extract("<div class=\"paywall\">Nur für Mitglieder</div>"), None);
+    assert_eq!(transcript::extract("<section id=\"transcript\"> </section>"), None);
+}